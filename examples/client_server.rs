@@ -15,28 +15,20 @@ use hpke::{
     aead::{AeadTag, ChaCha20Poly1305},
     kdf::HkdfSha384,
     kem::X25519HkdfSha256,
-    EncappedKey, Kem as KemTrait, KeyExchange, Marshallable, OpModeR, OpModeS, Unmarshallable,
+    Kem as KemTrait, Marshallable, OpModeR, OpModeS, Unmarshallable,
 };
 
-use rand;
-
-const INFO_STR: &'static [u8] = b"example session";
+const INFO_STR: &[u8] = b"example session";
 
 // These are the only algorithms we're gonna use for this example
 type Kem = X25519HkdfSha256;
 type Aead = ChaCha20Poly1305;
 type Kdf = HkdfSha384;
 
-// The KEX is dependent on the choice of KEM
-type Kex = <Kem as KemTrait>::Kex;
-
 // Initialize the server with a fresh keypair
-fn server_init() -> (
-    <Kex as KeyExchange>::PrivateKey,
-    <Kex as KeyExchange>::PublicKey,
-) {
+fn server_init() -> (<Kem as KemTrait>::PrivateKey, <Kem as KemTrait>::PublicKey) {
     let mut csprng = rand::thread_rng();
-    Kex::gen_keypair(&mut csprng)
+    Kem::gen_keypair(&mut csprng)
 }
 
 // Given a message and associated data, returns an encapsulated key, ciphertext, and tag. The
@@ -44,12 +36,12 @@ fn server_init() -> (
 fn client_encrypt_msg(
     msg: &[u8],
     associated_data: &[u8],
-    server_pk: &<Kex as KeyExchange>::PublicKey,
-) -> (EncappedKey<Kex>, Vec<u8>, AeadTag<Aead>) {
+    server_pk: &<Kem as KemTrait>::PublicKey,
+) -> (<Kem as KemTrait>::EncappedKey, Vec<u8>, AeadTag<Aead>) {
     let mut csprng = rand::thread_rng();
 
-    // Encapsulate a key and use the resulting shared secret to encrypt a message. The AEAD context
-    // is what you use to encrypt.
+    // Encapsulate a key and use the resulting shared secret to encrypt a message. The sender's
+    // context (an `AeadCtxS`) can only encrypt, since decrypting is the server's job.
     let (encapped_key, mut aead_ctx) =
         hpke::setup_sender::<Aead, Kdf, Kem, _>(&OpModeS::Base, server_pk, INFO_STR, &mut csprng)
             .expect("invalid server pubkey!");
@@ -76,13 +68,14 @@ fn server_decrypt_msg(
 ) -> Vec<u8> {
     // We have to unmarshal the secret key, AEAD tag, and encapsulated pubkey. These fail if the
     // bytestrings are the wrong length.
-    let server_sk = <Kex as KeyExchange>::PrivateKey::unmarshal(server_sk_bytes)
+    let server_sk = <Kem as KemTrait>::PrivateKey::unmarshal(server_sk_bytes)
         .expect("could not deserialize server privkey!");
     let tag = AeadTag::<Aead>::unmarshal(tag_bytes).expect("could not deserialize AEAD tag!");
-    let encapped_key = EncappedKey::<Kex>::unmarshal(encapped_key_bytes)
+    let encapped_key = <Kem as KemTrait>::EncappedKey::unmarshal(encapped_key_bytes)
         .expect("could not deserialize the encapsulated pubkey!");
 
-    // Decapsulate and derive the shared secret. This creates a shared AEAD context.
+    // Decapsulate and derive the shared secret. This creates the receiver's context (an
+    // `AeadCtxR`), which can only decrypt.
     let mut aead_ctx =
         hpke::setup_receiver::<Aead, Kdf, Kem>(&OpModeR::Base, &server_sk, &encapped_key, INFO_STR)
             .expect("failed to set up receiver!");
@@ -93,10 +86,8 @@ fn server_decrypt_msg(
         .open(&mut ciphertext_copy, associated_data, &tag)
         .expect("invalid ciphertext!");
 
-    // Rename for clarity
-    let plaintext = ciphertext_copy;
-
-    plaintext
+    // The ciphertext was decrypted in place, so it's now the plaintext
+    ciphertext_copy
 }
 
 fn main() {
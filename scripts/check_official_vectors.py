@@ -0,0 +1,39 @@
+"""Checks hpke_ref.py against the official RFC 9180 vectors for the NIST curves.
+
+Usage: python3 scripts/check_official_vectors.py
+"""
+import json, os
+from hpke_ref import CURVES, derive_keypair, encap, key_schedule, seal, export
+
+ROOT = os.path.join(os.path.dirname(os.path.abspath(__file__)), "..")
+
+tvs = json.load(open(os.path.join(ROOT, "test-vectors-5f503c5.json")))
+n = 0
+for tv in tvs:
+    if tv["kem_id"] not in CURVES:
+        continue
+    x = lambda k: bytes.fromhex(tv[k]) if k in tv else None
+    kem, kdf, aead, mode = tv["kem_id"], tv["kdf_id"], tv["aead_id"], tv["mode"]
+    skR, pkR = derive_keypair(kem, x("ikmR"))
+    assert skR == x("skRm") and pkR == x("pkRm")
+    skE, pkE = derive_keypair(kem, x("ikmE"))
+    assert skE == x("skEm") and pkE == x("pkEm")
+    skS = None
+    if "ikmS" in tv:
+        skS, pkS = derive_keypair(kem, x("ikmS"))
+        assert skS == x("skSm") and pkS == x("pkSm")
+    ss, enc = encap(kem, pkR, skE, skS)
+    assert ss == x("shared_secret") and enc == x("enc")
+    ks = key_schedule(kem, kdf, aead, mode, ss, x("info"), x("psk") or b"", x("psk_id") or b"")
+    assert ks["ctx"] == x("key_schedule_context") and ks["secret"] == x("secret")
+    assert ks["key"] == x("key") and ks["base_nonce"] == x("base_nonce")
+    assert ks["exporter"] == x("exporter_secret")
+    for i, e in enumerate(tv["encryptions"]):
+        aad, pt = bytes.fromhex(e["aad"]), bytes.fromhex(e["pt"])
+        nonce, ct = seal(aead, ks["key"], ks["base_nonce"], i, aad, pt)
+        assert nonce.hex() == e["nonce"] and ct.hex() == e["ct"]
+    for e in tv["exports"]:
+        exported = export(ks, bytes.fromhex(e["exporter_context"]), e["L"])
+        assert exported.hex() == e["exported_value"]
+    n += 1
+print("validated", n, "official vectors")
@@ -0,0 +1,102 @@
+"""Generates test-vectors-ml-kem.json, the ML-KEM known-answer vectors.
+
+  * keygen and encap come straight from the FIPS 203 ACVP vectors, in the
+    ML-KEM-keyGen-FIPS203 and ML-KEM-encapDecap-FIPS203 directories of
+    https://github.com/usnistgov/ACVP-Server/tree/master/gen-val/json-files
+  * decap is pyca/cryptography decapsulating a ciphertext it encapsulated, and the same ciphertext
+    with one bit flipped, which it implicitly rejects
+  * derive_keypair is the DeriveKeyPair of draft-ietf-hpke-pq, computed here with hashlib
+  * hpke is a Base mode ciphertext from pyca/cryptography's HPKE, sealed to a key from
+    derive_keypair
+
+pyca/cryptography is first checked against the ACVP key generation vectors. It can't encapsulate
+deterministically, so the decap and hpke vectors change on every run, and the script checks each
+one with pyca before writing it. Everything else is reproduced exactly.
+
+Usage: python3 scripts/gen_ml_kem_vectors.py ACVP_JSON_FILES_DIR test-vectors-ml-kem.json
+"""
+import hashlib, json, os, sys
+from cryptography.hazmat.primitives import hpke
+from cryptography.hazmat.primitives.asymmetric import mlkem
+
+# parameter set: (KEM ID, pyca key type, pyca HPKE KEM, KDF ID, pyca KDF, AEAD ID, pyca AEAD)
+PARAMS = {
+    "ML-KEM-768": (0x0041, mlkem.MLKEM768PrivateKey, hpke.KEM.MLKEM768,
+                   0x0001, hpke.KDF.HKDF_SHA256, 0x0001, hpke.AEAD.AES_128_GCM),
+    "ML-KEM-1024": (0x0042, mlkem.MLKEM1024PrivateKey, hpke.KEM.MLKEM1024,
+                    0x0002, hpke.KDF.HKDF_SHA384, 0x0002, hpke.AEAD.AES_256_GCM),
+}
+
+def i2osp(n, l):
+    return n.to_bytes(l, "big")
+
+# draft-ietf-hpke-pq:
+#   def DeriveKeyPair(ikm):
+#     seed = LabeledDerive(ikm, "DeriveKeyPair", "", 64)
+#     ...
+# where LabeledDerive(ikm, label, context, L) is
+#   SHAKE256(ikm || "HPKE-v1" || suite_id || I2OSP(len(label), 2) || label || I2OSP(L, 2)
+#            || context, L)
+def derive_seed(kem_id, ikm):
+    label = b"DeriveKeyPair"
+    suite_id = b"KEM" + i2osp(kem_id, 2)
+    labeled_ikm = ikm + b"HPKE-v1" + suite_id + i2osp(len(label), 2) + label + i2osp(64, 2)
+    return hashlib.shake_256(labeled_ikm).digest(64)
+
+def pk_bytes(sk):
+    return sk.public_key().public_bytes_raw()
+
+def acvp_groups(acvp_dir, name):
+    path = os.path.join(acvp_dir, name, "internalProjection.json")
+    return json.load(open(path))["testGroups"]
+
+acvp_dir, out_path = sys.argv[1], sys.argv[2]
+keygen_groups = acvp_groups(acvp_dir, "ML-KEM-keyGen-FIPS203")
+encdec_groups = acvp_groups(acvp_dir, "ML-KEM-encapDecap-FIPS203")
+
+out = dict(keygen=[], encap=[], decap=[], derive_keypair=[], hpke=[])
+for name, (kem_id, sk_cls, pyca_kem, kdf_id, pyca_kdf, aead_id, pyca_aead) in PARAMS.items():
+    suite = hpke.Suite(pyca_kem, pyca_kdf, pyca_aead)
+
+    # ACVP: KeyGen_internal(d, z) = ek. Check that pyca agrees, since it's used for the rest.
+    tc = next(g for g in keygen_groups if g["parameterSet"] == name)["tests"][0]
+    d, z, ek = bytes.fromhex(tc["d"]), bytes.fromhex(tc["z"]), bytes.fromhex(tc["ek"])
+    assert pk_bytes(sk_cls.from_seed_bytes(d + z)) == ek
+    out["keygen"].append(dict(kem_id=kem_id, d=d.hex(), z=z.hex(), ek=ek.hex()))
+
+    # ACVP: Encaps_internal(ek, m) = (K, c)
+    group = next(g for g in encdec_groups
+                 if g["parameterSet"] == name and g["function"] == "encapsulation")
+    tc = group["tests"][0]
+    out["encap"].append(dict(kem_id=kem_id, ek=tc["ek"], m=tc["m"], c=tc["c"], k=tc["k"]))
+
+    # Decaps(dk, c) with pyca, for a valid ciphertext and for one that's implicitly rejected
+    seed = hashlib.sha512(b"rust-hpke " + name.encode() + b" decap test vector").digest()
+    sk = sk_cls.from_seed_bytes(seed)
+    k, c = sk.public_key().encapsulate()
+    assert sk.decapsulate(c) == k
+    bad_c = bytes([c[0] ^ 1]) + c[1:]
+    bad_k = sk.decapsulate(bad_c)
+    assert bad_k != k
+    out["decap"].append(dict(kem_id=kem_id, seed=seed.hex(), c=c.hex(), k=k.hex()))
+    out["decap"].append(dict(kem_id=kem_id, seed=seed.hex(), c=bad_c.hex(), k=bad_k.hex()))
+
+    # DeriveKeyPair, for a short and a long ikm
+    long_ikm = hashlib.sha512(b"rust-hpke " + name.encode() + b" ikm").digest() * 2
+    for ikm in [b"\x42" * 32, long_ikm]:
+        skm = derive_seed(kem_id, ikm)
+        out["derive_keypair"].append(dict(kem_id=kem_id, ikm=ikm.hex(), skm=skm.hex()))
+
+    # A Base mode HPKE ciphertext from pyca, sealed to a derived key
+    seed = derive_seed(kem_id, b"\x42" * 32)
+    sk = sk_cls.from_seed_bytes(seed)
+    info, pt = b"Ode on a Grecian Urn", b"Beauty is truth, truth beauty"
+    enc_ct = suite.encrypt(pt, sk.public_key(), info=info)
+    assert suite.decrypt(enc_ct, sk, info=info) == pt
+    nenc = len(sk.public_key().encapsulate()[1])
+    out["hpke"].append(dict(kem_id=kem_id, kdf_id=kdf_id, aead_id=aead_id, skRm=seed.hex(),
+                            info=info.hex(), pt=pt.hex(), enc=enc_ct[:nenc].hex(),
+                            ct=enc_ct[nenc:].hex()))
+
+json.dump(out, open(out_path, "w"), separators=(",", ":"))
+print({k: len(v) for k, v in out.items()})
@@ -0,0 +1,92 @@
+"""Generates test-vectors-p384.json, the DHKEM(P-384, HKDF-SHA384) vectors, in the same JSON format
+as the RFC 9180 vectors. RFC 9180 has no P-384 vectors of its own.
+
+The vectors come from hpke_ref.py, which check_official_vectors.py checks against every official
+NIST curve vector. Before writing anything, this also checks that hpke_ref.py can open what
+pyca/cryptography's HPKE seals to P-384. The output is deterministic, so rerunning this reproduces
+the file byte for byte.
+
+Usage: python3 scripts/gen_p384_vectors.py test-vectors-p384.json
+"""
+import hashlib, json, sys
+from hpke_ref import AEADS, derive_keypair, dh, encap, export, extract_and_expand, i2osp
+from hpke_ref import key_schedule, seal
+from cryptography.hazmat.primitives import hpke
+from cryptography.hazmat.primitives.asymmetric import ec
+
+KEM = 0x0011
+KDFS = {0x0001: hpke.KDF.HKDF_SHA256, 0x0002: hpke.KDF.HKDF_SHA384, 0x0003: hpke.KDF.HKDF_SHA512}
+PYCA_AEADS = {
+    0x0001: hpke.AEAD.AES_128_GCM,
+    0x0002: hpke.AEAD.AES_256_GCM,
+    0x0003: hpke.AEAD.CHACHA20_POLY1305,
+}
+
+# Cross-check: pyca seals to a derived key, and the reference code decaps and opens it
+for kdf_id, pyca_kdf in KDFS.items():
+    for aead_id, pyca_aead in PYCA_AEADS.items():
+        skR, pkR = derive_keypair(KEM, b"\x42" * 48)
+        pub = ec.EllipticCurvePublicKey.from_encoded_point(ec.SECP384R1(), pkR)
+        info, pt = b"cross-check info", b"cross-check plaintext"
+        out = hpke.Suite(hpke.KEM.P384, pyca_kdf, pyca_aead).encrypt(pt, pub, info=info)
+        enc, ct = out[:97], out[97:]
+        ss = extract_and_expand(KEM, dh(KEM, skR, enc), enc + pkR)
+        ks = key_schedule(KEM, kdf_id, aead_id, 0, ss, info)
+        assert AEADS[aead_id][0](ks["key"]).decrypt(ks["base_nonce"], ct, b"") == pt
+print("pyca cross-check ok")
+
+def ikm(label):
+    return hashlib.sha384(b"rust-hpke P-384 test vector " + label).digest()
+
+info = bytes.fromhex("4f6465206f6e2061204772656369616e2055726e")
+psk = bytes.fromhex("0247fd33b913760fa1fa51e1892d9f307fbe65eb171e8132c2af18555a738b82")
+psk_id = bytes.fromhex("456e6e796e20447572696e206172616e204d6f726961")
+
+# Every mode with HKDF-SHA384 and AES-256-GCM, every other AEAD in Base mode, and every mode with
+# the other two KDFs
+suites = [(mode, 0x0002, 0x0002) for mode in range(4)]
+suites += [(0, 0x0002, aead_id) for aead_id in (0x0001, 0x0003, 0xFFFF)]
+suites += [(mode, kdf_id, 0x0001) for kdf_id in (0x0001, 0x0003) for mode in range(4)]
+
+vectors = []
+for mode, kdf_id, aead_id in suites:
+    # The labels of the original HKDF-SHA384 vectors don't include the KDF
+    tag = bytes([mode]) + i2osp(aead_id, 2)
+    if kdf_id != 0x0002:
+        tag += i2osp(kdf_id, 2)
+    ikmR, ikmE = ikm(b"R" + tag), ikm(b"E" + tag)
+    skR, pkR = derive_keypair(KEM, ikmR)
+    skE, pkE = derive_keypair(KEM, ikmE)
+    tv = dict(mode=mode, kem_id=KEM, kdf_id=kdf_id, aead_id=aead_id, info=info.hex(),
+              ikmR=ikmR.hex(), ikmE=ikmE.hex())
+    skS = None
+    if mode in (2, 3):
+        ikmS = ikm(b"S" + tag)
+        skS, pkS = derive_keypair(KEM, ikmS)
+        tv.update(ikmS=ikmS.hex(), skSm=skS.hex(), pkSm=pkS.hex())
+    tv.update(skRm=skR.hex(), skEm=skE.hex(), pkRm=pkR.hex(), pkEm=pkE.hex())
+    p, pid = (psk, psk_id) if mode in (1, 3) else (b"", b"")
+    if mode in (1, 3):
+        tv.update(psk=psk.hex(), psk_id=psk_id.hex())
+    ss, enc = encap(KEM, pkR, skE, skS)
+    ks = key_schedule(KEM, kdf_id, aead_id, mode, ss, info, p, pid)
+    tv.update(enc=enc.hex(), shared_secret=ss.hex(), key_schedule_context=ks["ctx"].hex(),
+              secret=ks["secret"].hex(), key=ks["key"].hex(), base_nonce=ks["base_nonce"].hex(),
+              exporter_secret=ks["exporter"].hex())
+    encs = []
+    if aead_id != 0xFFFF:
+        for i in range(3):
+            pt = b"Beauty is truth, truth beauty"
+            aad = ("Count-%d" % i).encode()
+            nonce, ct = seal(aead_id, ks["key"], ks["base_nonce"], i, aad, pt)
+            encs.append(dict(aad=aad.hex(), ct=ct.hex(), nonce=nonce.hex(), pt=pt.hex()))
+    tv["encryptions"] = encs
+    exps = []
+    for ctxv in [b"", b"\x00", b"TestContext"]:
+        exported = export(ks, ctxv, 32)
+        exps.append(dict(exporter_context=ctxv.hex(), L=32, exported_value=exported.hex()))
+    tv["exports"] = exps
+    vectors.append(tv)
+
+json.dump(vectors, open(sys.argv[1], "w"), separators=(",", ":"))
+print("wrote", len(vectors), "vectors")
@@ -0,0 +1,87 @@
+"""A minimal RFC 9180 implementation for the NIST curve DHKEMs, used to generate
+test-vectors-p384.json. It only takes curve arithmetic, HMAC, and the AEADs from its
+dependencies, so it shares no HPKE code with rust-hpke or pyca/cryptography."""
+import hashlib, hmac
+from cryptography.hazmat.primitives import serialization
+from cryptography.hazmat.primitives.asymmetric import ec
+from cryptography.hazmat.primitives.ciphers.aead import AESGCM, ChaCha20Poly1305
+
+CURVES = {
+    0x0010: (ec.SECP256R1(), 32, 65, 0xFF, 0x0001, 0xffffffff00000000ffffffffffffffffbce6faada7179e84f3b9cac2fc632551),
+    0x0011: (ec.SECP384R1(), 48, 97, 0xFF, 0x0002, int("ffffffffffffffffffffffffffffffffffffffffffffffffc7634d81f4372ddf581a0db248b0a77aecec196accc52973", 16)),
+    0x0012: (ec.SECP521R1(), 66, 133, 0x01, 0x0003, int("01fffffffffffffffffffffffffffffffffffffffffffffffffffffffffffffffffa51868783bf2f966b7fcc0148f709a5d03bb5c9b8899c47aebb6fb71e91386409", 16)),
+}
+HASHES = {0x0001: hashlib.sha256, 0x0002: hashlib.sha384, 0x0003: hashlib.sha512}
+AEADS = {0x0001: (AESGCM, 16, 12), 0x0002: (AESGCM, 32, 12), 0x0003: (ChaCha20Poly1305, 32, 12), 0xFFFF: (None, 0, 0)}
+
+def i2osp(n, l): return n.to_bytes(l, "big")
+
+def extract(h, salt, ikm):
+    if not salt: salt = b"\x00" * h().digest_size
+    return hmac.new(salt, ikm, h).digest()
+
+def expand(h, prk, info, L):
+    out, t, i = b"", b"", 1
+    while len(out) < L:
+        t = hmac.new(prk, t + info + bytes([i]), h).digest(); out += t; i += 1
+    return out[:L]
+
+def labeled_extract(h, suite_id, salt, label, ikm):
+    return extract(h, salt, b"HPKE-v1" + suite_id + label + ikm)
+
+def labeled_expand(h, suite_id, prk, label, info, L):
+    return expand(h, prk, i2osp(L, 2) + b"HPKE-v1" + suite_id + label + info, L)
+
+def sk_to_pk(kem_id, sk):
+    curve, nsk, npk, _, _, _ = CURVES[kem_id]
+    key = ec.derive_private_key(int.from_bytes(sk, "big"), curve)
+    return key.public_key().public_bytes(serialization.Encoding.X962, serialization.PublicFormat.UncompressedPoint)
+
+def derive_keypair(kem_id, ikm):
+    curve, nsk, npk, bitmask, kdf, order = CURVES[kem_id]
+    h = HASHES[kdf]; sid = b"KEM" + i2osp(kem_id, 2)
+    prk = labeled_extract(h, sid, b"", b"dkp_prk", ikm)
+    for counter in range(256):
+        b = bytearray(labeled_expand(h, sid, prk, b"candidate", bytes([counter]), nsk))
+        b[0] &= bitmask
+        sk = int.from_bytes(b, "big")
+        if 0 < sk < order:
+            skb = bytes(b); return skb, sk_to_pk(kem_id, skb)
+    raise Exception("DeriveKeyPairError")
+
+def dh(kem_id, sk, pk):
+    curve = CURVES[kem_id][0]
+    key = ec.derive_private_key(int.from_bytes(sk, "big"), curve)
+    return key.exchange(ec.ECDH(), ec.EllipticCurvePublicKey.from_encoded_point(curve, pk))
+
+def extract_and_expand(kem_id, dhv, kem_context):
+    h = HASHES[CURVES[kem_id][4]]; sid = b"KEM" + i2osp(kem_id, 2)
+    prk = labeled_extract(h, sid, b"", b"eae_prk", dhv)
+    return labeled_expand(h, sid, prk, b"shared_secret", kem_context, h().digest_size)
+
+def encap(kem_id, pkR, skE, skS=None):
+    pkE = sk_to_pk(kem_id, skE)
+    if skS is None:
+        return extract_and_expand(kem_id, dh(kem_id, skE, pkR), pkE + pkR), pkE
+    pkS = sk_to_pk(kem_id, skS)
+    return extract_and_expand(kem_id, dh(kem_id, skE, pkR) + dh(kem_id, skS, pkR), pkE + pkR + pkS), pkE
+
+def key_schedule(kem_id, kdf_id, aead_id, mode, shared_secret, info, psk=b"", psk_id=b""):
+    h = HASHES[kdf_id]; sid = b"HPKE" + i2osp(kem_id, 2) + i2osp(kdf_id, 2) + i2osp(aead_id, 2)
+    psk_id_hash = labeled_extract(h, sid, b"", b"psk_id_hash", psk_id)
+    info_hash = labeled_extract(h, sid, b"", b"info_hash", info)
+    ctx = bytes([mode]) + psk_id_hash + info_hash
+    secret = labeled_extract(h, sid, shared_secret, b"secret", psk)
+    _, nk, nn = AEADS[aead_id]
+    key = labeled_expand(h, sid, secret, b"key", ctx, nk) if nk else b""
+    base_nonce = labeled_expand(h, sid, secret, b"base_nonce", ctx, nn) if nn else b""
+    exporter = labeled_expand(h, sid, secret, b"exp", ctx, h().digest_size)
+    return dict(ctx=ctx, secret=secret, key=key, base_nonce=base_nonce, exporter=exporter, sid=sid, h=h)
+
+def seal(aead_id, key, base_nonce, seq, aad, pt):
+    cls, _, nn = AEADS[aead_id]
+    nonce = bytes(a ^ b for a, b in zip(base_nonce, i2osp(seq, nn)))
+    return nonce, cls(key).encrypt(nonce, pt, aad)
+
+def export(ks, exporter_context, L):
+    return labeled_expand(ks["h"], ks["sid"], ks["exporter"], b"sec", exporter_context, L)
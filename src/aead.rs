@@ -1,15 +1,24 @@
+#[cfg(feature = "alloc")]
+use crate::prelude::*;
 use crate::{
-    kdf::Kdf,
+    kdf::{Kdf, LabeledExpand},
     kex::{Marshallable, Unmarshallable},
     setup::ExporterSecret,
+    util::{FullSuiteId, HexFmt, Redacted},
     HpkeError,
 };
 
-use core::u8;
+use core::fmt;
 
 use aead::{Aead as BaseAead, NewAead as BaseNewAead};
-use digest::generic_array::GenericArray;
+#[cfg(feature = "persist")]
+use byteorder::{BigEndian, ByteOrder};
+use digest::generic_array::{typenum, GenericArray};
 use hkdf::Hkdf;
+use subtle::{Choice, ConstantTimeEq};
+#[cfg(feature = "persist")]
+use zeroize::Zeroizing;
+use zeroize::{Zeroize, ZeroizeOnDrop};
 
 /// Represents authenticated encryption functionality
 pub trait Aead {
@@ -20,36 +29,104 @@ pub trait Aead {
     const AEAD_ID: u16;
 }
 
+/// Represents an AEAD that can actually encrypt and decrypt. `AeadCtxS::seal` and `AeadCtxR::open`
+/// are only available for these. Every `Aead` but `ExportOnly` implements this.
+pub trait SealingAead: Aead {}
+
 /// The implementation of AES-GCM-128
 pub struct AesGcm128 {}
 
 impl Aead for AesGcm128 {
     type AeadImpl = aes_gcm::Aes128Gcm;
 
-    // draft02 §8.3: AES-GCM-128
+    // RFC 9180 §7.3: AES-128-GCM
     const AEAD_ID: u16 = 0x0001;
 }
 
+impl SealingAead for AesGcm128 {}
+
 /// The implementation of AES-GCM-128
 pub struct AesGcm256 {}
 
 impl Aead for AesGcm256 {
     type AeadImpl = aes_gcm::Aes256Gcm;
 
-    // draft02 §8.3: AES-GCM-256
+    // RFC 9180 §7.3: AES-256-GCM
     const AEAD_ID: u16 = 0x0002;
 }
 
+impl SealingAead for AesGcm256 {}
+
 /// The implementation of ChaCha20-Poly1305
 pub struct ChaCha20Poly1305 {}
 
 impl Aead for ChaCha20Poly1305 {
     type AeadImpl = chacha20poly1305::ChaCha20Poly1305;
 
-    // draft02 §8.3: ChaCha20Poly1305
+    // RFC 9180 §7.3: ChaCha20Poly1305
     const AEAD_ID: u16 = 0x0003;
 }
 
+impl SealingAead for ChaCha20Poly1305 {}
+
+/// A stand-in AEAD for contexts that are only used to export secrets. The key schedule doesn't
+/// derive a key or nonce for it, and its contexts have no `seal` or `open` method:
+///
+/// ```compile_fail
+/// # use hpke::{aead::ExportOnly, kdf::HkdfSha256, AeadCtxS};
+/// fn try_seal(ctx: &mut AeadCtxS<ExportOnly, HkdfSha256>) {
+///     let _ = ctx.seal(&mut [], b"");
+/// }
+/// ```
+pub struct ExportOnly {}
+
+impl Aead for ExportOnly {
+    type AeadImpl = EmptyAeadImpl;
+
+    // RFC 9180 §7.3: Export-only
+    const AEAD_ID: u16 = 0xFFFF;
+}
+
+/// The underlying AEAD implementation of `ExportOnly`. Its key, nonce, and tag are all empty, and
+/// it can't encrypt or decrypt anything.
+#[derive(Clone)]
+pub struct EmptyAeadImpl;
+
+impl BaseAead for EmptyAeadImpl {
+    type NonceSize = typenum::U0;
+    type TagSize = typenum::U0;
+    type CiphertextOverhead = typenum::U0;
+
+    // `ExportOnly` doesn't implement `SealingAead`, so this is never called
+    fn encrypt_in_place_detached(
+        &self,
+        _: &GenericArray<u8, Self::NonceSize>,
+        _: &[u8],
+        _: &mut [u8],
+    ) -> Result<GenericArray<u8, Self::TagSize>, aead::Error> {
+        Err(aead::Error)
+    }
+
+    // `ExportOnly` doesn't implement `SealingAead`, so this is never called
+    fn decrypt_in_place_detached(
+        &self,
+        _: &GenericArray<u8, Self::NonceSize>,
+        _: &[u8],
+        _: &mut [u8],
+        _: &GenericArray<u8, Self::TagSize>,
+    ) -> Result<(), aead::Error> {
+        Err(aead::Error)
+    }
+}
+
+impl BaseNewAead for EmptyAeadImpl {
+    type KeySize = typenum::U0;
+
+    fn new(_: GenericArray<u8, Self::KeySize>) -> Self {
+        EmptyAeadImpl
+    }
+}
+
 /// Treats the given seq (which is a bytestring) as a big-endian integer, and increments it
 ///
 /// Return Value
@@ -73,10 +150,10 @@ fn increment_seq<A: Aead>(arr: &mut Seq<A>) -> Result<(), ()> {
     Err(())
 }
 
-// From draft02 §6.6
-//     def Context.Nonce(seq):
-//       encSeq = encode_big_endian(seq, len(self.nonce))
-//       return xor(self.nonce, encSeq)
+// From RFC 9180 §5.2
+//     def Context<ROLE>.ComputeNonce(seq):
+//       seq_bytes = I2OSP(seq, Nn)
+//       return xor(self.base_nonce, seq_bytes)
 /// Derives a nonce from the given nonce and a "sequence number". The sequence number is treated as
 /// a big-endian integer with length equal to the nonce length.
 fn mix_nonce<A: Aead>(base_nonce: &AeadNonce<A>, seq: &Seq<A>) -> AeadNonce<A> {
@@ -106,6 +183,97 @@ impl<A: Aead> Default for Seq<A> {
     }
 }
 
+impl<A: Aead> Seq<A> {
+    /// Encodes the given sequence number as a big-endian integer with the length of a nonce.
+    /// Returns `Err(HpkeError::SeqOverflow)` if it doesn't fit.
+    fn from_u64(seq: u64) -> Result<Seq<A>, HpkeError> {
+        let mut buf = <AeadNonce<A> as Default>::default();
+        let seq_bytes = seq.to_be_bytes();
+
+        // Nonces are usually longer than 8 bytes, in which case we pad with zeros on the left.
+        // Otherwise, the bytes that don't fit must be zero.
+        if buf.len() >= seq_bytes.len() {
+            let pad_len = buf.len() - seq_bytes.len();
+            buf[pad_len..].copy_from_slice(&seq_bytes);
+        } else {
+            let (high, low) = seq_bytes.split_at(seq_bytes.len() - buf.len());
+            if high.iter().any(|&b| b != 0) {
+                return Err(HpkeError::SeqOverflow);
+            }
+            buf.copy_from_slice(low);
+        }
+
+        Ok(Seq(buf))
+    }
+}
+
+/// The number of sequence numbers below the highest one opened that `open_at` still accepts
+const REPLAY_WINDOW_SIZE: u64 = 64;
+
+/// Remembers which of the last `REPLAY_WINDOW_SIZE` sequence numbers have been opened, by either
+/// `open` or `open_at`
+#[derive(Clone, Default)]
+struct ReplayWindow {
+    /// The highest sequence number opened so far
+    highest: Option<u64>,
+    /// Bit `i` is set if and only if `highest - i` has been opened
+    seen: u64,
+}
+
+impl ReplayWindow {
+    /// Returns `Err(HpkeError::InvalidSeq)` if `seq` has already been opened, or is too far below
+    /// the highest sequence number opened to tell
+    fn check(&self, seq: u64) -> Result<(), HpkeError> {
+        match self.highest {
+            Some(highest) if seq <= highest => {
+                let age = highest - seq;
+                if age >= REPLAY_WINDOW_SIZE || (self.seen >> age) & 1 == 1 {
+                    Err(HpkeError::InvalidSeq)
+                } else {
+                    Ok(())
+                }
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// Returns the lowest sequence number at or above `seq` that hasn't been opened, treating
+    /// anything too far below the highest sequence number opened as opened. Returns `None` if
+    /// there is no such `u64`.
+    fn next_unopened(&self, seq: u64) -> Option<u64> {
+        let highest = match self.highest {
+            Some(highest) if seq <= highest => highest,
+            _ => return Some(seq),
+        };
+
+        let mut seq = seq.max(highest.saturating_sub(REPLAY_WINDOW_SIZE - 1));
+        while seq <= highest && (self.seen >> (highest - seq)) & 1 == 1 {
+            seq = seq.checked_add(1)?;
+        }
+        Some(seq)
+    }
+
+    /// Records that `seq` has been opened, sliding the window forward if necessary
+    fn mark(&mut self, seq: u64) {
+        match self.highest {
+            Some(highest) if seq <= highest => self.seen |= 1 << (highest - seq),
+            Some(highest) => {
+                let shift = seq - highest;
+                self.seen = if shift >= REPLAY_WINDOW_SIZE {
+                    1
+                } else {
+                    (self.seen << shift) | 1
+                };
+                self.highest = Some(seq);
+            }
+            None => {
+                self.seen = 1;
+                self.highest = Some(seq);
+            }
+        }
+    }
+}
+
 // Necessary for test_overflow
 #[cfg(test)]
 impl<A: Aead> Clone for Seq<A> {
@@ -125,6 +293,20 @@ impl<A: Aead> Marshallable for AeadTag<A> {
     }
 }
 
+// Tags are compared in constant time, so that checking a tag doesn't leak how much of it is right
+impl<A: Aead> ConstantTimeEq for AeadTag<A> {
+    fn ct_eq(&self, other: &AeadTag<A>) -> Choice {
+        self.0.ct_eq(&other.0)
+    }
+}
+
+// Tags aren't secret, so they're printed in hex
+impl<A: Aead> fmt::Debug for AeadTag<A> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_tuple("AeadTag").field(&HexFmt(&self.0)).finish()
+    }
+}
+
 impl<A: Aead> Unmarshallable for AeadTag<A> {
     fn unmarshal(encoded: &[u8]) -> Result<Self, HpkeError> {
         if encoded.len() != Self::size() {
@@ -138,18 +320,26 @@ impl<A: Aead> Unmarshallable for AeadTag<A> {
     }
 }
 
-/// The HPKE encryption context. This is what you use to `seal` plaintexts and `open` ciphertexts.
-pub struct AeadCtx<A: Aead, K: Kdf> {
+/// The state that sender and receiver contexts have in common. `AeadCtxS` and `AeadCtxR` wrap
+/// this, so that each side can only do its own half of the work.
+pub(crate) struct AeadCtx<A: Aead, K: Kdf> {
     /// Records whether the nonce sequence counter has overflowed
     overflowed: bool,
     /// The underlying AEAD instance. This also does decryption.
     encryptor: A::AeadImpl,
+    /// The raw AEAD key. The AEAD instance can't give it back, so we keep a copy for `to_bytes`.
+    #[cfg(feature = "persist")]
+    key: AeadKey<A>,
     /// The base nonce which we XOR with sequence numbers
     nonce: AeadNonce<A>,
     /// The exporter secret, used in the `export()` method
     exporter_secret: ExporterSecret<K>,
+    /// The ciphersuite this context was derived with. This is used in the `export()` method.
+    suite_id: FullSuiteId,
     /// The running sequence number
     seq: Seq<A>,
+    /// The sequence numbers recently opened by `open` or `open_at`
+    replay_window: ReplayWindow,
 }
 
 // Necessary for test_setup_soundness
@@ -159,28 +349,329 @@ impl<A: Aead, K: Kdf> Clone for AeadCtx<A, K> {
         AeadCtx {
             overflowed: self.overflowed,
             encryptor: self.encryptor.clone(),
+            #[cfg(feature = "persist")]
+            key: self.key.clone(),
             nonce: self.nonce.clone(),
             exporter_secret: self.exporter_secret.clone(),
+            suite_id: self.suite_id,
             seq: self.seq.clone(),
+            replay_window: self.replay_window.clone(),
         }
     }
 }
 
+// The nonce and exporter secret are wiped when the context is dropped. The AEAD instance is
+// responsible for wiping its own key. ChaCha20Poly1305 does this, but the AES key schedule in our
+// version of the aes crate isn't wiped.
+impl<A: Aead, K: Kdf> Drop for AeadCtx<A, K> {
+    fn drop(&mut self) {
+        #[cfg(feature = "persist")]
+        self.key.as_mut_slice().zeroize();
+        self.nonce.as_mut_slice().zeroize();
+        self.exporter_secret.as_mut_slice().zeroize();
+        self.seq.0.as_mut_slice().zeroize();
+    }
+}
+
+impl<A: Aead, K: Kdf> ZeroizeOnDrop for AeadCtx<A, K> {}
+
+// The key, nonce, and exporter secret are redacted. The rest of the context is public.
+impl<A: Aead, K: Kdf> fmt::Debug for AeadCtx<A, K> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("AeadCtx")
+            .field("suite_id", &HexFmt(&self.suite_id))
+            .field("key", &Redacted)
+            .field("nonce", &Redacted)
+            .field("exporter_secret", &Redacted)
+            .field("seq", &HexFmt(&self.seq.0))
+            .field("overflowed", &self.overflowed)
+            .finish()
+    }
+}
+
 impl<A: Aead, K: Kdf> AeadCtx<A, K> {
-    /// Makes an AeadCtx from a raw key and nonce
+    /// Makes an AeadCtx from a raw key and nonce. These are copied, so the caller is responsible
+    /// for wiping its own copies.
     pub(crate) fn new(
-        key: AeadKey<A>,
-        nonce: AeadNonce<A>,
-        exporter_secret: ExporterSecret<K>,
+        key: &AeadKey<A>,
+        nonce: &AeadNonce<A>,
+        exporter_secret: &ExporterSecret<K>,
+        suite_id: FullSuiteId,
     ) -> AeadCtx<A, K> {
         AeadCtx {
             overflowed: false,
-            encryptor: <A::AeadImpl as aead::NewAead>::new(key),
-            nonce,
-            exporter_secret,
+            encryptor: <A::AeadImpl as aead::NewAead>::new(key.clone()),
+            #[cfg(feature = "persist")]
+            key: key.clone(),
+            nonce: nonce.clone(),
+            exporter_secret: exporter_secret.clone(),
+            suite_id,
             seq: <Seq<A> as Default>::default(),
+            replay_window: ReplayWindow::default(),
+        }
+    }
+
+    /// Returns the running sequence number, saturating at `u64::MAX`
+    fn seq(&self) -> u64 {
+        // The sequence number is a big-endian integer of nonce length. Any byte beyond the last 8
+        // being nonzero means it doesn't fit in a u64.
+        let bytes = self.seq.0.as_slice();
+        let split = bytes.len().saturating_sub(8);
+        if bytes[..split].iter().any(|&b| b != 0) {
+            return u64::MAX;
+        }
+
+        bytes[split..]
+            .iter()
+            .fold(0u64, |acc, &b| (acc << 8) | u64::from(b))
+    }
+
+    // def Context.Export(exporter_context, L):
+    //   return LabeledExpand(self.exporter_secret, "sec", exporter_context, L)
+    /// Fills a given buffer with secret bytes derived from this encryption context
+    fn export(&self, exporter_ctx: &[u8], out_buf: &mut [u8]) -> Result<(), HpkeError> {
+        // Use our exporter secret as the PRK for an HKDF-Expand op. The only time this fails is
+        // when the length of the PRK is not the the underlying hash function's digest size. But
+        // that's guaranteed by the type system, so we can unwrap().
+        let hkdf_ctx = Hkdf::<K::HashImpl>::from_prk(self.exporter_secret.as_slice()).unwrap();
+
+        // This call either succeeds or returns hkdf::InvalidLength
+        hkdf_ctx
+            .labeled_expand(&self.suite_id, b"sec", exporter_ctx, out_buf)
+            .map_err(|_| HpkeError::InvalidKdfLength)
+    }
+}
+
+/// The length of a serialized suite ID, role, overflow flag, and replay window
+#[cfg(feature = "persist")]
+const CTX_HEADER_LEN: usize = 10 + 1 + 1 + 1 + 8 + 8;
+
+/// The role byte of a serialized sender context
+#[cfg(feature = "persist")]
+const ROLE_SENDER: u8 = b'S';
+/// The role byte of a serialized receiver context
+#[cfg(feature = "persist")]
+const ROLE_RECEIVER: u8 = b'R';
+
+#[cfg(feature = "persist")]
+impl<A: Aead, K: Kdf> AeadCtx<A, K> {
+    /// Serializes the entire state of this context. The format is `suite_id || role ||
+    /// overflowed || seq || has_window || window_highest || window_seen || key || base_nonce ||
+    /// exporter_secret`, where the role and flags are single bytes and the window values are
+    /// big-endian `u64`s.
+    fn to_bytes(&self, role: u8) -> Zeroizing<Vec<u8>> {
+        let mut out = Zeroizing::new(Vec::with_capacity(
+            CTX_HEADER_LEN
+                + self.key.len()
+                + self.seq.0.len()
+                + self.nonce.len()
+                + self.exporter_secret.len(),
+        ));
+
+        out.extend_from_slice(&self.suite_id);
+        out.push(role);
+        out.push(self.overflowed as u8);
+        out.extend_from_slice(&self.seq.0);
+        out.push(self.replay_window.highest.is_some() as u8);
+        out.extend_from_slice(&self.replay_window.highest.unwrap_or(0).to_be_bytes());
+        out.extend_from_slice(&self.replay_window.seen.to_be_bytes());
+        out.extend_from_slice(&self.key);
+        out.extend_from_slice(&self.nonce);
+        out.extend_from_slice(&self.exporter_secret);
+
+        out
+    }
+
+    /// Restores a context that was serialized with `to_bytes` under the given role
+    fn from_bytes(bytes: &[u8], role: u8) -> Result<AeadCtx<A, K>, HpkeError> {
+        let mut key = AeadKey::<A>::default();
+        let mut nonce = AeadNonce::<A>::default();
+        let mut exporter_secret = ExporterSecret::<K>::default();
+        let mut seq = Seq::<A>::default();
+
+        let expected_len =
+            CTX_HEADER_LEN + key.len() + seq.0.len() + nonce.len() + exporter_secret.len();
+        if bytes.len() != expected_len {
+            return Err(HpkeError::InvalidMarshalledLength);
+        }
+
+        // Splits off the next n bytes
+        let mut rest = bytes;
+        let mut take = |n: usize| {
+            let (head, tail) = rest.split_at(n);
+            rest = tail;
+            head
+        };
+        let (key_len, nonce_len, secret_len) = (key.len(), nonce.len(), exporter_secret.len());
+
+        // The suite ID is "HPKE" || kem_id || kdf_id || aead_id. The KEM doesn't matter once the
+        // key schedule is done, but the KDF and AEAD have to be ours. A sender restored as a
+        // receiver, or vice versa, would share a nonce sequence with its peer, so the role has to
+        // match too.
+        let mut suite_id = FullSuiteId::default();
+        suite_id.copy_from_slice(take(10));
+        if suite_id[..4] != *b"HPKE"
+            || suite_id[6..8] != K::KDF_ID.to_be_bytes()
+            || suite_id[8..10] != A::AEAD_ID.to_be_bytes()
+            || take(1)[0] != role
+        {
+            return Err(HpkeError::SuiteMismatch);
         }
+
+        let overflowed = take(1)[0] != 0;
+        seq.0.copy_from_slice(take(nonce_len));
+        let has_window = take(1)[0] != 0;
+        let window_highest = BigEndian::read_u64(take(8));
+        let window_seen = BigEndian::read_u64(take(8));
+        key.copy_from_slice(take(key_len));
+        nonce.copy_from_slice(take(nonce_len));
+        exporter_secret.copy_from_slice(take(secret_len));
+
+        let mut ctx = AeadCtx::new(&key, &nonce, &exporter_secret, suite_id);
+        ctx.overflowed = overflowed;
+        ctx.seq = seq;
+        ctx.replay_window = ReplayWindow {
+            highest: if has_window {
+                Some(window_highest)
+            } else {
+                None
+            },
+            seen: window_seen,
+        };
+
+        // The context has its own copies now
+        key.as_mut_slice().zeroize();
+        nonce.as_mut_slice().zeroize();
+        exporter_secret.as_mut_slice().zeroize();
+
+        Ok(ctx)
     }
+}
+
+/// The sender's HPKE encryption context. This is what you use to `seal` plaintexts. It can't
+/// `open` anything, since the receiver's context uses the same nonces:
+///
+/// ```compile_fail
+/// # use hpke::{aead::{AeadTag, ChaCha20Poly1305}, kdf::HkdfSha256, AeadCtxS};
+/// fn try_open(ctx: &mut AeadCtxS<ChaCha20Poly1305, HkdfSha256>, tag: &AeadTag<ChaCha20Poly1305>) {
+///     let _ = ctx.open(&mut [], b"", tag);
+/// }
+/// ```
+pub struct AeadCtxS<A: Aead, K: Kdf>(AeadCtx<A, K>);
+
+/// The receiver's HPKE encryption context. This is what you use to `open` ciphertexts. It can't
+/// `seal` anything, since the sender's context uses the same nonces:
+///
+/// ```compile_fail
+/// # use hpke::{aead::ChaCha20Poly1305, kdf::HkdfSha256, AeadCtxR};
+/// fn try_seal(ctx: &mut AeadCtxR<ChaCha20Poly1305, HkdfSha256>) {
+///     let _ = ctx.seal(&mut [], b"");
+/// }
+/// ```
+pub struct AeadCtxR<A: Aead, K: Kdf>(AeadCtx<A, K>);
+
+impl<A: Aead, K: Kdf> From<AeadCtx<A, K>> for AeadCtxS<A, K> {
+    fn from(ctx: AeadCtx<A, K>) -> AeadCtxS<A, K> {
+        AeadCtxS(ctx)
+    }
+}
+
+impl<A: Aead, K: Kdf> From<AeadCtx<A, K>> for AeadCtxR<A, K> {
+    fn from(ctx: AeadCtx<A, K>) -> AeadCtxR<A, K> {
+        AeadCtxR(ctx)
+    }
+}
+
+// Necessary for test_setup_soundness
+#[cfg(test)]
+impl<A: Aead, K: Kdf> Clone for AeadCtxS<A, K> {
+    fn clone(&self) -> AeadCtxS<A, K> {
+        AeadCtxS(self.0.clone())
+    }
+}
+
+// Necessary for test_setup_soundness
+#[cfg(test)]
+impl<A: Aead, K: Kdf> Clone for AeadCtxR<A, K> {
+    fn clone(&self) -> AeadCtxR<A, K> {
+        AeadCtxR(self.0.clone())
+    }
+}
+
+// The inner context wipes itself
+impl<A: Aead, K: Kdf> ZeroizeOnDrop for AeadCtxS<A, K> {}
+impl<A: Aead, K: Kdf> ZeroizeOnDrop for AeadCtxR<A, K> {}
+
+impl<A: Aead, K: Kdf> fmt::Debug for AeadCtxS<A, K> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_tuple("AeadCtxS").field(&self.0).finish()
+    }
+}
+
+impl<A: Aead, K: Kdf> fmt::Debug for AeadCtxR<A, K> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_tuple("AeadCtxR").field(&self.0).finish()
+    }
+}
+
+// Both sides have the same sequence number accessor, exporter, and (optionally) persistence
+macro_rules! impl_common_ctx_methods {
+    ($ctx_ty:ident, $role:expr) => {
+        impl<A: Aead, K: Kdf> $ctx_ty<A, K> {
+            /// Returns the running sequence number, i.e., the number of messages this context has
+            /// processed with its `seal`/`open` method. This saturates at `u64::MAX`, which no
+            /// context will reach in practice.
+            pub fn seq(&self) -> u64 {
+                self.0.seq()
+            }
+
+            /// Fills a given buffer with secret bytes derived from this encryption context. This
+            /// value does not depend on sequence number, so it is constant for the lifetime of
+            /// this context, and is the same on both sides.
+            ///
+            /// Return Value
+            /// ============
+            /// Returns `Ok(())` on success. If the buffer length is more than 255x the digest size
+            /// of the underlying hash function, returns an `Err(HpkeError::InvalidKdfLength)`.
+            pub fn export(&self, exporter_ctx: &[u8], out_buf: &mut [u8]) -> Result<(), HpkeError> {
+                self.0.export(exporter_ctx, out_buf)
+            }
+        }
+
+        #[cfg(feature = "persist")]
+        impl<A: Aead, K: Kdf> $ctx_ty<A, K> {
+            /// Serializes the entire state of this context, so that it can be restored with
+            /// `from_bytes`, e.g., after a restart.
+            ///
+            /// The output contains the context's key, so anyone who has it can read and forge
+            /// messages in this session. Also, a sender must never restore the same state twice,
+            /// or keep using a context after saving its state and restoring it elsewhere. Either
+            /// would reuse sequence numbers, and thus nonces, which breaks the security of the AEAD
+            /// entirely.
+            pub fn to_bytes(&self) -> Zeroizing<Vec<u8>> {
+                self.0.to_bytes($role)
+            }
+
+            /// Restores a context that was serialized with `to_bytes`. See `to_bytes` for how not
+            /// to use this.
+            ///
+            /// Return Value
+            /// ============
+            /// Returns the context on success. If `bytes` is the wrong length for this AEAD and
+            /// KDF, returns `Err(HpkeError::InvalidMarshalledLength)`. If the context was made with
+            /// a different AEAD or KDF, or was serialized from the other side of the conversation,
+            /// returns `Err(HpkeError::SuiteMismatch)`.
+            pub fn from_bytes(bytes: &[u8]) -> Result<$ctx_ty<A, K>, HpkeError> {
+                AeadCtx::from_bytes(bytes, $role).map($ctx_ty)
+            }
+        }
+    };
+}
+
+impl_common_ctx_methods!(AeadCtxS, ROLE_SENDER);
+impl_common_ctx_methods!(AeadCtxR, ROLE_RECEIVER);
+
+impl<A: SealingAead, K: Kdf> AeadCtxS<A, K> {
     // def Context.Seal(aad, pt):
     //   ct = Seal(self.key, self.Nonce(self.seq), aad, pt)
     //   self.IncrementSeq()
@@ -195,15 +686,16 @@ impl<A: Aead, K: Kdf> AeadCtx<A, K> {
     /// `plaintext` will be unmodified. If an unspecified error happened during encryption, returns
     /// `Err(HpkeError::Encryption)`. If this happens, the contents of `plaintext` is undefined.
     pub fn seal(&mut self, plaintext: &mut [u8], aad: &[u8]) -> Result<AeadTag<A>, HpkeError> {
-        if self.overflowed {
+        let ctx = &mut self.0;
+        if ctx.overflowed {
             // If the sequence counter overflowed, we've been used for far too long. Shut down.
             Err(HpkeError::SeqOverflow)
         } else {
             // Compute the nonce and do the encryption in place
-            let nonce = mix_nonce(&self.nonce, &self.seq);
-            let tag_res = self
+            let nonce = mix_nonce(&ctx.nonce, &ctx.seq);
+            let tag_res = ctx
                 .encryptor
-                .encrypt_in_place_detached(&nonce, &aad, plaintext);
+                .encrypt_in_place_detached(&nonce, aad, plaintext);
 
             // Check if an error occurred when encrypting
             let tag = match tag_res {
@@ -212,8 +704,8 @@ impl<A: Aead, K: Kdf> AeadCtx<A, K> {
             };
 
             // Try to increment the sequence counter. If it fails, this was our last encryption.
-            if increment_seq(&mut self.seq).is_err() {
-                self.overflowed = true;
+            if increment_seq(&mut ctx.seq).is_err() {
+                ctx.overflowed = true;
             }
 
             // Return the tag
@@ -221,6 +713,57 @@ impl<A: Aead, K: Kdf> AeadCtx<A, K> {
         }
     }
 
+    /// Does a `seal` at the given sequence number, instead of the running one. This is for
+    /// transports that can lose or reorder messages, where the receiver uses `open_at` with the
+    /// same sequence number. To rule out nonce reuse, `seq` can't be below the running sequence
+    /// number, and the running sequence number moves to `seq + 1` afterwards. So sequence numbers
+    /// can be skipped, but never reused.
+    ///
+    /// Return Value
+    /// ============
+    /// Returns `Ok(tag)` on success. If `seq` is below the running sequence number, returns
+    /// `Err(HpkeError::InvalidSeq)`. If this happens, `plaintext` will be unmodified. Otherwise,
+    /// errors are the same as in `seal`.
+    pub fn seal_at(
+        &mut self,
+        seq: u64,
+        plaintext: &mut [u8],
+        aad: &[u8],
+    ) -> Result<AeadTag<A>, HpkeError> {
+        if self.0.overflowed {
+            // If the sequence counter overflowed, we've been used for far too long. Shut down.
+            return Err(HpkeError::SeqOverflow);
+        }
+
+        // Both are big-endian and the same length, so comparing bytes compares the numbers
+        let seq = Seq::<A>::from_u64(seq)?;
+        if seq.0.as_slice() < self.0.seq.0.as_slice() {
+            return Err(HpkeError::InvalidSeq);
+        }
+
+        self.0.seq = seq;
+        self.seal(plaintext, aad)
+    }
+
+    /// Does a `seal` on a copy of `plaintext`, and returns `ciphertext || tag`, which is the
+    /// ciphertext format that the spec uses.
+    ///
+    /// Return Value
+    /// ============
+    /// Returns `Ok(ciphertext_and_tag)` on success. Errors are the same as in `seal`.
+    #[cfg(feature = "alloc")]
+    pub fn seal_to_vec(&mut self, plaintext: &[u8], aad: &[u8]) -> Result<Vec<u8>, HpkeError> {
+        // Encrypt a copy of the plaintext in place, then append the tag
+        let mut ciphertext_and_tag = Vec::with_capacity(plaintext.len() + AeadTag::<A>::size());
+        ciphertext_and_tag.extend_from_slice(plaintext);
+        let tag = self.seal(&mut ciphertext_and_tag, aad)?;
+        ciphertext_and_tag.extend_from_slice(&tag.marshal());
+
+        Ok(ciphertext_and_tag)
+    }
+}
+
+impl<A: SealingAead, K: Kdf> AeadCtxR<A, K> {
     // def Context.Open(aad, ct):
     //   pt = Open(self.key, self.Nonce(self.seq), aad, ct)
     //   if pt == OpenError:
@@ -236,21 +779,41 @@ impl<A: Aead, K: Kdf> AeadCtx<A, K> {
     /// the sequence number overflowed, returns `Err(HpkeError::SeqOverflow)`. If this happens,
     /// `plaintext` will be unmodified. If the tag fails to validate, returns
     /// `Err(HpkeError::InvalidTag)`. If this happens, `plaintext` is in an undefined state.
+    ///
+    /// If `open_at` already opened the running sequence number, or has moved so far past it that
+    /// it can't be told apart from an opened one, this first moves the running sequence number up
+    /// to the next one that can still be opened. This happens even if opening then fails.
     pub fn open(
         &mut self,
         ciphertext: &mut [u8],
         aad: &[u8],
         tag: &AeadTag<A>,
     ) -> Result<(), HpkeError> {
-        if self.overflowed {
+        let ctx = &mut self.0;
+        if ctx.overflowed {
             // If the sequence counter overflowed, we've been used for far too long. Shut down.
             Err(HpkeError::SeqOverflow)
         } else {
+            // Share the replay window with open_at, so neither can open what the other already
+            // has. Skip whatever open_at got to first, rather than getting stuck on it.
+            let seq = ctx.seq();
+            let next = match ctx.replay_window.next_unopened(seq) {
+                Some(next) => next,
+                None => {
+                    ctx.overflowed = true;
+                    return Err(HpkeError::SeqOverflow);
+                }
+            };
+            // A saturated seq is never moved, since its real value can be above next
+            if next > seq {
+                ctx.seq = Seq::from_u64(next)?;
+            }
+
             // Compute the nonce and do the encryption in place
-            let nonce = mix_nonce(&self.nonce, &self.seq);
-            let decrypt_res = self
+            let nonce = mix_nonce(&ctx.nonce, &ctx.seq);
+            let decrypt_res = ctx
                 .encryptor
-                .decrypt_in_place_detached(&nonce, &aad, ciphertext, &tag.0);
+                .decrypt_in_place_detached(&nonce, aad, ciphertext, &tag.0);
 
             if decrypt_res.is_err() {
                 // Opening failed due to a bad tag
@@ -258,44 +821,140 @@ impl<A: Aead, K: Kdf> AeadCtx<A, K> {
             }
 
             // Opening was a success
+            ctx.replay_window.mark(next);
             // Try to increment the sequence counter. If it fails, this was our last
             // decryption.
-            if increment_seq(&mut self.seq).is_err() {
-                self.overflowed = true;
+            if increment_seq(&mut ctx.seq).is_err() {
+                ctx.overflowed = true;
             }
 
             Ok(())
         }
     }
 
-    // def Context.Export(exporter_context, L):
-    //     return Expand(self.exporter_secret, exporter_context, L)
-    /// Fills a given buffer with secret bytes derived from this encryption context. This value
-    /// does not depend on sequence number, so it is constant for the lifetime of this context.
+    /// Does an `open` at the given sequence number, instead of the running one. This is for
+    /// transports that can lose or reorder messages. Messages can be opened in any order, but
+    /// each sequence number can only be opened once, and only if it's less than 64 below the
+    /// highest one opened so far. This shares its record of opened sequence numbers with `open`,
+    /// so a message can't be opened once by each, and `open` skips over the ones opened here.
     ///
     /// Return Value
     /// ============
-    /// Returns `Ok(())` on success. If the buffer length is more than 255x the digest size of the
-    /// underlying hash function, returns an `Err(HpkeError::InvalidKdfLength)`.
-    pub fn export(&self, info: &[u8], out_buf: &mut [u8]) -> Result<(), HpkeError> {
-        // Use our exporter secret as the PRK for an HKDF-Expand op. The only time this fails is
-        // when the length of the PRK is not the the underlying hash function's digest size. But
-        // that's guaranteed by the type system, so we can unwrap().
-        let hkdf_ctx = Hkdf::<K::HashImpl>::from_prk(self.exporter_secret.as_slice()).unwrap();
+    /// Returns `Ok(())` on success. If this context has been used for so many decryptions that
+    /// the sequence number overflowed, returns `Err(HpkeError::SeqOverflow)`. If this happens,
+    /// `ciphertext` will be unmodified. If `seq` was already opened, or is too old to tell, returns
+    /// `Err(HpkeError::InvalidSeq)`. If this happens, `ciphertext` will be unmodified. If the tag
+    /// fails to validate, returns `Err(HpkeError::InvalidTag)`. If this happens, `ciphertext` is
+    /// in an undefined state.
+    pub fn open_at(
+        &mut self,
+        seq: u64,
+        ciphertext: &mut [u8],
+        aad: &[u8],
+        tag: &AeadTag<A>,
+    ) -> Result<(), HpkeError> {
+        let ctx = &mut self.0;
+        if ctx.overflowed {
+            // If the sequence counter overflowed, we've been used for far too long. Shut down.
+            return Err(HpkeError::SeqOverflow);
+        }
+        ctx.replay_window.check(seq)?;
 
-        // This call either succeeds or returns hkdf::InvalidLength
-        hkdf_ctx
-            .expand(info, out_buf)
-            .map_err(|_| HpkeError::InvalidKdfLength)
+        // Compute the nonce and do the decryption in place
+        let nonce = mix_nonce(&ctx.nonce, &Seq::<A>::from_u64(seq)?);
+        ctx.encryptor
+            .decrypt_in_place_detached(&nonce, aad, ciphertext, &tag.0)
+            .map_err(|_| HpkeError::InvalidTag)?;
+
+        // Only authentic messages move the window. Otherwise anyone could slide it forward.
+        ctx.replay_window.mark(seq);
+        Ok(())
+    }
+
+    /// Does an `open` on `ciphertext || tag`, which is the ciphertext format that the spec uses,
+    /// and returns the plaintext.
+    ///
+    /// Return Value
+    /// ============
+    /// Returns `Ok(plaintext)` on success. If `ciphertext_and_tag` is shorter than a tag, returns
+    /// `Err(HpkeError::InvalidTag)`. Otherwise, errors are the same as in `open`.
+    #[cfg(feature = "alloc")]
+    pub fn open_to_vec(
+        &mut self,
+        ciphertext_and_tag: &[u8],
+        aad: &[u8],
+    ) -> Result<Vec<u8>, HpkeError> {
+        // Split the input into the ciphertext and the tag. If there's no room for a tag, there's
+        // certainly no valid tag.
+        let tag_idx = ciphertext_and_tag
+            .len()
+            .checked_sub(AeadTag::<A>::size())
+            .ok_or(HpkeError::InvalidTag)?;
+        let (ciphertext, tag_bytes) = ciphertext_and_tag.split_at(tag_idx);
+        let tag = AeadTag::<A>::unmarshal(tag_bytes)?;
+
+        // Decrypt a copy of the ciphertext in place
+        let mut plaintext = ciphertext.to_vec();
+        self.open(&mut plaintext, aad, &tag)?;
+
+        Ok(plaintext)
     }
 }
 
 #[cfg(test)]
 mod test {
     use super::{AeadTag, AesGcm128, AesGcm256, ChaCha20Poly1305, Seq};
-    use crate::{kdf::HkdfSha256, kex::Unmarshallable, test_util::gen_ctx_simple_pair, HpkeError};
+    use crate::{
+        kdf::HkdfSha256,
+        kex::{Marshallable, Unmarshallable},
+        test_util::gen_ctx_simple_pair,
+        HpkeError,
+    };
+
+    use core::mem::ManuallyDrop;
+    use subtle::ConstantTimeEq;
 
-    use core::u8;
+    /// Tests that tags compare correctly in constant time, and that context secrets aren't printed
+    #[test]
+    fn test_ct_eq_and_debug() {
+        type A = ChaCha20Poly1305;
+        let (mut aead_ctx, _) = gen_ctx_simple_pair::<A, HkdfSha256>();
+
+        let mut plaintext = *b"Bow down before me!";
+        let tag1 = aead_ctx.seal(&mut plaintext, b"").unwrap();
+        let tag2 = aead_ctx.seal(&mut plaintext, b"").unwrap();
+        let tag1_copy = AeadTag::<A>::unmarshal(&tag1.marshal()).unwrap();
+        assert!(bool::from(tag1.ct_eq(&tag1_copy)));
+        assert!(!bool::from(tag1.ct_eq(&tag2)));
+
+        // Tags are printed in hex
+        assert_eq!(
+            format!("{:?}", tag1),
+            format!("AeadTag({})", hex::encode(tag1.marshal()))
+        );
+
+        // None of the context's secrets show up
+        let debug_str = format!("{:?}", aead_ctx);
+        assert!(!debug_str.contains(&hex::encode(&aead_ctx.0.nonce)));
+        assert!(!debug_str.contains(&hex::encode(&aead_ctx.0.exporter_secret)));
+        assert!(debug_str.contains("exporter_secret: [redacted]"));
+    }
+
+    /// Tests that the nonce and exporter secret of a context are wiped when it's dropped
+    #[test]
+    fn test_ctx_zeroize_on_drop() {
+        let (aead_ctx, _) = gen_ctx_simple_pair::<ChaCha20Poly1305, HkdfSha256>();
+
+        // Run the destructor without freeing the memory, so we can look at what it left behind
+        let mut aead_ctx = ManuallyDrop::new(aead_ctx);
+        assert!(aead_ctx.0.nonce.iter().any(|&b| b != 0));
+        assert!(aead_ctx.0.exporter_secret.iter().any(|&b| b != 0));
+        // Safety: the context is never dropped again, and byte arrays are valid after their
+        // destructors
+        unsafe { core::ptr::drop_in_place(&mut *aead_ctx) };
+        assert!(aead_ctx.0.nonce.iter().all(|&b| b == 0));
+        assert!(aead_ctx.0.exporter_secret.iter().all(|&b| b == 0));
+    }
 
     /// Tests that encryption context secret export does not change behavior based on the
     /// underlying sequence number
@@ -325,6 +984,236 @@ mod test {
         assert_eq!(secret1, secret2);
     }
 
+    /// Tests that exporting more than 255x the digest size of the hash function is an error
+    #[test]
+    fn test_export_length() {
+        // Set up a context. The digest size of SHA-256 is 32 bytes.
+        let (aead_ctx, _) = gen_ctx_simple_pair::<ChaCha20Poly1305, HkdfSha256>();
+
+        // The maximum length is fine
+        let mut secret = vec![0u8; 255 * 32];
+        aead_ctx
+            .export(b"test_export_length", &mut secret)
+            .expect("export() failed");
+
+        // One more byte is too much
+        let mut secret = vec![0u8; 255 * 32 + 1];
+        match aead_ctx.export(b"test_export_length", &mut secret) {
+            Err(HpkeError::InvalidKdfLength) => {} // Good, this is too long
+            Err(e) => panic!("export() should have failed. Instead got {}", e),
+            _ => panic!("export() should have failed. Instead it succeeded"),
+        }
+    }
+
+    /// Tests that seal_at and open_at work out of order, and reject reused and stale sequence
+    /// numbers
+    #[test]
+    fn test_seal_at_open_at() {
+        type A = ChaCha20Poly1305;
+        let (mut sender_ctx, mut receiver_ctx) = gen_ctx_simple_pair::<A, HkdfSha256>();
+
+        // Seal messages 0, 1, 2 normally, and then 100 after skipping some
+        let msg = b"Rock on, Simon";
+        let mut sealed = vec![];
+        for &seq in &[0u64, 1, 2, 100] {
+            let mut ciphertext = *msg;
+            let tag = if seq == 100 {
+                sender_ctx.seal_at(seq, &mut ciphertext, b"").unwrap()
+            } else {
+                sender_ctx.seal(&mut ciphertext, b"").unwrap()
+            };
+            sealed.push((seq, ciphertext, tag));
+        }
+
+        assert_eq!(sender_ctx.seq(), 101);
+
+        // Sequence numbers can't go backwards on the sender side
+        let mut plaintext = *msg;
+        assert!(matches!(
+            sender_ctx.seal_at(50, &mut plaintext, b""),
+            Err(HpkeError::InvalidSeq)
+        ));
+
+        // Open them out of order
+        for &i in &[2, 0, 1, 3] {
+            let (seq, mut ciphertext, ref tag) = sealed[i];
+            receiver_ctx
+                .open_at(seq, &mut ciphertext, b"", tag)
+                .unwrap();
+            assert_eq!(&ciphertext, msg);
+        }
+
+        // Opening the same message twice is a replay. 0 is also too far behind 100 to tell.
+        for &i in &[3, 0] {
+            let (seq, mut ciphertext, ref tag) = sealed[i];
+            assert!(matches!(
+                receiver_ctx.open_at(seq, &mut ciphertext, b"", tag),
+                Err(HpkeError::InvalidSeq)
+            ));
+        }
+
+        // A message opened at the wrong sequence number doesn't authenticate
+        let (_, mut ciphertext, ref tag) = sealed[1];
+        assert!(matches!(
+            receiver_ctx.open_at(99, &mut ciphertext, b"", tag),
+            Err(HpkeError::InvalidTag)
+        ));
+    }
+
+    /// Tests that open and open_at can't each open the same message
+    #[test]
+    fn test_open_and_open_at_replay() {
+        type A = ChaCha20Poly1305;
+        let (mut sender_ctx, mut receiver_ctx) = gen_ctx_simple_pair::<A, HkdfSha256>();
+
+        let msg = b"Wish you were here";
+        let mut sealed = vec![];
+        for _ in 0..3 {
+            let mut ciphertext = *msg;
+            let tag = sender_ctx.seal(&mut ciphertext, b"").unwrap();
+            sealed.push((ciphertext, tag));
+        }
+
+        // Message 0 is opened in order, and then replayed with open_at
+        let (mut ciphertext, ref tag) = sealed[0];
+        receiver_ctx.open(&mut ciphertext, b"", tag).unwrap();
+        let (mut ciphertext, ref tag) = sealed[0];
+        assert!(matches!(
+            receiver_ctx.open_at(0, &mut ciphertext, b"", tag),
+            Err(HpkeError::InvalidSeq)
+        ));
+
+        // Message 1 is opened with open_at. open skips over it rather than getting stuck, so a
+        // replay of it fails, and message 2 is opened in order.
+        let (mut ciphertext, ref tag) = sealed[1];
+        receiver_ctx.open_at(1, &mut ciphertext, b"", tag).unwrap();
+        let (mut ciphertext, ref tag) = sealed[1];
+        assert!(receiver_ctx.open(&mut ciphertext, b"", tag).is_err());
+        assert_eq!(receiver_ctx.seq(), 2);
+        let (mut ciphertext, ref tag) = sealed[2];
+        receiver_ctx.open(&mut ciphertext, b"", tag).unwrap();
+        assert_eq!(&ciphertext, msg);
+        assert_eq!(receiver_ctx.seq(), 3);
+    }
+
+    /// Tests that `open` keeps working after `open_at` moves the replay window far past the running
+    /// sequence number, and that `open_at` stops once the sequence number overflows
+    #[test]
+    fn test_open_after_open_at() {
+        type A = ChaCha20Poly1305;
+        let (mut sender_ctx, mut receiver_ctx) = gen_ctx_simple_pair::<A, HkdfSha256>();
+
+        let msg = b"Wish you were here";
+        let mut sealed = vec![];
+        for _ in 0..71 {
+            let mut ciphertext = *msg;
+            let tag = sender_ctx.seal(&mut ciphertext, b"").unwrap();
+            sealed.push((ciphertext, tag));
+        }
+
+        // Opening message 70 first leaves messages 0 through 6 too old to tell. open moves up to
+        // message 7 and carries on from there.
+        let (mut ciphertext, ref tag) = sealed[70];
+        receiver_ctx.open_at(70, &mut ciphertext, b"", tag).unwrap();
+        for (i, (ciphertext, tag)) in sealed.iter().enumerate().take(70).skip(7) {
+            let mut ciphertext = *ciphertext;
+            receiver_ctx.open(&mut ciphertext, b"", tag).unwrap();
+            assert_eq!(&ciphertext, msg);
+            assert_eq!(receiver_ctx.seq(), i as u64 + 1);
+        }
+
+        // Message 70 was already opened, so open moves past it too
+        let mut ciphertext = *msg;
+        let tag = sender_ctx.seal(&mut ciphertext, b"").unwrap();
+        receiver_ctx.open(&mut ciphertext, b"", &tag).unwrap();
+        assert_eq!(receiver_ctx.seq(), 72);
+
+        // An overflowed context can't open anything, at any sequence number
+        receiver_ctx.0.overflowed = true;
+        let mut ciphertext = *msg;
+        let tag = sender_ctx.seal_at(100, &mut ciphertext, b"").unwrap();
+        assert!(matches!(
+            receiver_ctx.open_at(100, &mut ciphertext, b"", &tag),
+            Err(HpkeError::SeqOverflow)
+        ));
+    }
+
+    /// Tests that a context restored from `to_bytes()` picks up right where the original left off,
+    /// and that it can't be restored under a different ciphersuite or role
+    #[cfg(feature = "persist")]
+    #[test]
+    fn test_ctx_persistence() {
+        use super::{AeadCtxR, AeadCtxS};
+        use crate::{kem::X25519HkdfSha256, test_util::aead_ctx_eq, util::full_suite_id};
+
+        type A = ChaCha20Poly1305;
+        type K = HkdfSha256;
+
+        let (mut sender_ctx, mut receiver_ctx) = gen_ctx_simple_pair::<A, K>();
+        // Pretend these came from a key schedule
+        let suite_id = full_suite_id::<A, K, X25519HkdfSha256>();
+        sender_ctx.0.suite_id = suite_id;
+        receiver_ctx.0.suite_id = suite_id;
+
+        // Move both contexts along a bit, opening one message out of order
+        let msg = b"Nobody said it was easy";
+        let mut sealed = vec![];
+        for _ in 0..5 {
+            let mut ciphertext = *msg;
+            let tag = sender_ctx.seal(&mut ciphertext, b"").unwrap();
+            sealed.push((ciphertext, tag));
+        }
+        for &seq in &[1u64, 4] {
+            let (mut ciphertext, ref tag) = sealed[seq as usize];
+            receiver_ctx
+                .open_at(seq, &mut ciphertext, b"", tag)
+                .unwrap();
+        }
+        assert_eq!(sender_ctx.seq(), 5);
+
+        let mut new_sender_ctx = AeadCtxS::<A, K>::from_bytes(&sender_ctx.to_bytes()).unwrap();
+        let mut new_receiver_ctx = AeadCtxR::<A, K>::from_bytes(&receiver_ctx.to_bytes()).unwrap();
+        assert_eq!(new_sender_ctx.seq(), 5);
+
+        // The replay window came along. 4 was already opened, but 2 wasn't.
+        let (mut ciphertext, ref tag) = sealed[4];
+        assert!(matches!(
+            new_receiver_ctx.open_at(4, &mut ciphertext, b"", tag),
+            Err(HpkeError::InvalidSeq)
+        ));
+        let (mut ciphertext, ref tag) = sealed[2];
+        new_receiver_ctx
+            .open_at(2, &mut ciphertext, b"", tag)
+            .unwrap();
+
+        // The exporter secret came along too
+        let mut secret1 = [0u8; 32];
+        let mut secret2 = [0u8; 32];
+        sender_ctx.export(b"info", &mut secret1).unwrap();
+        new_receiver_ctx.export(b"info", &mut secret2).unwrap();
+        assert_eq!(secret1, secret2);
+
+        // The restored sender is at seq 5, so sync the receiver up to it and check they agree
+        new_receiver_ctx.0.seq = new_sender_ctx.0.seq.clone();
+        assert!(aead_ctx_eq(&mut new_sender_ctx, &mut new_receiver_ctx));
+
+        // Same lengths, different AEAD
+        let bytes = sender_ctx.to_bytes();
+        assert!(matches!(
+            AeadCtxS::<AesGcm256, K>::from_bytes(&bytes),
+            Err(HpkeError::SuiteMismatch)
+        ));
+        // A sender can't be restored as a receiver, since they'd use the same nonces
+        assert!(matches!(
+            AeadCtxR::<A, K>::from_bytes(&bytes),
+            Err(HpkeError::SuiteMismatch)
+        ));
+        assert!(matches!(
+            AeadCtxS::<A, K>::from_bytes(&bytes[1..]),
+            Err(HpkeError::InvalidMarshalledLength)
+        ));
+    }
+
     /// Tests that sequence overflowing causes an error. This logic is cipher-agnostic, so we don't
     /// bother making this a macro
     #[test]
@@ -340,8 +1229,8 @@ mod test {
         };
 
         let (mut aead_ctx1, mut aead_ctx2) = gen_ctx_simple_pair::<ChaCha20Poly1305, HkdfSha256>();
-        aead_ctx1.seq = big_seq.clone();
-        aead_ctx2.seq = big_seq.clone();
+        aead_ctx1.0.seq = big_seq.clone();
+        aead_ctx2.0.seq = big_seq.clone();
 
         // These should support precisely one more encryption before it registers an overflow
 
@@ -422,6 +1311,45 @@ mod test {
         };
     }
 
+    /// Tests that `open_to_vec()` can decrypt things encrypted with `seal_to_vec()`, and that the
+    /// output is `ciphertext || tag`
+    #[cfg(feature = "alloc")]
+    #[test]
+    fn test_to_vec_correctness() {
+        use crate::kex::Marshallable;
+
+        type A = ChaCha20Poly1305;
+        type K = HkdfSha256;
+
+        let (mut ctx1, mut ctx2) = gen_ctx_simple_pair::<A, K>();
+        let mut ctx3 = ctx1.clone();
+
+        let msg = b"Been spendin' most their lives livin' in the gangsta's paradise";
+        let aad = b"keep spendin' most our lives livin' in the gangsta's paradise";
+
+        // Seal with the first context, and do the same thing in place with a clone of it
+        let ciphertext_and_tag = ctx1.seal_to_vec(msg, aad).expect("seal_to_vec() failed");
+        let mut ciphertext = *msg;
+        let tag = ctx3.seal(&mut ciphertext[..], aad).expect("seal() failed");
+        assert_eq!(
+            ciphertext_and_tag,
+            [&ciphertext[..], tag.marshal().as_slice()].concat()
+        );
+
+        // Open with the second context
+        let decrypted = ctx2
+            .open_to_vec(&ciphertext_and_tag, aad)
+            .expect("open_to_vec() failed");
+        assert_eq!(&decrypted[..], &msg[..]);
+
+        // Something shorter than a tag can't be opened
+        match ctx2.open_to_vec(&ciphertext_and_tag[..15], aad) {
+            Err(HpkeError::InvalidTag) => {} // Good, there's no tag here
+            Err(e) => panic!("open_to_vec() should have failed. Instead got {}", e),
+            _ => panic!("open_to_vec() should have failed. Instead it succeeded"),
+        }
+    }
+
     // The hash function and DH impl shouldn't really matter
     test_ctx_correctness!(test_ctx_correctness_aes128, AesGcm128);
     test_ctx_correctness!(test_ctx_correctness_aes256, AesGcm256);
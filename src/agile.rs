@@ -0,0 +1,928 @@
+//! Runtime algorithm agility. Instead of picking a ciphersuite at the type level, you can pick one
+//! from its algorithm IDs at runtime, and get back a boxed encryption context. Keys, encapsulated
+//! keys, and tags are passed around as bytes, tagged with the algorithm they belong to, and every
+//! algorithm tag is checked before anything is unmarshalled.
+//!
+//! This is more error-prone than the typed API, and it pulls every supported algorithm into your
+//! binary. Only use it if you actually need to negotiate a ciphersuite at runtime.
+
+use crate::prelude::*;
+use crate::{
+    aead::{
+        Aead, AeadCtxR, AeadCtxS, AeadTag, AesGcm128, AesGcm256, ChaCha20Poly1305, ExportOnly,
+        SealingAead,
+    },
+    kdf::{HkdfSha256, HkdfSha384, HkdfSha512, Kdf as KdfTrait},
+    kem::{
+        DhP256HkdfSha256, DhP384HkdfSha384, DhP521HkdfSha512, Kem as KemTrait, MlKem1024, MlKem768,
+        X25519HkdfSha256, X448HkdfSha512,
+    },
+    kex::{Marshallable, Unmarshallable},
+    op_mode::{OpModeR, OpModeS, Psk, PskBundle},
+    util::{HexFmt, Redacted},
+    HpkeError,
+};
+
+use core::fmt;
+
+use rand::{CryptoRng, RngCore};
+use zeroize::{Zeroize, ZeroizeOnDrop};
+
+/// Describes things that can go wrong in the agile API
+#[derive(Clone, Copy, Debug)]
+pub enum AgileHpkeError {
+    /// When two values that should use the same algorithm don't. Error is of the form
+    /// `((alg1, alg1_location) , (alg2, alg2_location))`.
+    AlgMismatch((&'static str, &'static str), (&'static str, &'static str)),
+    /// When you get an algorithm identifier you don't recognize. Error is of the form
+    /// `(alg, given_id)`.
+    UnknownAlgIdent(&'static str, u16),
+    /// When `seal` or `open` is called on a context whose AEAD is `AeadAlg::ExportOnly`
+    ExportOnly,
+    /// When an auth mode is used with a KEM that has no auth modes, like ML-KEM. Error is of the
+    /// form `kem_alg`.
+    UnsupportedMode(&'static str),
+    /// Represents an error in the rest of this crate
+    HpkeError(HpkeError),
+}
+
+// This just wraps the HpkeError
+impl From<HpkeError> for AgileHpkeError {
+    fn from(e: HpkeError) -> AgileHpkeError {
+        AgileHpkeError::HpkeError(e)
+    }
+}
+
+impl core::fmt::Display for AgileHpkeError {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        match self {
+            AgileHpkeError::AlgMismatch((alg1, loc1), (alg2, loc2)) => write!(
+                f,
+                "Algorithm mismatch: {} ({}) vs {} ({})",
+                alg1, loc1, alg2, loc2
+            ),
+            AgileHpkeError::UnknownAlgIdent(alg, id) => {
+                write!(f, "Unknown {} identifier: 0x{:04x}", alg, id)
+            }
+            AgileHpkeError::ExportOnly => write!(f, "Export-only contexts can't seal or open"),
+            AgileHpkeError::UnsupportedMode(kem_alg) => {
+                write!(f, "Auth modes are unsupported by {}", kem_alg)
+            }
+            AgileHpkeError::HpkeError(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl std::error::Error for AgileHpkeError {}
+
+/// The supported AEAD algorithms
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum AeadAlg {
+    /// AES-GCM-128
+    AesGcm128,
+    /// AES-GCM-256
+    AesGcm256,
+    /// ChaCha20-Poly1305
+    ChaCha20Poly1305,
+    /// Export-only. Contexts with this AEAD can only export secrets.
+    ExportOnly,
+}
+
+impl AeadAlg {
+    /// Every supported AEAD algorithm
+    pub const ALL: [AeadAlg; 4] = [
+        AeadAlg::AesGcm128,
+        AeadAlg::AesGcm256,
+        AeadAlg::ChaCha20Poly1305,
+        AeadAlg::ExportOnly,
+    ];
+
+    /// Returns the name of this algorithm
+    pub fn name(&self) -> &'static str {
+        match self {
+            AeadAlg::AesGcm128 => "AesGcm128",
+            AeadAlg::AesGcm256 => "AesGcm256",
+            AeadAlg::ChaCha20Poly1305 => "ChaCha20Poly1305",
+            AeadAlg::ExportOnly => "ExportOnly",
+        }
+    }
+
+    /// Looks up the algorithm with the given AEAD ID
+    pub fn try_from_u16(id: u16) -> Result<AeadAlg, AgileHpkeError> {
+        AeadAlg::ALL
+            .iter()
+            .copied()
+            .find(|alg| alg.to_u16() == id)
+            .ok_or(AgileHpkeError::UnknownAlgIdent("AeadAlg", id))
+    }
+
+    /// Returns the AEAD ID of this algorithm
+    pub fn to_u16(self) -> u16 {
+        match self {
+            AeadAlg::AesGcm128 => AesGcm128::AEAD_ID,
+            AeadAlg::AesGcm256 => AesGcm256::AEAD_ID,
+            AeadAlg::ChaCha20Poly1305 => ChaCha20Poly1305::AEAD_ID,
+            AeadAlg::ExportOnly => ExportOnly::AEAD_ID,
+        }
+    }
+}
+
+/// The supported KDF algorithms
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum KdfAlg {
+    /// HKDF-SHA256
+    HkdfSha256,
+    /// HKDF-SHA384
+    HkdfSha384,
+    /// HKDF-SHA512
+    HkdfSha512,
+}
+
+impl KdfAlg {
+    /// Every supported KDF algorithm
+    pub const ALL: [KdfAlg; 3] = [KdfAlg::HkdfSha256, KdfAlg::HkdfSha384, KdfAlg::HkdfSha512];
+
+    /// Returns the name of this algorithm
+    pub fn name(&self) -> &'static str {
+        match self {
+            KdfAlg::HkdfSha256 => "HkdfSha256",
+            KdfAlg::HkdfSha384 => "HkdfSha384",
+            KdfAlg::HkdfSha512 => "HkdfSha512",
+        }
+    }
+
+    /// Looks up the algorithm with the given KDF ID
+    pub fn try_from_u16(id: u16) -> Result<KdfAlg, AgileHpkeError> {
+        KdfAlg::ALL
+            .iter()
+            .copied()
+            .find(|alg| alg.to_u16() == id)
+            .ok_or(AgileHpkeError::UnknownAlgIdent("KdfAlg", id))
+    }
+
+    /// Returns the KDF ID of this algorithm
+    pub fn to_u16(self) -> u16 {
+        match self {
+            KdfAlg::HkdfSha256 => HkdfSha256::KDF_ID,
+            KdfAlg::HkdfSha384 => HkdfSha384::KDF_ID,
+            KdfAlg::HkdfSha512 => HkdfSha512::KDF_ID,
+        }
+    }
+}
+
+/// The supported KEM algorithms
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum KemAlg {
+    /// DHKEM(X25519, HKDF-SHA256)
+    X25519HkdfSha256,
+    /// DHKEM(X448, HKDF-SHA512)
+    X448HkdfSha512,
+    /// DHKEM(P-256, HKDF-SHA256)
+    DhP256HkdfSha256,
+    /// DHKEM(P-384, HKDF-SHA384)
+    DhP384HkdfSha384,
+    /// DHKEM(P-521, HKDF-SHA512)
+    DhP521HkdfSha512,
+    /// ML-KEM-768. This has no auth modes.
+    MlKem768,
+    /// ML-KEM-1024. This has no auth modes.
+    MlKem1024,
+}
+
+impl KemAlg {
+    /// Every supported KEM algorithm
+    pub const ALL: [KemAlg; 7] = [
+        KemAlg::X25519HkdfSha256,
+        KemAlg::X448HkdfSha512,
+        KemAlg::DhP256HkdfSha256,
+        KemAlg::DhP384HkdfSha384,
+        KemAlg::DhP521HkdfSha512,
+        KemAlg::MlKem768,
+        KemAlg::MlKem1024,
+    ];
+
+    /// Returns the name of this algorithm
+    pub fn name(&self) -> &'static str {
+        match self {
+            KemAlg::X25519HkdfSha256 => "X25519HkdfSha256",
+            KemAlg::X448HkdfSha512 => "X448HkdfSha512",
+            KemAlg::DhP256HkdfSha256 => "DhP256HkdfSha256",
+            KemAlg::DhP384HkdfSha384 => "DhP384HkdfSha384",
+            KemAlg::DhP521HkdfSha512 => "DhP521HkdfSha512",
+            KemAlg::MlKem768 => "MlKem768",
+            KemAlg::MlKem1024 => "MlKem1024",
+        }
+    }
+
+    /// Returns whether this algorithm can be used in the auth modes. ML-KEM has no authenticated
+    /// encapsulation, so it can only be used in the base and PSK modes.
+    pub fn supports_auth(&self) -> bool {
+        !matches!(self, KemAlg::MlKem768 | KemAlg::MlKem1024)
+    }
+
+    /// Looks up the algorithm with the given KEM ID
+    pub fn try_from_u16(id: u16) -> Result<KemAlg, AgileHpkeError> {
+        KemAlg::ALL
+            .iter()
+            .copied()
+            .find(|alg| alg.to_u16() == id)
+            .ok_or(AgileHpkeError::UnknownAlgIdent("KemAlg", id))
+    }
+
+    /// Returns the KEM ID of this algorithm
+    pub fn to_u16(self) -> u16 {
+        match self {
+            KemAlg::X25519HkdfSha256 => X25519HkdfSha256::KEM_ID,
+            KemAlg::X448HkdfSha512 => X448HkdfSha512::KEM_ID,
+            KemAlg::DhP256HkdfSha256 => DhP256HkdfSha256::KEM_ID,
+            KemAlg::DhP384HkdfSha384 => DhP384HkdfSha384::KEM_ID,
+            KemAlg::DhP521HkdfSha512 => DhP521HkdfSha512::KEM_ID,
+            KemAlg::MlKem768 => MlKem768::KEM_ID,
+            KemAlg::MlKem1024 => MlKem1024::KEM_ID,
+        }
+    }
+}
+
+// Runs $body with $Kem set to the KEM type that $kem_alg refers to
+macro_rules! with_kem {
+    ($kem_alg:expr, |$Kem:ident| $body:block) => {
+        match $kem_alg {
+            KemAlg::X25519HkdfSha256 => {
+                type $Kem = X25519HkdfSha256;
+                $body
+            }
+            KemAlg::X448HkdfSha512 => {
+                type $Kem = X448HkdfSha512;
+                $body
+            }
+            KemAlg::DhP256HkdfSha256 => {
+                type $Kem = DhP256HkdfSha256;
+                $body
+            }
+            KemAlg::DhP384HkdfSha384 => {
+                type $Kem = DhP384HkdfSha384;
+                $body
+            }
+            KemAlg::DhP521HkdfSha512 => {
+                type $Kem = DhP521HkdfSha512;
+                $body
+            }
+            KemAlg::MlKem768 => {
+                type $Kem = MlKem768;
+                $body
+            }
+            KemAlg::MlKem1024 => {
+                type $Kem = MlKem1024;
+                $body
+            }
+        }
+    };
+}
+
+// Runs $body with $A, $Kdf, and $Kem set to the types that the given algorithms refer to. This
+// expands to a branch for every one of the 4·3·7 = 84 combinations.
+macro_rules! with_suite {
+    ($aead_alg:expr, $kdf_alg:expr, $kem_alg:expr, |$A:ident, $Kdf:ident, $Kem:ident| $body:block) => {
+        match $aead_alg {
+            AeadAlg::AesGcm128 => {
+                type $A = AesGcm128;
+                with_suite!(@kdf $kdf_alg, $kem_alg, |$Kdf, $Kem| $body)
+            }
+            AeadAlg::AesGcm256 => {
+                type $A = AesGcm256;
+                with_suite!(@kdf $kdf_alg, $kem_alg, |$Kdf, $Kem| $body)
+            }
+            AeadAlg::ChaCha20Poly1305 => {
+                type $A = ChaCha20Poly1305;
+                with_suite!(@kdf $kdf_alg, $kem_alg, |$Kdf, $Kem| $body)
+            }
+            AeadAlg::ExportOnly => {
+                type $A = ExportOnly;
+                with_suite!(@kdf $kdf_alg, $kem_alg, |$Kdf, $Kem| $body)
+            }
+        }
+    };
+    (@kdf $kdf_alg:expr, $kem_alg:expr, |$Kdf:ident, $Kem:ident| $body:block) => {
+        match $kdf_alg {
+            KdfAlg::HkdfSha256 => {
+                type $Kdf = HkdfSha256;
+                with_kem!($kem_alg, |$Kem| $body)
+            }
+            KdfAlg::HkdfSha384 => {
+                type $Kdf = HkdfSha384;
+                with_kem!($kem_alg, |$Kem| $body)
+            }
+            KdfAlg::HkdfSha512 => {
+                type $Kdf = HkdfSha512;
+                with_kem!($kem_alg, |$Kem| $body)
+            }
+        }
+    };
+}
+
+// Returns an AlgMismatch if the KEM of some input isn't the KEM that was asked for
+fn check_kem_alg(
+    kem_alg: KemAlg,
+    given_alg: KemAlg,
+    location: &'static str,
+) -> Result<(), AgileHpkeError> {
+    if kem_alg != given_alg {
+        Err(AgileHpkeError::AlgMismatch(
+            (kem_alg.name(), "kem_alg"),
+            (given_alg.name(), location),
+        ))
+    } else {
+        Ok(())
+    }
+}
+
+/// A public key, tagged with the KEM it belongs to
+#[derive(Clone)]
+pub struct AgilePublicKey {
+    /// The KEM this key belongs to
+    pub kem_alg: KemAlg,
+    /// The marshalled public key
+    pub pubkey_bytes: Vec<u8>,
+}
+
+impl fmt::Debug for AgilePublicKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("AgilePublicKey")
+            .field("kem_alg", &self.kem_alg)
+            .field("pubkey_bytes", &HexFmt(&self.pubkey_bytes))
+            .finish()
+    }
+}
+
+impl AgilePublicKey {
+    fn try_lift<Pk: Unmarshallable>(&self) -> Result<Pk, AgileHpkeError> {
+        Pk::unmarshal(&self.pubkey_bytes).map_err(|e| e.into())
+    }
+}
+
+/// A private key, tagged with the KEM it belongs to. The key bytes are wiped when this is dropped.
+#[derive(Clone, Zeroize, ZeroizeOnDrop)]
+pub struct AgilePrivateKey {
+    /// The KEM this key belongs to
+    #[zeroize(skip)]
+    pub kem_alg: KemAlg,
+    /// The marshalled private key
+    pub privkey_bytes: Vec<u8>,
+}
+
+impl fmt::Debug for AgilePrivateKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("AgilePrivateKey")
+            .field("kem_alg", &self.kem_alg)
+            .field("privkey_bytes", &Redacted)
+            .finish()
+    }
+}
+
+impl AgilePrivateKey {
+    fn try_lift<Sk: Unmarshallable>(&self) -> Result<Sk, AgileHpkeError> {
+        Sk::unmarshal(&self.privkey_bytes).map_err(|e| e.into())
+    }
+}
+
+/// An encapsulated key, tagged with the KEM it belongs to
+#[derive(Clone)]
+pub struct AgileEncappedKey {
+    /// The KEM this key belongs to
+    pub kem_alg: KemAlg,
+    /// The marshalled encapsulated key
+    pub encapped_key_bytes: Vec<u8>,
+}
+
+impl fmt::Debug for AgileEncappedKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("AgileEncappedKey")
+            .field("kem_alg", &self.kem_alg)
+            .field("encapped_key_bytes", &HexFmt(&self.encapped_key_bytes))
+            .finish()
+    }
+}
+
+impl AgileEncappedKey {
+    fn try_lift<Kem: KemTrait>(&self) -> Result<Kem::EncappedKey, AgileHpkeError> {
+        Kem::EncappedKey::unmarshal(&self.encapped_key_bytes).map_err(|e| e.into())
+    }
+}
+
+/// A private key and its corresponding public key
+#[derive(Clone, Debug)]
+pub struct AgileKeypair(pub AgilePrivateKey, pub AgilePublicKey);
+
+impl AgileKeypair {
+    fn try_lift<Sk: Unmarshallable, Pk: Unmarshallable>(&self) -> Result<(Sk, Pk), AgileHpkeError> {
+        Ok((self.0.try_lift::<Sk>()?, self.1.try_lift::<Pk>()?))
+    }
+
+    /// Checks that both halves of this keypair belong to `kem_alg`
+    fn check_kem_alg(&self, kem_alg: KemAlg, location: &'static str) -> Result<(), AgileHpkeError> {
+        check_kem_alg(kem_alg, self.0.kem_alg, location)?;
+        check_kem_alg(kem_alg, self.1.kem_alg, location)
+    }
+}
+
+/// Contains preshared key bytes and an identifier. The key bytes are wiped when this is dropped.
+#[derive(Clone, Zeroize, ZeroizeOnDrop)]
+pub struct AgilePskBundle {
+    /// The preshared key
+    pub psk_bytes: Vec<u8>,
+    /// An bytestring that uniquely identifies this PSK
+    #[zeroize(skip)]
+    pub psk_id: Vec<u8>,
+}
+
+impl fmt::Debug for AgilePskBundle {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("AgilePskBundle")
+            .field("psk_bytes", &Redacted)
+            .field("psk_id", &HexFmt(&self.psk_id))
+            .finish()
+    }
+}
+
+impl AgilePskBundle {
+    fn lift<Kdf: KdfTrait>(&self) -> PskBundle<Kdf> {
+        PskBundle {
+            psk: Psk::<Kdf>::from_bytes(self.psk_bytes.clone()),
+            psk_id: self.psk_id.clone(),
+        }
+    }
+}
+
+// Returns an error if the given KEM can't be used in the auth modes
+fn check_auth_support(kem_alg: KemAlg) -> Result<(), AgileHpkeError> {
+    if kem_alg.supports_auth() {
+        Ok(())
+    } else {
+        Err(AgileHpkeError::UnsupportedMode(kem_alg.name()))
+    }
+}
+
+/// The agile equivalent of `OpModeR`
+#[derive(Clone, Debug)]
+pub enum AgileOpModeR {
+    /// No extra information included
+    Base,
+    /// A preshared key known to the sender and receiver
+    Psk(AgilePskBundle),
+    /// The identity public key of the sender
+    Auth(AgilePublicKey),
+    /// Both of the above
+    AuthPsk(AgilePublicKey, AgilePskBundle),
+}
+
+impl AgileOpModeR {
+    fn check_kem_alg(&self, kem_alg: KemAlg) -> Result<(), AgileHpkeError> {
+        match self {
+            AgileOpModeR::Auth(pk) | AgileOpModeR::AuthPsk(pk, _) => {
+                check_auth_support(kem_alg)?;
+                check_kem_alg(kem_alg, pk.kem_alg, "mode::pk_sender_id")
+            }
+            _ => Ok(()),
+        }
+    }
+
+    fn try_lift<Kem: KemTrait, Kdf: KdfTrait>(&self) -> Result<OpModeR<Kem, Kdf>, AgileHpkeError> {
+        let res = match self {
+            AgileOpModeR::Base => OpModeR::Base,
+            AgileOpModeR::Psk(bundle) => OpModeR::Psk(bundle.lift::<Kdf>()),
+            AgileOpModeR::Auth(pk) => OpModeR::Auth(pk.try_lift::<Kem::AuthPublicKey>()?),
+            AgileOpModeR::AuthPsk(pk, bundle) => {
+                OpModeR::AuthPsk(pk.try_lift::<Kem::AuthPublicKey>()?, bundle.lift::<Kdf>())
+            }
+        };
+
+        Ok(res)
+    }
+}
+
+/// The agile equivalent of `OpModeS`
+#[derive(Clone, Debug)]
+pub enum AgileOpModeS {
+    /// No extra information included
+    Base,
+    /// A preshared key known to the sender and receiver
+    Psk(AgilePskBundle),
+    /// The identity keypair of the sender
+    Auth(AgileKeypair),
+    /// Both of the above
+    AuthPsk(AgileKeypair, AgilePskBundle),
+}
+
+impl AgileOpModeS {
+    fn check_kem_alg(&self, kem_alg: KemAlg) -> Result<(), AgileHpkeError> {
+        match self {
+            AgileOpModeS::Auth(keypair) | AgileOpModeS::AuthPsk(keypair, _) => {
+                check_auth_support(kem_alg)?;
+                keypair.check_kem_alg(kem_alg, "mode::sender_id_keypair")
+            }
+            _ => Ok(()),
+        }
+    }
+
+    fn try_lift<Kem: KemTrait, Kdf: KdfTrait>(&self) -> Result<OpModeS<Kem, Kdf>, AgileHpkeError> {
+        let res = match self {
+            AgileOpModeS::Base => OpModeS::Base,
+            AgileOpModeS::Psk(bundle) => OpModeS::Psk(bundle.lift::<Kdf>()),
+            AgileOpModeS::Auth(keypair) => {
+                OpModeS::Auth(keypair.try_lift::<Kem::AuthPrivateKey, Kem::AuthPublicKey>()?)
+            }
+            AgileOpModeS::AuthPsk(keypair, bundle) => OpModeS::AuthPsk(
+                keypair.try_lift::<Kem::AuthPrivateKey, Kem::AuthPublicKey>()?,
+                bundle.lift::<Kdf>(),
+            ),
+        };
+
+        Ok(res)
+    }
+}
+
+/// A serialized AEAD tag
+pub type AgileAeadTag = Vec<u8>;
+
+/// A sender's encryption context whose ciphersuite was picked at runtime. See `AeadCtxS` for the
+/// meaning of each method.
+pub trait AgileAeadCtxS {
+    /// Does an in-place seal, and returns the marshalled tag. If the AEAD is
+    /// `AeadAlg::ExportOnly`, returns `Err(AgileHpkeError::ExportOnly)`.
+    fn seal(&mut self, plaintext: &mut [u8], aad: &[u8]) -> Result<AgileAeadTag, AgileHpkeError>;
+
+    /// Fills `out_buf` with the secret derived from `exporter_ctx`
+    fn export(&self, exporter_ctx: &[u8], out_buf: &mut [u8]) -> Result<(), AgileHpkeError>;
+}
+
+/// A receiver's encryption context whose ciphersuite was picked at runtime. See `AeadCtxR` for
+/// the meaning of each method.
+pub trait AgileAeadCtxR {
+    /// Does an in-place open, using the given marshalled tag. If the AEAD is
+    /// `AeadAlg::ExportOnly`, returns `Err(AgileHpkeError::ExportOnly)`.
+    fn open(
+        &mut self,
+        ciphertext: &mut [u8],
+        aad: &[u8],
+        tag_bytes: &[u8],
+    ) -> Result<(), AgileHpkeError>;
+
+    /// Fills `out_buf` with the secret derived from `exporter_ctx`
+    fn export(&self, exporter_ctx: &[u8], out_buf: &mut [u8]) -> Result<(), AgileHpkeError>;
+}
+
+impl<A: SealingAead, Kdf: KdfTrait> AgileAeadCtxS for AeadCtxS<A, Kdf> {
+    fn seal(&mut self, plaintext: &mut [u8], aad: &[u8]) -> Result<AgileAeadTag, AgileHpkeError> {
+        let tag = self.seal(plaintext, aad)?;
+        Ok(tag.marshal().to_vec())
+    }
+
+    fn export(&self, exporter_ctx: &[u8], out_buf: &mut [u8]) -> Result<(), AgileHpkeError> {
+        self.export(exporter_ctx, out_buf).map_err(|e| e.into())
+    }
+}
+
+impl<A: SealingAead, Kdf: KdfTrait> AgileAeadCtxR for AeadCtxR<A, Kdf> {
+    fn open(
+        &mut self,
+        ciphertext: &mut [u8],
+        aad: &[u8],
+        tag_bytes: &[u8],
+    ) -> Result<(), AgileHpkeError> {
+        let tag = AeadTag::<A>::unmarshal(tag_bytes)?;
+        self.open(ciphertext, aad, &tag).map_err(|e| e.into())
+    }
+
+    fn export(&self, exporter_ctx: &[u8], out_buf: &mut [u8]) -> Result<(), AgileHpkeError> {
+        self.export(exporter_ctx, out_buf).map_err(|e| e.into())
+    }
+}
+
+// Export-only contexts have no seal or open, so they always fail
+impl<Kdf: KdfTrait> AgileAeadCtxS for AeadCtxS<ExportOnly, Kdf> {
+    fn seal(&mut self, _: &mut [u8], _: &[u8]) -> Result<AgileAeadTag, AgileHpkeError> {
+        Err(AgileHpkeError::ExportOnly)
+    }
+
+    fn export(&self, exporter_ctx: &[u8], out_buf: &mut [u8]) -> Result<(), AgileHpkeError> {
+        self.export(exporter_ctx, out_buf).map_err(|e| e.into())
+    }
+}
+
+impl<Kdf: KdfTrait> AgileAeadCtxR for AeadCtxR<ExportOnly, Kdf> {
+    fn open(&mut self, _: &mut [u8], _: &[u8], _: &[u8]) -> Result<(), AgileHpkeError> {
+        Err(AgileHpkeError::ExportOnly)
+    }
+
+    fn export(&self, exporter_ctx: &[u8], out_buf: &mut [u8]) -> Result<(), AgileHpkeError> {
+        self.export(exporter_ctx, out_buf).map_err(|e| e.into())
+    }
+}
+
+/// Generates a random keypair for the given KEM
+pub fn agile_gen_keypair<R: CryptoRng + RngCore>(kem_alg: KemAlg, csprng: &mut R) -> AgileKeypair {
+    with_kem!(kem_alg, |Kem| {
+        let (sk, pk) = <Kem as KemTrait>::gen_keypair(csprng);
+        let sk = AgilePrivateKey {
+            kem_alg,
+            privkey_bytes: sk.marshal().to_vec(),
+        };
+        let pk = AgilePublicKey {
+            kem_alg,
+            pubkey_bytes: pk.marshal().to_vec(),
+        };
+
+        AgileKeypair(sk, pk)
+    })
+}
+
+/// The agile equivalent of `setup::setup_sender`. The ciphersuite is given by `aead_alg`,
+/// `kdf_alg`, and `kem_alg`.
+///
+/// Return Value
+/// ============
+/// On success, returns an encapsulated key and a boxed sender context. If `mode` or `pk_recip`
+/// belong to a KEM other than `kem_alg`, returns `Err(AgileHpkeError::AlgMismatch)`. If `mode` is
+/// an auth mode and `kem_alg` has no auth modes, returns `Err(AgileHpkeError::UnsupportedMode)`.
+/// Any error from unmarshalling or `setup_sender` is wrapped in `AgileHpkeError::HpkeError`.
+pub fn setup_sender<R: CryptoRng + RngCore>(
+    aead_alg: AeadAlg,
+    kdf_alg: KdfAlg,
+    kem_alg: KemAlg,
+    mode: &AgileOpModeS,
+    pk_recip: &AgilePublicKey,
+    info: &[u8],
+    csprng: &mut R,
+) -> Result<(AgileEncappedKey, Box<dyn AgileAeadCtxS>), AgileHpkeError> {
+    // Make sure everything agrees on the KEM before unmarshalling anything
+    mode.check_kem_alg(kem_alg)?;
+    check_kem_alg(kem_alg, pk_recip.kem_alg, "pk_recip")?;
+
+    with_suite!(aead_alg, kdf_alg, kem_alg, |A, Kdf, Kem| {
+        let mode = mode.try_lift::<Kem, Kdf>()?;
+        let pk_recip = pk_recip.try_lift::<<Kem as KemTrait>::PublicKey>()?;
+
+        let (encapped_key, aead_ctx) =
+            crate::setup::setup_sender::<A, Kdf, Kem, R>(&mode, &pk_recip, info, csprng)?;
+        let encapped_key = AgileEncappedKey {
+            kem_alg,
+            encapped_key_bytes: encapped_key.marshal().to_vec(),
+        };
+
+        Ok((encapped_key, Box::new(aead_ctx)))
+    })
+}
+
+/// The agile equivalent of `setup::setup_receiver`. The ciphersuite is given by `aead_alg`,
+/// `kdf_alg`, and `kem_alg`.
+///
+/// Return Value
+/// ============
+/// On success, returns a boxed receiver context. If `mode`, `sk_recip`, or `encapped_key` belong
+/// to a KEM other than `kem_alg`, returns `Err(AgileHpkeError::AlgMismatch)`. If `mode` is an auth
+/// mode and `kem_alg` has no auth modes, returns `Err(AgileHpkeError::UnsupportedMode)`. Any error
+/// from unmarshalling or `setup_receiver` is wrapped in `AgileHpkeError::HpkeError`.
+pub fn setup_receiver(
+    aead_alg: AeadAlg,
+    kdf_alg: KdfAlg,
+    kem_alg: KemAlg,
+    mode: &AgileOpModeR,
+    sk_recip: &AgilePrivateKey,
+    encapped_key: &AgileEncappedKey,
+    info: &[u8],
+) -> Result<Box<dyn AgileAeadCtxR>, AgileHpkeError> {
+    // Make sure everything agrees on the KEM before unmarshalling anything
+    mode.check_kem_alg(kem_alg)?;
+    check_kem_alg(kem_alg, sk_recip.kem_alg, "sk_recip")?;
+    check_kem_alg(kem_alg, encapped_key.kem_alg, "encapped_key")?;
+
+    with_suite!(aead_alg, kdf_alg, kem_alg, |A, Kdf, Kem| {
+        let mode = mode.try_lift::<Kem, Kdf>()?;
+        let sk_recip = sk_recip.try_lift::<<Kem as KemTrait>::PrivateKey>()?;
+        let encapped_key = encapped_key.try_lift::<Kem>()?;
+
+        let aead_ctx =
+            crate::setup::setup_receiver::<A, Kdf, Kem>(&mode, &sk_recip, &encapped_key, info)?;
+        Ok(Box::new(aead_ctx))
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    /// Makes a pair of agreeing AuthPsk modes, since that's the most complicated mode. ML-KEM has
+    /// no auth modes, so it gets Psk modes instead.
+    fn gen_agile_op_mode_pair<R: CryptoRng + RngCore>(
+        kem_alg: KemAlg,
+        csprng: &mut R,
+    ) -> (AgileOpModeS, AgileOpModeR) {
+        let mut psk_bytes = vec![0u8; 32];
+        csprng.fill_bytes(&mut psk_bytes);
+        let psk_bundle = AgilePskBundle {
+            psk_bytes,
+            psk_id: b"preshared key attempt #5, take 2".to_vec(),
+        };
+
+        if let KemAlg::MlKem768 | KemAlg::MlKem1024 = kem_alg {
+            return (
+                AgileOpModeS::Psk(psk_bundle.clone()),
+                AgileOpModeR::Psk(psk_bundle),
+            );
+        }
+
+        let sender_keypair = agile_gen_keypair(kem_alg, csprng);
+        let pk_sender_id = sender_keypair.1.clone();
+        (
+            AgileOpModeS::AuthPsk(sender_keypair, psk_bundle.clone()),
+            AgileOpModeR::AuthPsk(pk_sender_id, psk_bundle),
+        )
+    }
+
+    /// Tests that algorithm IDs round-trip, and that unknown IDs are rejected
+    #[test]
+    fn test_alg_ids() {
+        for &alg in AeadAlg::ALL.iter() {
+            assert_eq!(AeadAlg::try_from_u16(alg.to_u16()).unwrap(), alg);
+        }
+        for &alg in KdfAlg::ALL.iter() {
+            assert_eq!(KdfAlg::try_from_u16(alg.to_u16()).unwrap(), alg);
+        }
+        for &alg in KemAlg::ALL.iter() {
+            assert_eq!(KemAlg::try_from_u16(alg.to_u16()).unwrap(), alg);
+        }
+
+        // 0x0000 is reserved
+        match AeadAlg::try_from_u16(0x0000) {
+            Err(AgileHpkeError::UnknownAlgIdent("AeadAlg", 0x0000)) => {} // Good
+            Err(e) => panic!("try_from_u16() should have failed. Instead got {}", e),
+            _ => panic!("try_from_u16() should have failed. Instead it succeeded"),
+        }
+        match KemAlg::try_from_u16(0x0000) {
+            Err(AgileHpkeError::UnknownAlgIdent("KemAlg", 0x0000)) => {} // Good
+            Err(e) => panic!("try_from_u16() should have failed. Instead got {}", e),
+            _ => panic!("try_from_u16() should have failed. Instead it succeeded"),
+        }
+    }
+
+    /// Tests an encryption-decryption round trip, and agreement of exported secrets, for every
+    /// supported ciphersuite
+    #[test]
+    fn test_agile_correctness() {
+        let mut csprng = rand::thread_rng();
+
+        for &aead_alg in AeadAlg::ALL.iter() {
+            for &kdf_alg in KdfAlg::ALL.iter() {
+                for &kem_alg in KemAlg::ALL.iter() {
+                    let info = b"we're gonna agile him in his clavicle";
+
+                    let (sender_mode, receiver_mode) = gen_agile_op_mode_pair(kem_alg, &mut csprng);
+                    let recip_keypair = agile_gen_keypair(kem_alg, &mut csprng);
+
+                    let (encapped_key, mut sender_ctx) = setup_sender(
+                        aead_alg,
+                        kdf_alg,
+                        kem_alg,
+                        &sender_mode,
+                        &recip_keypair.1,
+                        info,
+                        &mut csprng,
+                    )
+                    .unwrap();
+                    let mut receiver_ctx = setup_receiver(
+                        aead_alg,
+                        kdf_alg,
+                        kem_alg,
+                        &receiver_mode,
+                        &recip_keypair.0,
+                        &encapped_key,
+                        info,
+                    )
+                    .unwrap();
+
+                    // Test an encryption-decryption round trip. Export-only contexts refuse.
+                    let msg = b"paper boy paper boy";
+                    let aad = b"all about that paper, boy";
+                    let mut plaintext = *msg;
+                    if aead_alg == AeadAlg::ExportOnly {
+                        assert!(matches!(
+                            sender_ctx.seal(&mut plaintext, aad),
+                            Err(AgileHpkeError::ExportOnly)
+                        ));
+                        assert!(matches!(
+                            receiver_ctx.open(&mut plaintext, aad, &[]),
+                            Err(AgileHpkeError::ExportOnly)
+                        ));
+                    } else {
+                        let tag = sender_ctx.seal(&mut plaintext, aad).unwrap();
+                        let mut ciphertext = plaintext;
+                        receiver_ctx.open(&mut ciphertext, aad, &tag).unwrap();
+                        assert_eq!(&ciphertext, msg);
+                    }
+
+                    // Both sides should export the same secret
+                    let mut sender_secret = [0u8; 32];
+                    let mut receiver_secret = [0u8; 32];
+                    sender_ctx.export(b"ctx", &mut sender_secret).unwrap();
+                    receiver_ctx.export(b"ctx", &mut receiver_secret).unwrap();
+                    assert_eq!(sender_secret, receiver_secret);
+                }
+            }
+        }
+    }
+
+    /// Tests that keys from one KEM are rejected when setting up a context for another KEM
+    #[test]
+    fn test_agile_alg_mismatch() {
+        let mut csprng = rand::thread_rng();
+
+        let (sender_mode, _) = gen_agile_op_mode_pair(KemAlg::X25519HkdfSha256, &mut csprng);
+        let recip_keypair = agile_gen_keypair(KemAlg::DhP256HkdfSha256, &mut csprng);
+
+        let res = setup_sender(
+            AeadAlg::ChaCha20Poly1305,
+            KdfAlg::HkdfSha256,
+            KemAlg::X25519HkdfSha256,
+            &sender_mode,
+            &recip_keypair.1,
+            b"",
+            &mut csprng,
+        );
+        match res {
+            Err(AgileHpkeError::AlgMismatch(_, (_, "pk_recip"))) => {} // Good
+            Err(e) => panic!("setup_sender() should have failed. Instead got {}", e),
+            _ => panic!("setup_sender() should have failed. Instead it succeeded"),
+        }
+    }
+
+    /// Tests that the auth modes are rejected for ML-KEM, on both sides
+    #[test]
+    fn test_agile_ml_kem_no_auth() {
+        let mut csprng = rand::thread_rng();
+
+        for &kem_alg in &[KemAlg::MlKem768, KemAlg::MlKem1024] {
+            assert!(!kem_alg.supports_auth());
+
+            let sender_keypair = agile_gen_keypair(kem_alg, &mut csprng);
+            let recip_keypair = agile_gen_keypair(kem_alg, &mut csprng);
+            let psk_bundle = AgilePskBundle {
+                psk_bytes: vec![0u8; 32],
+                psk_id: b"preshared key attempt #5, take 2. action".to_vec(),
+            };
+
+            let sender_modes = [
+                AgileOpModeS::Auth(sender_keypair.clone()),
+                AgileOpModeS::AuthPsk(sender_keypair.clone(), psk_bundle.clone()),
+            ];
+            for mode in sender_modes.iter() {
+                let res = setup_sender(
+                    AeadAlg::ChaCha20Poly1305,
+                    KdfAlg::HkdfSha256,
+                    kem_alg,
+                    mode,
+                    &recip_keypair.1,
+                    b"",
+                    &mut csprng,
+                );
+                match res {
+                    Err(AgileHpkeError::UnsupportedMode(name)) => assert_eq!(name, kem_alg.name()),
+                    Err(e) => panic!("setup_sender() should have failed. Instead got {}", e),
+                    _ => panic!("setup_sender() should have failed. Instead it succeeded"),
+                }
+            }
+
+            // The encapped key is never looked at, so any will do
+            let (encapped_key, _) = setup_sender(
+                AeadAlg::ChaCha20Poly1305,
+                KdfAlg::HkdfSha256,
+                kem_alg,
+                &AgileOpModeS::Base,
+                &recip_keypair.1,
+                b"",
+                &mut csprng,
+            )
+            .unwrap();
+            let receiver_modes = [
+                AgileOpModeR::Auth(sender_keypair.1.clone()),
+                AgileOpModeR::AuthPsk(sender_keypair.1.clone(), psk_bundle),
+            ];
+            for mode in receiver_modes.iter() {
+                let res = setup_receiver(
+                    AeadAlg::ChaCha20Poly1305,
+                    KdfAlg::HkdfSha256,
+                    kem_alg,
+                    mode,
+                    &recip_keypair.0,
+                    &encapped_key,
+                    b"",
+                );
+                match res {
+                    Err(AgileHpkeError::UnsupportedMode(name)) => assert_eq!(name, kem_alg.name()),
+                    Err(e) => panic!("setup_receiver() should have failed. Instead got {}", e),
+                    _ => panic!("setup_receiver() should have failed. Instead it succeeded"),
+                }
+            }
+        }
+    }
+}
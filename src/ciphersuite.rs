@@ -0,0 +1,241 @@
+//! Ciphersuites, i.e., vetted combinations of a KEM, a KDF, and an AEAD. The functions in this
+//! module do the same thing as their counterparts in `setup` and `single_shot`, but they take a
+//! single `Ciphersuite` type parameter instead of three independent ones.
+
+use crate::{
+    aead::{
+        Aead, AeadCtxR, AeadCtxS, AeadTag, AesGcm128, AesGcm256, ChaCha20Poly1305, SealingAead,
+    },
+    kdf::{HkdfSha256, HkdfSha384, HkdfSha512, Kdf as KdfTrait},
+    kem::{
+        DhP256HkdfSha256, DhP384HkdfSha384, DhP521HkdfSha512, Kem as KemTrait, X25519HkdfSha256,
+        X448HkdfSha512,
+    },
+    op_mode::{OpModeR, OpModeS},
+    util::full_suite_id,
+    HpkeError,
+};
+
+use rand::{CryptoRng, RngCore};
+
+/// Represents a combination of a KEM, a KDF, and an AEAD
+pub trait Ciphersuite {
+    /// The KEM of this ciphersuite
+    type Kem: KemTrait;
+    /// The KDF of this ciphersuite
+    type Kdf: KdfTrait;
+    /// The AEAD of this ciphersuite
+    type Aead: Aead;
+}
+
+/// Returns the suite ID of the given ciphersuite, as defined in RFC 9180 §5.1. This is
+/// `"HPKE" || kem_id || kdf_id || aead_id`, and is always the one used by the key schedule.
+pub fn suite_id<S: Ciphersuite>() -> [u8; 10] {
+    full_suite_id::<S::Aead, S::Kdf, S::Kem>()
+}
+
+/// The sender's encryption context of a ciphersuite
+pub type SuiteAeadCtxS<S> = AeadCtxS<<S as Ciphersuite>::Aead, <S as Ciphersuite>::Kdf>;
+
+/// The receiver's encryption context of a ciphersuite
+pub type SuiteAeadCtxR<S> = AeadCtxR<<S as Ciphersuite>::Aead, <S as Ciphersuite>::Kdf>;
+
+// Defines a unit struct implementing Ciphersuite with the given KEM, KDF, and AEAD
+macro_rules! ciphersuite {
+    ($name:ident, $kem:ty, $kdf:ty, $aead:ty, $doc:expr) => {
+        #[doc = $doc]
+        pub struct $name {}
+
+        impl Ciphersuite for $name {
+            type Kem = $kem;
+            type Kdf = $kdf;
+            type Aead = $aead;
+        }
+    };
+}
+
+ciphersuite!(
+    X25519Sha256AesGcm128,
+    X25519HkdfSha256,
+    HkdfSha256,
+    AesGcm128,
+    "DHKEM(X25519, HKDF-SHA256), HKDF-SHA256, AES-GCM-128"
+);
+ciphersuite!(
+    X25519Sha256ChaCha20Poly1305,
+    X25519HkdfSha256,
+    HkdfSha256,
+    ChaCha20Poly1305,
+    "DHKEM(X25519, HKDF-SHA256), HKDF-SHA256, ChaCha20-Poly1305"
+);
+ciphersuite!(
+    X448Sha512AesGcm256,
+    X448HkdfSha512,
+    HkdfSha512,
+    AesGcm256,
+    "DHKEM(X448, HKDF-SHA512), HKDF-SHA512, AES-GCM-256"
+);
+ciphersuite!(
+    X448Sha512ChaCha20Poly1305,
+    X448HkdfSha512,
+    HkdfSha512,
+    ChaCha20Poly1305,
+    "DHKEM(X448, HKDF-SHA512), HKDF-SHA512, ChaCha20-Poly1305"
+);
+ciphersuite!(
+    P256Sha256AesGcm128,
+    DhP256HkdfSha256,
+    HkdfSha256,
+    AesGcm128,
+    "DHKEM(P-256, HKDF-SHA256), HKDF-SHA256, AES-GCM-128"
+);
+ciphersuite!(
+    P384Sha384AesGcm256,
+    DhP384HkdfSha384,
+    HkdfSha384,
+    AesGcm256,
+    "DHKEM(P-384, HKDF-SHA384), HKDF-SHA384, AES-GCM-256"
+);
+ciphersuite!(
+    P521Sha512AesGcm256,
+    DhP521HkdfSha512,
+    HkdfSha512,
+    AesGcm256,
+    "DHKEM(P-521, HKDF-SHA512), HKDF-SHA512, AES-GCM-256"
+);
+
+/// Does `setup::setup_sender` with the algorithms of the given ciphersuite
+pub fn setup_sender<S, R>(
+    mode: &OpModeS<S::Kem, S::Kdf>,
+    pk_recip: &<S::Kem as KemTrait>::PublicKey,
+    info: &[u8],
+    csprng: &mut R,
+) -> Result<(<S::Kem as KemTrait>::EncappedKey, SuiteAeadCtxS<S>), HpkeError>
+where
+    S: Ciphersuite,
+    R: CryptoRng + RngCore,
+{
+    crate::setup::setup_sender::<S::Aead, S::Kdf, S::Kem, R>(mode, pk_recip, info, csprng)
+}
+
+/// Does `setup::setup_receiver` with the algorithms of the given ciphersuite
+pub fn setup_receiver<S: Ciphersuite>(
+    mode: &OpModeR<S::Kem, S::Kdf>,
+    sk_recip: &<S::Kem as KemTrait>::PrivateKey,
+    encapped_key: &<S::Kem as KemTrait>::EncappedKey,
+    info: &[u8],
+) -> Result<SuiteAeadCtxR<S>, HpkeError> {
+    crate::setup::setup_receiver::<S::Aead, S::Kdf, S::Kem>(mode, sk_recip, encapped_key, info)
+}
+
+/// Does `single_shot::single_shot_seal` with the algorithms of the given ciphersuite
+pub fn single_shot_seal<S, R>(
+    mode: &OpModeS<S::Kem, S::Kdf>,
+    pk_recip: &<S::Kem as KemTrait>::PublicKey,
+    info: &[u8],
+    plaintext: &mut [u8],
+    aad: &[u8],
+    csprng: &mut R,
+) -> Result<(<S::Kem as KemTrait>::EncappedKey, AeadTag<S::Aead>), HpkeError>
+where
+    S: Ciphersuite,
+    S::Aead: SealingAead,
+    R: CryptoRng + RngCore,
+{
+    crate::single_shot::single_shot_seal::<S::Aead, S::Kdf, S::Kem, R>(
+        mode, pk_recip, info, plaintext, aad, csprng,
+    )
+}
+
+/// Does `single_shot::single_shot_open` with the algorithms of the given ciphersuite
+pub fn single_shot_open<S>(
+    mode: &OpModeR<S::Kem, S::Kdf>,
+    sk_recip: &<S::Kem as KemTrait>::PrivateKey,
+    encapped_key: &<S::Kem as KemTrait>::EncappedKey,
+    info: &[u8],
+    ciphertext: &mut [u8],
+    aad: &[u8],
+    tag: &AeadTag<S::Aead>,
+) -> Result<(), HpkeError>
+where
+    S: Ciphersuite,
+    S::Aead: SealingAead,
+{
+    crate::single_shot::single_shot_open::<S::Aead, S::Kdf, S::Kem>(
+        mode,
+        sk_recip,
+        encapped_key,
+        info,
+        ciphertext,
+        aad,
+        tag,
+    )
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::test_util::{aead_ctx_eq, gen_op_mode_pair, OpModeKind};
+
+    /// Tests that suite_id gives the suite IDs in RFC 9180
+    #[test]
+    fn test_suite_id() {
+        // RFC 9180 §A.1: DHKEM(X25519, HKDF-SHA256), HKDF-SHA256, AES-128-GCM
+        assert_eq!(
+            &suite_id::<X25519Sha256AesGcm128>(),
+            b"HPKE\x00\x20\x00\x01\x00\x01"
+        );
+        // RFC 9180 §A.6: DHKEM(P-521, HKDF-SHA512), HKDF-SHA512, AES-256-GCM
+        assert_eq!(
+            &suite_id::<P521Sha512AesGcm256>(),
+            b"HPKE\x00\x12\x00\x03\x00\x02"
+        );
+    }
+
+    /// Tests that the ciphersuite entry points agree with each other
+    #[test]
+    fn test_ciphersuite_correctness() {
+        type S = P256Sha256AesGcm128;
+        let mut csprng = rand::thread_rng();
+
+        let info = b"test_ciphersuite_correctness";
+        let (sk_recip, pk_recip) = <S as Ciphersuite>::Kem::gen_keypair(&mut csprng);
+        let (sender_mode, receiver_mode) = gen_op_mode_pair::<
+            <S as Ciphersuite>::Kem,
+            <S as Ciphersuite>::Kdf,
+        >(OpModeKind::AuthPsk);
+
+        // Contexts from setup_sender and setup_receiver should agree
+        let (encapped_key, mut sender_ctx) =
+            setup_sender::<S, _>(&sender_mode, &pk_recip, info, &mut csprng).unwrap();
+        let mut receiver_ctx =
+            setup_receiver::<S>(&receiver_mode, &sk_recip, &encapped_key, info).unwrap();
+        assert!(aead_ctx_eq(&mut sender_ctx, &mut receiver_ctx));
+
+        // Single-shot sealing and opening should round-trip
+        let msg = b"Outside, the wind was blowing";
+        let aad = b"and the rain was pouring";
+        let mut plaintext = *msg;
+        let (encapped_key, tag) = single_shot_seal::<S, _>(
+            &sender_mode,
+            &pk_recip,
+            info,
+            &mut plaintext,
+            aad,
+            &mut csprng,
+        )
+        .unwrap();
+        let mut ciphertext = plaintext;
+        single_shot_open::<S>(
+            &receiver_mode,
+            &sk_recip,
+            &encapped_key,
+            info,
+            &mut ciphertext,
+            aad,
+            &tag,
+        )
+        .unwrap();
+        assert_eq!(&ciphertext, msg);
+    }
+}
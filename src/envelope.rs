@@ -0,0 +1,309 @@
+//! A self-describing wire format for single-shot ciphertexts. An envelope carries everything a
+//! recipient needs to open a ciphertext, other than its keys, PSK, info string, and AAD:
+//!
+//! ```text
+//! version (1 byte) || mode (1 byte) || kem_id (2 bytes) || kdf_id (2 bytes) || aead_id (2 bytes)
+//!     || enc (Npk bytes) || ciphertext || tag (Nt bytes)
+//! ```
+//!
+//! All integers are big-endian.
+
+use crate::prelude::*;
+use crate::{
+    aead::{Aead, AeadTag, SealingAead},
+    kdf::Kdf as KdfTrait,
+    kem::Kem as KemTrait,
+    kex::{Marshallable, Unmarshallable},
+    op_mode::{OpMode, OpModeR, OpModeS},
+    single_shot::{single_shot_open_to_vec, single_shot_seal_to_vec},
+    HpkeError,
+};
+
+use core::marker::PhantomData;
+
+use byteorder::{BigEndian, ByteOrder};
+use rand::{CryptoRng, RngCore};
+
+/// The only envelope version so far
+const ENVELOPE_VERSION: u8 = 1;
+
+/// The length of version || mode || kem_id || kdf_id || aead_id
+const HEADER_LEN: usize = 8;
+
+/// A single-shot ciphertext, along with the ciphersuite and mode it was made with. The type
+/// parameters are the ciphersuite that the holder of this envelope expects. The ciphersuite that
+/// the sender declared is checked against these in `open_envelope`.
+pub struct Envelope<A: Aead, Kdf: KdfTrait, Kem: KemTrait> {
+    mode_id: u8,
+    kem_id: u16,
+    kdf_id: u16,
+    aead_id: u16,
+    encapped_key: Kem::EncappedKey,
+    ciphertext_and_tag: Vec<u8>,
+    marker: PhantomData<(A, Kdf)>,
+}
+
+impl<A: Aead, Kdf: KdfTrait, Kem: KemTrait> Envelope<A, Kdf, Kem> {
+    /// Returns the ID of the mode this envelope was sealed with. This is 0 for Base, 1 for Psk, 2
+    /// for Auth, and 3 for AuthPsk.
+    pub fn mode_id(&self) -> u8 {
+        self.mode_id
+    }
+
+    /// Returns the encapsulated key of this envelope
+    pub fn encapped_key(&self) -> &Kem::EncappedKey {
+        &self.encapped_key
+    }
+
+    /// Returns the `ciphertext || tag` of this envelope
+    pub fn ciphertext_and_tag(&self) -> &[u8] {
+        &self.ciphertext_and_tag
+    }
+
+    /// Serializes this envelope
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut header = [0u8; HEADER_LEN];
+        header[0] = ENVELOPE_VERSION;
+        header[1] = self.mode_id;
+        BigEndian::write_u16(&mut header[2..4], self.kem_id);
+        BigEndian::write_u16(&mut header[4..6], self.kdf_id);
+        BigEndian::write_u16(&mut header[6..8], self.aead_id);
+
+        [
+            &header[..],
+            self.encapped_key.marshal().as_slice(),
+            &self.ciphertext_and_tag,
+        ]
+        .concat()
+    }
+
+    /// Deserializes an envelope. The declared ciphersuite is not checked here. That happens in
+    /// `open_envelope`.
+    ///
+    /// Return Value
+    /// ============
+    /// Returns `Ok(envelope)` on success. If `bytes` is too short to hold a header, an encapsulated
+    /// key, and a tag, returns `Err(HpkeError::InvalidMarshalledLength)`. If the version or mode
+    /// is unknown, returns `Err(HpkeError::InvalidEnvelope)`. If the encapsulated key is invalid,
+    /// returns the error from unmarshalling it.
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, HpkeError> {
+        let enc_size = Kem::EncappedKey::size();
+        if bytes.len() < HEADER_LEN + enc_size + AeadTag::<A>::size() {
+            return Err(HpkeError::InvalidMarshalledLength);
+        }
+
+        let (header, rest) = bytes.split_at(HEADER_LEN);
+        let (enc_bytes, ciphertext_and_tag) = rest.split_at(enc_size);
+
+        // Modes are defined in RFC 9180 §5
+        let (version, mode_id) = (header[0], header[1]);
+        if version != ENVELOPE_VERSION || mode_id > 3 {
+            return Err(HpkeError::InvalidEnvelope);
+        }
+
+        Ok(Envelope {
+            mode_id,
+            kem_id: BigEndian::read_u16(&header[2..4]),
+            kdf_id: BigEndian::read_u16(&header[4..6]),
+            aead_id: BigEndian::read_u16(&header[6..8]),
+            encapped_key: Kem::EncappedKey::unmarshal(enc_bytes)?,
+            ciphertext_and_tag: ciphertext_and_tag.to_vec(),
+            marker: PhantomData,
+        })
+    }
+}
+
+/// Does a `single_shot_seal_to_vec` and wraps the result in an envelope. See
+/// `single_shot::single_shot_seal_to_vec` for more detail.
+///
+/// Return Value
+/// ============
+/// Returns `Ok(envelope)` on success. Errors are the same as in `single_shot_seal_to_vec`.
+pub fn seal_envelope<A, Kdf, Kem, R>(
+    mode: &OpModeS<Kem, Kdf>,
+    pk_recip: &Kem::PublicKey,
+    info: &[u8],
+    plaintext: &[u8],
+    aad: &[u8],
+    csprng: &mut R,
+) -> Result<Envelope<A, Kdf, Kem>, HpkeError>
+where
+    A: SealingAead,
+    Kdf: KdfTrait,
+    Kem: KemTrait,
+    R: CryptoRng + RngCore,
+{
+    let (encapped_key, ciphertext_and_tag) =
+        single_shot_seal_to_vec::<A, Kdf, Kem, R>(mode, pk_recip, info, plaintext, aad, csprng)?;
+
+    Ok(Envelope {
+        mode_id: mode.mode_id(),
+        kem_id: Kem::KEM_ID,
+        kdf_id: Kdf::KDF_ID,
+        aead_id: A::AEAD_ID,
+        encapped_key,
+        ciphertext_and_tag,
+        marker: PhantomData,
+    })
+}
+
+/// Checks that the envelope was sealed with the ciphersuite given in the type parameters, and then
+/// does a `single_shot_open_to_vec` on its contents. See `single_shot::single_shot_open_to_vec`
+/// for more detail.
+///
+/// Return Value
+/// ============
+/// Returns `Ok(plaintext)` on success. If the envelope declares a different ciphersuite than `A`,
+/// `Kdf`, and `Kem`, returns `Err(HpkeError::SuiteMismatch)`. If the envelope declares a different
+/// mode than `mode`, returns `Err(HpkeError::InvalidEnvelope)`. Otherwise, errors are the same as
+/// in `single_shot_open_to_vec`.
+pub fn open_envelope<A, Kdf, Kem>(
+    envelope: &Envelope<A, Kdf, Kem>,
+    mode: &OpModeR<Kem, Kdf>,
+    sk_recip: &Kem::PrivateKey,
+    info: &[u8],
+    aad: &[u8],
+) -> Result<Vec<u8>, HpkeError>
+where
+    A: SealingAead,
+    Kdf: KdfTrait,
+    Kem: KemTrait,
+{
+    let declared_suite = (envelope.kem_id, envelope.kdf_id, envelope.aead_id);
+    if declared_suite != (Kem::KEM_ID, Kdf::KDF_ID, A::AEAD_ID) {
+        return Err(HpkeError::SuiteMismatch);
+    }
+    if envelope.mode_id != mode.mode_id() {
+        return Err(HpkeError::InvalidEnvelope);
+    }
+
+    single_shot_open_to_vec::<A, Kdf, Kem>(
+        mode,
+        sk_recip,
+        &envelope.encapped_key,
+        info,
+        &envelope.ciphertext_and_tag,
+        aad,
+    )
+}
+
+#[cfg(test)]
+mod test {
+    use super::{open_envelope, seal_envelope, Envelope, HEADER_LEN};
+    use crate::{
+        aead::{AesGcm128, ChaCha20Poly1305},
+        kdf::HkdfSha256,
+        kem::{Kem as KemTrait, X25519HkdfSha256},
+        test_util::{gen_op_mode_pair, OpModeKind},
+        HpkeError,
+    };
+
+    type A = ChaCha20Poly1305;
+    type Kd = HkdfSha256;
+    type Ke = X25519HkdfSha256;
+
+    /// Tests that an envelope survives serialization and opens to the original plaintext
+    #[test]
+    fn test_envelope_correctness() {
+        let mut csprng = rand::thread_rng();
+
+        let msg = b"I've been working on the railroad";
+        let aad = b"all the live-long day";
+        let info = b"test_envelope_correctness";
+
+        let (sk_recip, pk_recip) = Ke::gen_keypair(&mut csprng);
+        let (sender_mode, receiver_mode) = gen_op_mode_pair::<Ke, Kd>(OpModeKind::Psk);
+
+        // Seal and serialize
+        let envelope =
+            seal_envelope::<A, Kd, Ke, _>(&sender_mode, &pk_recip, info, msg, aad, &mut csprng)
+                .unwrap();
+        let envelope_bytes = envelope.to_bytes();
+
+        // Deserialize and open
+        let new_envelope = Envelope::<A, Kd, Ke>::from_bytes(&envelope_bytes).unwrap();
+        assert_eq!(new_envelope.mode_id(), 1);
+        assert_eq!(new_envelope.to_bytes(), envelope_bytes);
+
+        let decrypted = open_envelope(&new_envelope, &receiver_mode, &sk_recip, info, aad).unwrap();
+        assert_eq!(&decrypted[..], &msg[..]);
+    }
+
+    /// Tests that malformed envelopes are rejected when deserializing
+    #[test]
+    fn test_envelope_malformed() {
+        let mut csprng = rand::thread_rng();
+
+        let (_, pk_recip) = Ke::gen_keypair(&mut csprng);
+        let (sender_mode, _) = gen_op_mode_pair::<Ke, Kd>(OpModeKind::Base);
+
+        // An empty message still has a tag, so the serialized envelope is as short as possible
+        let envelope_bytes =
+            seal_envelope::<A, Kd, Ke, _>(&sender_mode, &pk_recip, b"", b"", b"", &mut csprng)
+                .unwrap()
+                .to_bytes();
+
+        // Truncating it by a byte cuts into the tag
+        let truncated = &envelope_bytes[..envelope_bytes.len() - 1];
+        match Envelope::<A, Kd, Ke>::from_bytes(truncated) {
+            Err(HpkeError::InvalidMarshalledLength) => {} // Good, this is too short
+            Err(e) => panic!("from_bytes() should have failed. Instead got {}", e),
+            _ => panic!("from_bytes() should have failed. Instead it succeeded"),
+        }
+
+        // Unknown versions and modes are rejected
+        for &(idx, val) in &[(0, 2u8), (1, 4u8)] {
+            let mut bad_bytes = envelope_bytes.clone();
+            bad_bytes[idx] = val;
+            assert!(HEADER_LEN > idx);
+            match Envelope::<A, Kd, Ke>::from_bytes(&bad_bytes) {
+                Err(HpkeError::InvalidEnvelope) => {} // Good, this header is invalid
+                Err(e) => panic!("from_bytes() should have failed. Instead got {}", e),
+                _ => panic!("from_bytes() should have failed. Instead it succeeded"),
+            }
+        }
+    }
+
+    /// Tests that an envelope can't be opened as a different ciphersuite than it was sealed with
+    #[test]
+    fn test_envelope_suite_mismatch() {
+        let mut csprng = rand::thread_rng();
+
+        let info = b"test_envelope_suite_mismatch";
+        let (sk_recip, pk_recip) = Ke::gen_keypair(&mut csprng);
+        let (sender_mode, receiver_mode) = gen_op_mode_pair::<Ke, Kd>(OpModeKind::Base);
+
+        let envelope_bytes =
+            seal_envelope::<A, Kd, Ke, _>(&sender_mode, &pk_recip, info, b"hi", b"", &mut csprng)
+                .unwrap()
+                .to_bytes();
+
+        // AES-GCM-128 has the same tag length as ChaCha20Poly1305, so this deserializes fine
+        let envelope = Envelope::<AesGcm128, Kd, Ke>::from_bytes(&envelope_bytes).unwrap();
+        match open_envelope(&envelope, &receiver_mode, &sk_recip, info, b"") {
+            Err(HpkeError::SuiteMismatch) => {} // Good, this is the wrong AEAD
+            Err(e) => panic!("open_envelope() should have failed. Instead got {}", e),
+            _ => panic!("open_envelope() should have failed. Instead it succeeded"),
+        }
+    }
+
+    /// Tests that an envelope can't be opened in a different mode than it declares
+    #[test]
+    fn test_envelope_mode_mismatch() {
+        let mut csprng = rand::thread_rng();
+
+        let info = b"test_envelope_mode_mismatch";
+        let (sk_recip, pk_recip) = Ke::gen_keypair(&mut csprng);
+        let (sender_mode, _) = gen_op_mode_pair::<Ke, Kd>(OpModeKind::Base);
+        let (_, receiver_mode) = gen_op_mode_pair::<Ke, Kd>(OpModeKind::Psk);
+
+        let envelope =
+            seal_envelope::<A, Kd, Ke, _>(&sender_mode, &pk_recip, info, b"hi", b"", &mut csprng)
+                .unwrap();
+        match open_envelope(&envelope, &receiver_mode, &sk_recip, info, b"") {
+            Err(HpkeError::InvalidEnvelope) => {} // Good, this is the wrong mode
+            Err(e) => panic!("open_envelope() should have failed. Instead got {}", e),
+            _ => panic!("open_envelope() should have failed. Instead it succeeded"),
+        }
+    }
+}
@@ -0,0 +1,382 @@
+//! JSON Web Key (JWK) encodings of KEM keys. X25519 and X448 keys are `OKP` keys, as in RFC 8037.
+//! NIST curve keys are `EC` keys, as in RFC 7518 §6.2. All key material is base64url-encoded
+//! without padding.
+
+use crate::prelude::*;
+use crate::{
+    kex::{KeyExchange, Marshallable, Unmarshallable, P256, P384, P521, X25519, X448},
+    util::{base64, Redacted},
+    HpkeError,
+};
+
+use core::fmt;
+
+use digest::Digest;
+use serde::{Deserialize, Serialize};
+use sha2::Sha256;
+use zeroize::{Zeroize, Zeroizing};
+
+/// A JSON Web Key. Members that this crate doesn't use, like `use` and `key_ops`, are ignored when
+/// deserializing.
+#[derive(Clone, Default, Eq, PartialEq, Serialize, Deserialize)]
+pub struct Jwk {
+    /// The key type, e.g., `OKP` or `EC`
+    pub kty: String,
+    /// The curve, e.g., `X25519` or `P-256`
+    pub crv: String,
+    /// The public key for `OKP` keys, or the x coordinate of the public key for `EC` keys
+    pub x: String,
+    /// The y coordinate of the public key. This is only set for `EC` keys.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub y: Option<String>,
+    /// The private key. This is only set for private keys.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub d: Option<String>,
+    /// The key ID
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub kid: Option<String>,
+    /// A hint for the algorithm this key is meant to be used with, e.g., the name of an HPKE
+    /// ciphersuite. This is not checked by anything in this crate.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub alg: Option<String>,
+}
+
+// Only the private key is secret
+impl Zeroize for Jwk {
+    fn zeroize(&mut self) {
+        self.d.zeroize();
+    }
+}
+
+// Everything but the private key is printed
+impl fmt::Debug for Jwk {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Jwk")
+            .field("kty", &self.kty)
+            .field("crv", &self.crv)
+            .field("x", &self.x)
+            .field("y", &self.y)
+            .field("d", &self.d.as_ref().map(|_| Redacted))
+            .field("kid", &self.kid)
+            .field("alg", &self.alg)
+            .finish()
+    }
+}
+
+// Encodes bytes as base64url without padding
+fn b64_encode(bytes: &[u8]) -> String {
+    let encoded = base64::encode(base64::URL_SAFE, false, bytes);
+    // Every byte in encoded is an ASCII character, so this can't fail
+    core::str::from_utf8(&encoded).unwrap().into()
+}
+
+// Decodes base64url without padding
+fn b64_decode(s: &str) -> Result<Zeroizing<Vec<u8>>, HpkeError> {
+    base64::decode(base64::URL_SAFE, false, s.as_bytes()).ok_or(HpkeError::InvalidJwk)
+}
+
+impl Jwk {
+    /// Serializes this JWK as JSON
+    pub fn to_json(&self) -> String {
+        // Serializing a struct of strings can't fail
+        serde_json::to_string(self).unwrap()
+    }
+
+    /// Deserializes a JWK from JSON. This returns `Err(HpkeError::InvalidJwk)` if `json` isn't a
+    /// JSON object with at least the `kty`, `crv`, and `x` members.
+    pub fn from_json(json: &str) -> Result<Jwk, HpkeError> {
+        serde_json::from_str(json).map_err(|_| HpkeError::InvalidJwk)
+    }
+
+    // RFC 7638 §3.2: The thumbprint is the hash of a JSON object containing only the required
+    // members of the key, in lexicographic order, with no whitespace
+    /// Computes the RFC 7638 SHA-256 thumbprint of this key. Only `OKP` and `EC` keys are
+    /// supported. Returns `Err(HpkeError::InvalidJwk)` if this is another key type, or if a
+    /// required member is missing.
+    pub fn thumbprint(&self) -> Result<[u8; 32], HpkeError> {
+        // The values can be arbitrary strings, so we let serde_json escape them
+        let json_str = |s: &str| serde_json::to_string(s).unwrap();
+
+        let canonical = match self.kty.as_str() {
+            // RFC 8037 §2: the required members are crv, kty, and x
+            "OKP" => [
+                "{\"crv\":",
+                &json_str(&self.crv),
+                ",\"kty\":\"OKP\",\"x\":",
+                &json_str(&self.x),
+                "}",
+            ]
+            .concat(),
+            // RFC 7638 §3.2: the required members are crv, kty, x, and y
+            "EC" => {
+                let y = self.y.as_ref().ok_or(HpkeError::InvalidJwk)?;
+                [
+                    "{\"crv\":",
+                    &json_str(&self.crv),
+                    ",\"kty\":\"EC\",\"x\":",
+                    &json_str(&self.x),
+                    ",\"y\":",
+                    &json_str(y),
+                    "}",
+                ]
+                .concat()
+            }
+            _ => return Err(HpkeError::InvalidJwk),
+        };
+
+        let mut out = [0u8; 32];
+        out.copy_from_slice(&Sha256::digest(canonical.as_bytes()));
+        Ok(out)
+    }
+
+    /// Computes the RFC 7638 SHA-256 thumbprint of this key, encoded as base64url. This is
+    /// commonly used as the `kid`.
+    pub fn thumbprint_base64url(&self) -> Result<String, HpkeError> {
+        Ok(b64_encode(&self.thumbprint()?))
+    }
+}
+
+/// Implemented by key exchanges whose keys have a JWK encoding
+pub trait JwkEncoding: KeyExchange {
+    /// The `kty` member of this key exchange's JWKs
+    const KTY: &'static str;
+    /// The `crv` member of this key exchange's JWKs
+    const CRV: &'static str;
+
+    /// Returns the `x` and `y` members that encode the given public key
+    fn public_key_to_coords(pk: &Self::PublicKey) -> (Vec<u8>, Option<Vec<u8>>);
+
+    /// Decodes a public key from the `x` and `y` members of a JWK
+    fn public_key_from_coords(x: &[u8], y: Option<&[u8]>) -> Result<Self::PublicKey, HpkeError>;
+
+    /// Returns the JWK encoding of the given public key. The `kid` and `alg` members are unset.
+    fn public_key_to_jwk(pk: &Self::PublicKey) -> Jwk {
+        let (x, y) = Self::public_key_to_coords(pk);
+        Jwk {
+            kty: Self::KTY.into(),
+            crv: Self::CRV.into(),
+            x: b64_encode(&x),
+            y: y.map(|y| b64_encode(&y)),
+            ..Default::default()
+        }
+    }
+
+    /// Returns the JWK encoding of the given private key. This includes its public key. The `kid`
+    /// and `alg` members are unset.
+    fn private_key_to_jwk(sk: &Self::PrivateKey) -> Zeroizing<Jwk> {
+        let mut jwk = Self::public_key_to_jwk(&Self::sk_to_pk(sk));
+        jwk.d = Some(b64_encode(&sk.marshal()));
+        Zeroizing::new(jwk)
+    }
+
+    /// Decodes a public key from a JWK. Any private key in the JWK is ignored.
+    ///
+    /// Return Value
+    /// ============
+    /// If the JWK is for a different key type or curve, or is missing a member, returns
+    /// `Err(HpkeError::InvalidJwk)`. Otherwise, errors are the same as in `unmarshal`.
+    fn public_key_from_jwk(jwk: &Jwk) -> Result<Self::PublicKey, HpkeError> {
+        if jwk.kty != Self::KTY || jwk.crv != Self::CRV {
+            return Err(HpkeError::InvalidJwk);
+        }
+
+        let x = b64_decode(&jwk.x)?;
+        let y = jwk.y.as_deref().map(b64_decode).transpose()?;
+        Self::public_key_from_coords(&x, y.as_ref().map(|y| y.as_slice()))
+    }
+
+    /// Decodes a private key from a JWK, and checks that it matches the public key in the JWK
+    ///
+    /// Return Value
+    /// ============
+    /// If the JWK is for a different key type or curve, is missing a member, or has a public key
+    /// that doesn't match its private key, returns `Err(HpkeError::InvalidJwk)`. Otherwise, errors
+    /// are the same as in `unmarshal`.
+    fn private_key_from_jwk(jwk: &Jwk) -> Result<Self::PrivateKey, HpkeError> {
+        let pk = Self::public_key_from_jwk(jwk)?;
+        let d = b64_decode(jwk.d.as_ref().ok_or(HpkeError::InvalidJwk)?)?;
+        let sk = Self::PrivateKey::unmarshal(&d)?;
+
+        Self::validate_keypair(&sk, &pk).map_err(|_| HpkeError::InvalidJwk)?;
+
+        Ok(sk)
+    }
+}
+
+// RFC 8037 §2: The public key is x, and there is no y
+macro_rules! impl_okp_encoding {
+    ($kex:ty, $crv:expr) => {
+        impl JwkEncoding for $kex {
+            const KTY: &'static str = "OKP";
+            const CRV: &'static str = $crv;
+
+            fn public_key_to_coords(pk: &Self::PublicKey) -> (Vec<u8>, Option<Vec<u8>>) {
+                (pk.marshal().to_vec(), None)
+            }
+
+            fn public_key_from_coords(
+                x: &[u8],
+                y: Option<&[u8]>,
+            ) -> Result<Self::PublicKey, HpkeError> {
+                if y.is_some() {
+                    return Err(HpkeError::InvalidJwk);
+                }
+                Self::PublicKey::unmarshal(x)
+            }
+        }
+    };
+}
+
+// RFC 7518 §6.2.1: The public key is the point (x, y). Both coordinates are the full width of the
+// field, so the uncompressed point is 0x04 || x || y.
+macro_rules! impl_ec_encoding {
+    ($kex:ty, $crv:expr) => {
+        impl JwkEncoding for $kex {
+            const KTY: &'static str = "EC";
+            const CRV: &'static str = $crv;
+
+            fn public_key_to_coords(pk: &Self::PublicKey) -> (Vec<u8>, Option<Vec<u8>>) {
+                let point = pk.marshal();
+                let (x, y) = point[1..].split_at((point.len() - 1) / 2);
+                (x.to_vec(), Some(y.to_vec()))
+            }
+
+            fn public_key_from_coords(
+                x: &[u8],
+                y: Option<&[u8]>,
+            ) -> Result<Self::PublicKey, HpkeError> {
+                let y = y.ok_or(HpkeError::InvalidJwk)?;
+                if x.len() != y.len() {
+                    return Err(HpkeError::InvalidJwk);
+                }
+                Self::PublicKey::unmarshal(&[&[0x04], x, y].concat())
+            }
+        }
+    };
+}
+
+impl_okp_encoding!(X25519, "X25519");
+impl_okp_encoding!(X448, "X448");
+impl_ec_encoding!(P256, "P-256");
+impl_ec_encoding!(P384, "P-384");
+impl_ec_encoding!(P521, "P-521");
+
+#[cfg(test)]
+mod test {
+    use super::{Jwk, JwkEncoding};
+    use crate::{
+        kex::{KeyExchange, Marshallable, Unmarshallable, P256, P384, P521, X25519, X448},
+        HpkeError,
+    };
+
+    /// Tests against the X25519 key in RFC 8037 §A.6, which is Bob's key from RFC 7748 §6.1
+    #[test]
+    fn test_rfc8037_x25519() {
+        let sk_bytes =
+            hex::decode("5dab087e624a8a4b79e17f8b83800ee66f3bb1292618b6fd1c2f8b27ff88e0eb")
+                .unwrap();
+        let sk = <X25519 as KeyExchange>::PrivateKey::unmarshal(&sk_bytes).unwrap();
+
+        let jwk = X25519::private_key_to_jwk(&sk);
+        assert_eq!(jwk.kty, "OKP");
+        assert_eq!(jwk.crv, "X25519");
+        assert_eq!(jwk.x, "3p7bfXt9wbTTW2HC7OQ1Nz-DQ8hbeGdNrfx-FG-IK08");
+
+        let new_sk = X25519::private_key_from_jwk(&jwk).unwrap();
+        assert_eq!(new_sk.marshal(), sk.marshal());
+    }
+
+    /// Tests the thumbprint against the example in RFC 8037 §A.3
+    #[test]
+    fn test_rfc8037_thumbprint() {
+        let jwk = Jwk::from_json(
+            r#"{"kty":"OKP","crv":"Ed25519",
+                "x":"11qYAYKxCrfVS_7TyWQHOg7hcvPapiMlrwIaaPcHURo"}"#,
+        )
+        .unwrap();
+        assert_eq!(
+            jwk.thumbprint_base64url().unwrap(),
+            "kPrK_qmxVWaYVA9wwBF6Iuo3vVzz7TxHCTwXBygrS4k"
+        );
+
+        // The thumbprint doesn't depend on the optional members
+        let mut jwk_with_kid = jwk.clone();
+        jwk_with_kid.kid = Some("my key".into());
+        jwk_with_kid.alg = Some("HPKE".into());
+        assert_eq!(
+            jwk_with_kid.thumbprint().unwrap(),
+            jwk.thumbprint().unwrap()
+        );
+    }
+
+    /// Tests that random keys survive a round trip through JSON
+    #[test]
+    fn test_jwk_roundtrip() {
+        let mut csprng = rand::thread_rng();
+
+        macro_rules! test_roundtrip {
+            ($kex:ty) => {{
+                let (sk, pk) = <$kex>::gen_keypair(&mut csprng);
+
+                let mut pk_jwk = <$kex>::public_key_to_jwk(&pk);
+                pk_jwk.kid = Some(pk_jwk.thumbprint_base64url().unwrap());
+                pk_jwk.alg = Some("HPKE-Base".into());
+                let sk_json = <$kex>::private_key_to_jwk(&sk).to_json();
+                let pk_json = pk_jwk.to_json();
+
+                let new_pk_jwk = Jwk::from_json(&pk_json).unwrap();
+                assert!(new_pk_jwk == pk_jwk);
+                let new_sk = <$kex>::private_key_from_jwk(&Jwk::from_json(&sk_json).unwrap());
+                let new_pk = <$kex>::public_key_from_jwk(&new_pk_jwk);
+
+                assert_eq!(new_sk.unwrap().marshal(), sk.marshal());
+                assert_eq!(new_pk.unwrap().marshal(), pk.marshal());
+            }};
+        }
+
+        test_roundtrip!(X25519);
+        test_roundtrip!(X448);
+        test_roundtrip!(P256);
+        test_roundtrip!(P384);
+        test_roundtrip!(P521);
+    }
+
+    /// Tests that JWKs for other curves, and JWKs with mismatched keys, are rejected
+    #[test]
+    fn test_jwk_invalid() {
+        let mut csprng = rand::thread_rng();
+        let (sk, _) = P256::gen_keypair(&mut csprng);
+        let (_, other_pk) = P256::gen_keypair(&mut csprng);
+
+        // A P-256 key is not an X25519 key
+        let jwk = P256::private_key_to_jwk(&sk);
+        match X25519::public_key_from_jwk(&jwk) {
+            Err(HpkeError::InvalidJwk) => {} // Good, this is the wrong curve
+            Err(e) => panic!(
+                "public_key_from_jwk() should have failed. Instead got {}",
+                e
+            ),
+            _ => panic!("public_key_from_jwk() should have failed. Instead it succeeded"),
+        }
+
+        // The private key has to match the public key
+        let mut mismatched_jwk = P256::public_key_to_jwk(&other_pk);
+        mismatched_jwk.d = jwk.d.clone();
+        match P256::private_key_from_jwk(&mismatched_jwk) {
+            Err(HpkeError::InvalidJwk) => {} // Good, these keys don't match
+            Err(e) => panic!(
+                "private_key_from_jwk() should have failed. Instead got {}",
+                e
+            ),
+            _ => panic!("private_key_from_jwk() should have failed. Instead it succeeded"),
+        }
+
+        // Padding isn't allowed in base64url
+        let mut padded_jwk = P256::public_key_to_jwk(&other_pk);
+        padded_jwk.x.push('=');
+        assert!(P256::public_key_from_jwk(&padded_jwk).is_err());
+
+        // Missing members are an error
+        assert!(Jwk::from_json(r#"{"kty":"OKP","crv":"X25519"}"#).is_err());
+    }
+}
@@ -1,18 +1,24 @@
 use crate::prelude::*;
 use crate::{
-    aead::{Aead, AeadTag, AesGcm128, AesGcm256, ChaCha20Poly1305},
+    aead::{
+        Aead, AeadCtxR, AeadTag, AesGcm128, AesGcm256, ChaCha20Poly1305, ExportOnly, SealingAead,
+    },
     kdf::{HkdfSha256, HkdfSha384, HkdfSha512, Kdf as KdfTrait},
-    kem::{encap_with_eph, Kem as KemTrait, X25519HkdfSha256},
-    kex::{KeyExchange, Marshallable, Unmarshallable},
+    kem::{
+        DhP256HkdfSha256, DhP384HkdfSha384, DhP521HkdfSha512, Kem as KemTrait, MlKem1024, MlKem768,
+        SharedSecret, X25519HkdfSha256, X448HkdfSha512,
+    },
+    kex::{Marshallable, Unmarshallable},
     op_mode::{OpModeR, Psk, PskBundle},
     setup::setup_receiver,
 };
 
-use std::fs::File;
+use core::convert::TryFrom;
+use std::{fs::File, string::String};
+
+use ml_kem::B32;
 
-use hex;
 use serde::{de::Error as SError, Deserialize, Deserializer};
-use serde_json;
 
 // Tells serde how to deserialize bytes from the hex representation
 fn bytes_from_hex<'de, D>(deserializer: D) -> Result<Vec<u8>, D::Error>
@@ -32,114 +38,125 @@ fn bytes_from_hex_opt<'de, D>(deserializer: D) -> Result<Option<Vec<u8>>, D::Err
 where
     D: Deserializer<'de>,
 {
-    bytes_from_hex(deserializer).map(|v| Some(v))
+    bytes_from_hex(deserializer).map(Some)
 }
 
 // Each individual test case looks like this
-#[derive(Deserialize)]
+#[derive(serde_derive::Deserialize)]
 struct MainTestVector {
     // Parameters
     mode: u8,
-    #[serde(rename = "kemID")]
     kem_id: u16,
-    #[serde(rename = "kdfID")]
     kdf_id: u16,
-    #[serde(rename = "aeadID")]
     aead_id: u16,
     #[serde(deserialize_with = "bytes_from_hex")]
     info: Vec<u8>,
 
+    // Keying material
+    #[serde(rename = "ikmR", deserialize_with = "bytes_from_hex")]
+    ikm_recip: Vec<u8>,
+    #[serde(default, rename = "ikmS", deserialize_with = "bytes_from_hex_opt")]
+    ikm_sender: Option<Vec<u8>>,
+    #[serde(rename = "ikmE", deserialize_with = "bytes_from_hex")]
+    ikm_eph: Vec<u8>,
+
     // Private keys
-    #[serde(rename = "skR", deserialize_with = "bytes_from_hex")]
+    #[serde(rename = "skRm", deserialize_with = "bytes_from_hex")]
     sk_recip: Vec<u8>,
-    #[serde(default, rename = "skS", deserialize_with = "bytes_from_hex_opt")]
+    #[serde(default, rename = "skSm", deserialize_with = "bytes_from_hex_opt")]
     sk_sender: Option<Vec<u8>>,
-    #[serde(rename = "skE", deserialize_with = "bytes_from_hex")]
+    #[serde(rename = "skEm", deserialize_with = "bytes_from_hex")]
     sk_eph: Vec<u8>,
     #[serde(default, deserialize_with = "bytes_from_hex_opt")]
     psk: Option<Vec<u8>>,
-    #[serde(default, rename = "pskID", deserialize_with = "bytes_from_hex_opt")]
+    #[serde(default, deserialize_with = "bytes_from_hex_opt")]
     psk_id: Option<Vec<u8>>,
 
     // Public Keys
-    #[serde(rename = "pkR", deserialize_with = "bytes_from_hex")]
+    #[serde(rename = "pkRm", deserialize_with = "bytes_from_hex")]
     pk_recip: Vec<u8>,
-    #[serde(default, rename = "pkS", deserialize_with = "bytes_from_hex_opt")]
+    #[serde(default, rename = "pkSm", deserialize_with = "bytes_from_hex_opt")]
     pk_sender: Option<Vec<u8>>,
-    #[serde(rename = "pkE", deserialize_with = "bytes_from_hex")]
+    #[serde(rename = "pkEm", deserialize_with = "bytes_from_hex")]
     pk_eph: Vec<u8>,
 
     // Key schedule inputs and computations
     #[serde(rename = "enc", deserialize_with = "bytes_from_hex")]
     encapped_key: Vec<u8>,
-    #[serde(rename = "zz", deserialize_with = "bytes_from_hex")]
-    _shared_secret: Vec<u8>,
-    #[serde(rename = "context", deserialize_with = "bytes_from_hex")]
-    _hpke_context: Vec<u8>,
+    #[serde(deserialize_with = "bytes_from_hex")]
+    shared_secret: Vec<u8>,
+    #[serde(rename = "key_schedule_context", deserialize_with = "bytes_from_hex")]
+    _key_schedule_context: Vec<u8>,
     #[serde(rename = "secret", deserialize_with = "bytes_from_hex")]
     _key_schedule_secret: Vec<u8>,
     #[serde(rename = "key", deserialize_with = "bytes_from_hex")]
     _aead_key: Vec<u8>,
-    #[serde(rename = "nonce", deserialize_with = "bytes_from_hex")]
-    _aead_nonce: Vec<u8>,
-    #[serde(rename = "exporterSecret", deserialize_with = "bytes_from_hex")]
+    #[serde(rename = "base_nonce", deserialize_with = "bytes_from_hex")]
+    _aead_base_nonce: Vec<u8>,
+    #[serde(rename = "exporter_secret", deserialize_with = "bytes_from_hex")]
     _exporter_secret: Vec<u8>,
 
     encryptions: Vec<EncryptionTestVector>,
     exports: Vec<ExporterTestVector>,
 }
 
-#[derive(Deserialize)]
+#[derive(serde_derive::Deserialize)]
 struct EncryptionTestVector {
-    #[serde(deserialize_with = "bytes_from_hex")]
+    #[serde(rename = "pt", deserialize_with = "bytes_from_hex")]
     plaintext: Vec<u8>,
     #[serde(deserialize_with = "bytes_from_hex")]
     aad: Vec<u8>,
     #[serde(rename = "nonce", deserialize_with = "bytes_from_hex")]
     _nonce: Vec<u8>,
-    #[serde(deserialize_with = "bytes_from_hex")]
+    #[serde(rename = "ct", deserialize_with = "bytes_from_hex")]
     ciphertext: Vec<u8>,
 }
 
-#[derive(Deserialize)]
+#[derive(serde_derive::Deserialize)]
 struct ExporterTestVector {
-    #[serde(rename = "context", deserialize_with = "bytes_from_hex")]
-    info: Vec<u8>,
-    #[serde(rename = "exportLength")]
+    #[serde(rename = "exporter_context", deserialize_with = "bytes_from_hex")]
+    exporter_ctx: Vec<u8>,
+    #[serde(rename = "L")]
     export_len: usize,
-    #[serde(rename = "exportValue", deserialize_with = "bytes_from_hex")]
+    #[serde(rename = "exported_value", deserialize_with = "bytes_from_hex")]
     export_val: Vec<u8>,
 }
 
-/// Returns a KEX keypair given the secret bytes and pubkey bytes, and ensures that the pubkey does
+/// Returns a KEM keypair given the secret bytes and pubkey bytes, and ensures that the pubkey does
 /// indeed correspond to that secret key
-fn get_and_assert_keypair<Kex: KeyExchange>(
+fn get_and_assert_keypair<Kem: KemTrait>(
     sk_bytes: &[u8],
     pk_bytes: &[u8],
-) -> (Kex::PrivateKey, Kex::PublicKey) {
+) -> (Kem::PrivateKey, Kem::PublicKey) {
     // Unmarshall the secret key
-    let sk = <Kex as KeyExchange>::PrivateKey::unmarshal(sk_bytes).unwrap();
+    let sk = Kem::PrivateKey::unmarshal(sk_bytes).unwrap();
     // Unmarshall the pubkey
-    let pk = <Kex as KeyExchange>::PublicKey::unmarshal(pk_bytes).unwrap();
+    let pk = Kem::PublicKey::unmarshal(pk_bytes).unwrap();
 
     // Make sure the derived pubkey matches the given pubkey
-    assert_eq!(pk.marshal(), Kex::sk_to_pk(&sk).marshal());
+    assert_eq!(pk.marshal(), Kem::sk_to_pk(&sk).marshal());
 
     (sk, pk)
 }
 
+/// Asserts that `Kem::derive_keypair(ikm)` derives the given keypair
+fn assert_derived_keypair<Kem: KemTrait>(ikm: &[u8], sk: &Kem::PrivateKey, pk: &Kem::PublicKey) {
+    let (derived_sk, derived_pk) = Kem::derive_keypair(ikm);
+    assert_eq!(derived_sk.marshal(), sk.marshal());
+    assert_eq!(derived_pk.marshal(), pk.marshal());
+}
+
 /// Constructs an `OpModeR` from the given components. The variant constructed is determined solely
 /// by `mode_id`. This will panic if there is insufficient data to construct the variants specified
 /// by `mode_id`.
-fn make_op_mode_r<Kex: KeyExchange, Kdf: KdfTrait>(
+fn make_op_mode_r<Kem: KemTrait, Kdf: KdfTrait>(
     mode_id: u8,
     pk_sender_bytes: Option<Vec<u8>>,
     psk: Option<Vec<u8>>,
     psk_id: Option<Vec<u8>>,
-) -> OpModeR<Kex, Kdf> {
+) -> OpModeR<Kem, Kdf> {
     // Unmarshal the optional pubkey
-    let pk =
-        pk_sender_bytes.map(|bytes| <Kex as KeyExchange>::PublicKey::unmarshal(&bytes).unwrap());
+    let pk = pk_sender_bytes.map(|bytes| Kem::AuthPublicKey::unmarshal(&bytes).unwrap());
     // Unmarshal the optinoal bundle
     let bundle = psk.map(|bytes| PskBundle::<Kdf> {
         psk: Psk::<Kdf>::from_bytes(bytes),
@@ -156,117 +173,363 @@ fn make_op_mode_r<Kex: KeyExchange, Kdf: KdfTrait>(
     }
 }
 
-// Implements a test case for a given AEAD implementation
-macro_rules! test_case {
-    ($tv:ident, $aead_ty:ty, $kdf_ty:ty) => {{
+/// Asserts that every ciphertext in `encryptions` decrypts to the corresponding plaintext under
+/// the given context
+fn assert_decryptions<A: SealingAead, Kdf: KdfTrait>(
+    aead_ctx: &mut AeadCtxR<A, Kdf>,
+    encryptions: Vec<EncryptionTestVector>,
+) {
+    for enc_packet in encryptions {
+        let aad = enc_packet.aad;
+
+        // The test vector's ciphertext is of the form ciphertext || tag. Break it up into two
+        // pieces so we can call open() on it.
+        let (mut ciphertext, tag) = {
+            let mut ciphertext_and_tag = enc_packet.ciphertext;
+            let total_len = ciphertext_and_tag.len();
+
+            let tag_size = AeadTag::<A>::size();
+            let (ciphertext_bytes, tag_bytes) =
+                ciphertext_and_tag.split_at_mut(total_len - tag_size);
+
+            (
+                ciphertext_bytes.to_vec(),
+                AeadTag::unmarshal(tag_bytes).unwrap(),
+            )
+        };
+
+        // Open the ciphertext in place and assert that this succeeds
+        aead_ctx
+            .open(&mut ciphertext, &aad, &tag)
+            .expect("open failed");
+        // Rename for clarity
+        let plaintext = ciphertext;
+
+        // Assert the plaintext equals the expected plaintext
+        assert_eq!(plaintext, enc_packet.plaintext.as_slice());
+    }
+}
+
+/// Asserts that `AeadCtxR::export` returns the expected values
+fn assert_exports<A: Aead, Kdf: KdfTrait>(
+    aead_ctx: &AeadCtxR<A, Kdf>,
+    exports: Vec<ExporterTestVector>,
+) {
+    for export in exports {
+        let mut exported_val = vec![0u8; export.export_len];
+        aead_ctx
+            .export(&export.exporter_ctx, &mut exported_val)
+            .unwrap();
+        assert_eq!(exported_val, export.export_val);
+    }
+}
+
+// Checks the keys and encapsulation of a test vector, and returns the receiver's context for the
+// given AEAD, KDF, and KEM implementation
+macro_rules! receiver_ctx {
+    ($tv:ident, $aead_ty:ty, $kdf_ty:ty, $kem_ty:ty) => {{
         type A = $aead_ty;
         type Kdf = $kdf_ty;
-        type Kem = X25519HkdfSha256;
-        type Kex = <X25519HkdfSha256 as KemTrait>::Kex;
+        type Kem = $kem_ty;
 
         // First, unmarshall all the relevant keys so we can reconstruct the encapped key
-        let (sk_recip, pk_recip) = get_and_assert_keypair::<Kex>(&$tv.sk_recip, &$tv.pk_recip);
-        let (sk_eph, _) = get_and_assert_keypair::<Kex>(&$tv.sk_eph, &$tv.pk_eph);
+        let (sk_recip, pk_recip) = get_and_assert_keypair::<Kem>(&$tv.sk_recip, &$tv.pk_recip);
+        let (sk_eph, _) = get_and_assert_keypair::<Kem>(&$tv.sk_eph, &$tv.pk_eph);
 
         let sk_sender = $tv
             .sk_sender
-            .map(|bytes| <Kex as KeyExchange>::PrivateKey::unmarshal(&bytes).unwrap());
+            .map(|bytes| <Kem as KemTrait>::PrivateKey::unmarshal(&bytes).unwrap());
         let pk_sender = $tv
             .pk_sender
             .clone()
-            .map(|bytes| <Kex as KeyExchange>::PublicKey::unmarshal(&bytes).unwrap());
+            .map(|bytes| <Kem as KemTrait>::PublicKey::unmarshal(&bytes).unwrap());
         // If sk_sender is Some, then so is pk_sender
         let sender_keypair = sk_sender.map(|sk| (sk, pk_sender.unwrap()));
 
+        // Make sure the keypairs are the ones derived from the given IKMs
+        assert_derived_keypair::<Kem>(&$tv.ikm_recip, &sk_recip, &pk_recip);
+        assert_derived_keypair::<Kem>(&$tv.ikm_eph, &sk_eph, &Kem::sk_to_pk(&sk_eph));
+        if let Some((sk, pk)) = sender_keypair.as_ref() {
+            assert_derived_keypair::<Kem>(&$tv.ikm_sender.unwrap(), sk, pk);
+        }
+
         // Now derive the encapped key with the deterministic encap function, using all the inputs
         // above
-        let (_, encapped_key) =
-            encap_with_eph::<Kem>(&pk_recip, sender_keypair.as_ref(), sk_eph.clone())
+        let (shared_secret, encapped_key) =
+            Kem::encap_with_eph(&pk_recip, sender_keypair.as_ref(), sk_eph.clone())
                 .expect("encap failed");
-        // Now assert that the derived encapped key is identical to the one provided
+        // Now assert that the derived encapped key and shared secret are identical to the ones
+        // provided
         assert_eq!(
             encapped_key.marshal().as_slice(),
             $tv.encapped_key.as_slice()
         );
+        assert_eq!(shared_secret.0.as_slice(), $tv.shared_secret.as_slice());
 
         // We're going to test the encryption contexts. First, construct the appropriate OpMode.
         let mode = make_op_mode_r($tv.mode, $tv.pk_sender, $tv.psk, $tv.psk_id);
-        let mut aead_ctx =
-            setup_receiver::<A, Kdf, Kem>(&mode, &sk_recip, &encapped_key, &$tv.info)
-                .expect("setup_receiver failed");
+        setup_receiver::<A, Kdf, Kem>(&mode, &sk_recip, &encapped_key, &$tv.info)
+            .expect("setup_receiver failed")
+    }};
+}
 
+// Implements a test case for a given AEAD, KDF, and KEM implementation
+macro_rules! test_case {
+    // Export-only contexts have nothing to decrypt
+    ($tv:ident, ExportOnly, $kdf_ty:ty, $kem_ty:ty) => {{
+        let aead_ctx = receiver_ctx!($tv, ExportOnly, $kdf_ty, $kem_ty);
+        assert_exports(&aead_ctx, $tv.exports);
+    }};
+    ($tv:ident, $aead_ty:ty, $kdf_ty:ty, $kem_ty:ty) => {{
         // Go through all the plaintext-ciphertext pairs of this test vector and assert the
         // ciphertext decrypts to the corresponding plaintext
-        for enc_packet in $tv.encryptions {
-            let aad = enc_packet.aad;
-
-            // The test vector's ciphertext is of the form ciphertext || tag. Break it up into two
-            // pieces so we can call open() on it.
-            let (mut ciphertext, tag) = {
-                let mut ciphertext_and_tag = enc_packet.ciphertext;
-                let total_len = ciphertext_and_tag.len();
-
-                let tag_size = AeadTag::<A>::size();
-                let (ciphertext_bytes, tag_bytes) =
-                    ciphertext_and_tag.split_at_mut(total_len - tag_size);
-
-                (
-                    ciphertext_bytes.to_vec(),
-                    AeadTag::unmarshal(tag_bytes).unwrap(),
-                )
-            };
-
-            // Open the ciphertext in place and assert that this succeeds
-            aead_ctx
-                .open(&mut ciphertext, &aad, &tag)
-                .expect("open failed");
-            // Rename for clarity
-            let plaintext = ciphertext;
-
-            // Assert the plaintext equals the expected plaintext
-            assert_eq!(plaintext, enc_packet.plaintext.as_slice());
-        }
+        let mut aead_ctx = receiver_ctx!($tv, $aead_ty, $kdf_ty, $kem_ty);
+        assert_decryptions(&mut aead_ctx, $tv.encryptions);
 
-        // Now check that AeadCtx::export returns the expected values
-        for export in $tv.exports {
-            let mut exported_val = vec![0u8; export.export_len];
-            aead_ctx.export(&export.info, &mut exported_val).unwrap();
-            assert_eq!(exported_val, export.export_val);
-        }
+        // Now check that AeadCtxR::export returns the expected values
+        assert_exports(&aead_ctx, $tv.exports);
     }};
 }
 
-#[test]
-fn kat_test() {
-    let file = File::open("test-vectors-d1dbba6.json").unwrap();
-    let tvs: Vec<MainTestVector> = serde_json::from_reader(file).unwrap();
-
-    for tv in tvs.into_iter() {
-        // Ignore everything that doesn't use X25519, since that's all we support right now
-        if tv.kem_id != X25519HkdfSha256::KEM_ID {
-            continue;
-        }
-
-        match (tv.aead_id, tv.kdf_id) {
-            (AesGcm128::AEAD_ID, HkdfSha256::KDF_ID) => test_case!(tv, AesGcm128, HkdfSha256),
-            (AesGcm128::AEAD_ID, HkdfSha384::KDF_ID) => test_case!(tv, AesGcm128, HkdfSha384),
-            (AesGcm128::AEAD_ID, HkdfSha512::KDF_ID) => test_case!(tv, AesGcm128, HkdfSha512),
-            (AesGcm256::AEAD_ID, HkdfSha256::KDF_ID) => test_case!(tv, AesGcm256, HkdfSha256),
-            (AesGcm256::AEAD_ID, HkdfSha384::KDF_ID) => test_case!(tv, AesGcm256, HkdfSha384),
-            (AesGcm256::AEAD_ID, HkdfSha512::KDF_ID) => test_case!(tv, AesGcm256, HkdfSha512),
+// Dispatches a test case to the right AEAD and KDF, given a KEM
+macro_rules! dispatch_testcase {
+    ($tv:ident, $kem_ty:ty) => {{
+        match ($tv.aead_id, $tv.kdf_id) {
+            (AesGcm128::AEAD_ID, HkdfSha256::KDF_ID) => {
+                test_case!($tv, AesGcm128, HkdfSha256, $kem_ty)
+            }
+            (AesGcm128::AEAD_ID, HkdfSha384::KDF_ID) => {
+                test_case!($tv, AesGcm128, HkdfSha384, $kem_ty)
+            }
+            (AesGcm128::AEAD_ID, HkdfSha512::KDF_ID) => {
+                test_case!($tv, AesGcm128, HkdfSha512, $kem_ty)
+            }
+            (AesGcm256::AEAD_ID, HkdfSha256::KDF_ID) => {
+                test_case!($tv, AesGcm256, HkdfSha256, $kem_ty)
+            }
+            (AesGcm256::AEAD_ID, HkdfSha384::KDF_ID) => {
+                test_case!($tv, AesGcm256, HkdfSha384, $kem_ty)
+            }
+            (AesGcm256::AEAD_ID, HkdfSha512::KDF_ID) => {
+                test_case!($tv, AesGcm256, HkdfSha512, $kem_ty)
+            }
             (ChaCha20Poly1305::AEAD_ID, HkdfSha256::KDF_ID) => {
-                test_case!(tv, ChaCha20Poly1305, HkdfSha256)
+                test_case!($tv, ChaCha20Poly1305, HkdfSha256, $kem_ty)
             }
             (ChaCha20Poly1305::AEAD_ID, HkdfSha384::KDF_ID) => {
-                test_case!(tv, ChaCha20Poly1305, HkdfSha384)
+                test_case!($tv, ChaCha20Poly1305, HkdfSha384, $kem_ty)
             }
             (ChaCha20Poly1305::AEAD_ID, HkdfSha512::KDF_ID) => {
-                test_case!(tv, ChaCha20Poly1305, HkdfSha512)
+                test_case!($tv, ChaCha20Poly1305, HkdfSha512, $kem_ty)
+            }
+            (ExportOnly::AEAD_ID, HkdfSha256::KDF_ID) => {
+                test_case!($tv, ExportOnly, HkdfSha256, $kem_ty)
+            }
+            (ExportOnly::AEAD_ID, HkdfSha384::KDF_ID) => {
+                test_case!($tv, ExportOnly, HkdfSha384, $kem_ty)
+            }
+            (ExportOnly::AEAD_ID, HkdfSha512::KDF_ID) => {
+                test_case!($tv, ExportOnly, HkdfSha512, $kem_ty)
             }
             _ => panic!(
                 "Invalid (AEAD ID, KDF ID) combo: ({}, {})",
-                tv.aead_id, tv.kdf_id
+                $tv.aead_id, $tv.kdf_id
             ),
-        };
+        }
+    }};
+}
+
+/// Runs every vector in the given file, skipping the KEMs we don't support
+fn run_kat_file(path: &str) {
+    let file = File::open(path).unwrap();
+    let tvs: Vec<MainTestVector> = serde_json::from_reader(file).unwrap();
+
+    for tv in tvs.into_iter() {
+        match tv.kem_id {
+            X25519HkdfSha256::KEM_ID => dispatch_testcase!(tv, X25519HkdfSha256),
+            X448HkdfSha512::KEM_ID => dispatch_testcase!(tv, X448HkdfSha512),
+            DhP256HkdfSha256::KEM_ID => dispatch_testcase!(tv, DhP256HkdfSha256),
+            DhP384HkdfSha384::KEM_ID => dispatch_testcase!(tv, DhP384HkdfSha384),
+            DhP521HkdfSha512::KEM_ID => dispatch_testcase!(tv, DhP521HkdfSha512),
+            // Skip the KEMs we don't support
+            _ => continue,
+        }
+    }
+}
+
+#[test]
+fn kat_test() {
+    run_kat_file("test-vectors-5f503c5.json");
+}
+
+/// RFC 9180 has no P-384 vectors, so these come from scripts/gen_p384_vectors.py
+#[test]
+fn kat_test_p384() {
+    run_kat_file("test-vectors-p384.json");
+}
+
+// The ML-KEM vectors have their own format, since ML-KEM has no ephemeral keypair. The keygen and
+// encap vectors are from the FIPS 203 ACVP vectors. The decap vectors, which include an implicitly
+// rejected ciphertext, the derive_keypair vectors, and the Base mode ciphertexts were made with
+// pyca/cryptography.
+#[derive(serde_derive::Deserialize)]
+struct MlKemTestVectors {
+    keygen: Vec<MlKemKeyGenVector>,
+    encap: Vec<MlKemEncapVector>,
+    decap: Vec<MlKemDecapVector>,
+    derive_keypair: Vec<MlKemDeriveVector>,
+    hpke: Vec<MlKemHpkeVector>,
+}
+
+// ML-KEM.KeyGen_internal(d, z) = (ek, dk)
+#[derive(serde_derive::Deserialize)]
+struct MlKemKeyGenVector {
+    kem_id: u16,
+    #[serde(deserialize_with = "bytes_from_hex")]
+    d: Vec<u8>,
+    #[serde(deserialize_with = "bytes_from_hex")]
+    z: Vec<u8>,
+    #[serde(deserialize_with = "bytes_from_hex")]
+    ek: Vec<u8>,
+}
+
+// ML-KEM.Encaps_internal(ek, m) = (K, c)
+#[derive(serde_derive::Deserialize)]
+struct MlKemEncapVector {
+    kem_id: u16,
+    #[serde(deserialize_with = "bytes_from_hex")]
+    ek: Vec<u8>,
+    #[serde(deserialize_with = "bytes_from_hex")]
+    m: Vec<u8>,
+    #[serde(deserialize_with = "bytes_from_hex")]
+    c: Vec<u8>,
+    #[serde(deserialize_with = "bytes_from_hex")]
+    k: Vec<u8>,
+}
+
+// ML-KEM.Decaps(dk, c) = K, where dk is expanded from seed
+#[derive(serde_derive::Deserialize)]
+struct MlKemDecapVector {
+    kem_id: u16,
+    #[serde(deserialize_with = "bytes_from_hex")]
+    seed: Vec<u8>,
+    #[serde(deserialize_with = "bytes_from_hex")]
+    c: Vec<u8>,
+    #[serde(deserialize_with = "bytes_from_hex")]
+    k: Vec<u8>,
+}
+
+#[derive(serde_derive::Deserialize)]
+struct MlKemDeriveVector {
+    kem_id: u16,
+    #[serde(deserialize_with = "bytes_from_hex")]
+    ikm: Vec<u8>,
+    #[serde(rename = "skm", deserialize_with = "bytes_from_hex")]
+    sk: Vec<u8>,
+}
+
+// A Base mode ciphertext with an empty AAD
+#[derive(serde_derive::Deserialize)]
+struct MlKemHpkeVector {
+    kem_id: u16,
+    kdf_id: u16,
+    aead_id: u16,
+    #[serde(rename = "skRm", deserialize_with = "bytes_from_hex")]
+    sk_recip: Vec<u8>,
+    #[serde(deserialize_with = "bytes_from_hex")]
+    info: Vec<u8>,
+    #[serde(rename = "pt", deserialize_with = "bytes_from_hex")]
+    plaintext: Vec<u8>,
+    #[serde(rename = "enc", deserialize_with = "bytes_from_hex")]
+    encapped_key: Vec<u8>,
+    #[serde(rename = "ct", deserialize_with = "bytes_from_hex")]
+    ciphertext: Vec<u8>,
+}
+
+/// Checks every keygen, encap, decap, and derive_keypair vector for the given ML-KEM. The KEM's
+/// deterministic encap isn't part of the `Kem` trait, so it's passed in as `encap`.
+fn check_ml_kem_vectors<Kem, F>(tvs: &MlKemTestVectors, encap: F)
+where
+    Kem: KemTrait,
+    F: Fn(&Kem::PublicKey, &[u8]) -> (SharedSecret<Kem>, Kem::EncappedKey),
+{
+    let mut num_checked = [0usize; 4];
+
+    for tv in tvs.keygen.iter().filter(|tv| tv.kem_id == Kem::KEM_ID) {
+        // The private key is the seed d || z
+        let sk = Kem::PrivateKey::unmarshal(&[&tv.d[..], &tv.z[..]].concat()).unwrap();
+        assert_eq!(Kem::sk_to_pk(&sk).marshal().as_slice(), tv.ek.as_slice());
+        num_checked[0] += 1;
+    }
+
+    for tv in tvs.encap.iter().filter(|tv| tv.kem_id == Kem::KEM_ID) {
+        let pk = Kem::PublicKey::unmarshal(&tv.ek).unwrap();
+        let (shared_secret, encapped_key) = encap(&pk, &tv.m);
+        assert_eq!(encapped_key.marshal().as_slice(), tv.c.as_slice());
+        assert_eq!(shared_secret.0.as_slice(), tv.k.as_slice());
+        num_checked[1] += 1;
+    }
+
+    for tv in tvs.decap.iter().filter(|tv| tv.kem_id == Kem::KEM_ID) {
+        let sk = Kem::PrivateKey::unmarshal(&tv.seed).unwrap();
+        let encapped_key = Kem::EncappedKey::unmarshal(&tv.c).unwrap();
+        let shared_secret = Kem::decap(&sk, &encapped_key).unwrap();
+        assert_eq!(shared_secret.0.as_slice(), tv.k.as_slice());
+        num_checked[2] += 1;
+    }
+
+    for tv in tvs
+        .derive_keypair
+        .iter()
+        .filter(|tv| tv.kem_id == Kem::KEM_ID)
+    {
+        let (sk, _) = Kem::derive_keypair(&tv.ikm);
+        assert_eq!(sk.marshal().as_slice(), tv.sk.as_slice());
+        num_checked[3] += 1;
+    }
+
+    // Make sure there was a vector of every kind
+    assert!(num_checked.iter().all(|&n| n > 0));
+}
+
+/// Opens the ciphertext of an ML-KEM HPKE vector
+fn check_ml_kem_hpke<A: SealingAead, Kdf: KdfTrait, Kem: KemTrait>(tv: MlKemHpkeVector) {
+    let sk_recip = Kem::PrivateKey::unmarshal(&tv.sk_recip).unwrap();
+    let encapped_key = Kem::EncappedKey::unmarshal(&tv.encapped_key).unwrap();
+    let mut aead_ctx =
+        setup_receiver::<A, Kdf, Kem>(&OpModeR::Base, &sk_recip, &encapped_key, &tv.info)
+            .expect("setup_receiver failed");
+    let plaintext = aead_ctx
+        .open_to_vec(&tv.ciphertext, b"")
+        .expect("open failed");
+    assert_eq!(plaintext, tv.plaintext);
+}
+
+/// The RFC vectors have no ML-KEM cases, so these come from a separate file
+#[test]
+fn kat_test_ml_kem() {
+    let file = File::open("test-vectors-ml-kem.json").unwrap();
+    let tvs: MlKemTestVectors = serde_json::from_reader(file).unwrap();
+
+    check_ml_kem_vectors::<MlKem768, _>(&tvs, |pk, m| {
+        MlKem768::encap_with_randomness(pk, &B32::try_from(m).unwrap()).unwrap()
+    });
+    check_ml_kem_vectors::<MlKem1024, _>(&tvs, |pk, m| {
+        MlKem1024::encap_with_randomness(pk, &B32::try_from(m).unwrap()).unwrap()
+    });
+
+    for tv in tvs.hpke.into_iter() {
+        match (tv.kem_id, tv.kdf_id, tv.aead_id) {
+            (MlKem768::KEM_ID, HkdfSha256::KDF_ID, AesGcm128::AEAD_ID) => {
+                check_ml_kem_hpke::<AesGcm128, HkdfSha256, MlKem768>(tv)
+            }
+            (MlKem1024::KEM_ID, HkdfSha384::KDF_ID, AesGcm256::AEAD_ID) => {
+                check_ml_kem_hpke::<AesGcm256, HkdfSha384, MlKem1024>(tv)
+            }
+            (kem_id, kdf_id, aead_id) => panic!(
+                "Unexpected ML-KEM suite: ({}, {}, {})",
+                kem_id, kdf_id, aead_id
+            ),
+        }
     }
 }
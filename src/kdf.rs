@@ -1,11 +1,12 @@
-use crate::{prelude::*, util::static_zeros};
+use crate::prelude::*;
 
 use byteorder::{BigEndian, WriteBytesExt};
 use digest::{generic_array::GenericArray, BlockInput, Digest, FixedOutput, Input, Reset};
 use sha2::{Sha256, Sha384, Sha512};
+use zeroize::Zeroizing;
 
-// This has a space because LabeledExtract calls for a space between the RFC string and the label
-const RFC_STR: &[u8] = b"RFCXXXX ";
+// RFC 9180 §4: Every labeled KDF input is prefixed with this version string
+pub(crate) const VERSION_LABEL: &[u8] = b"HPKE-v1";
 
 // Pretty much all the KDF functionality is covered by the hkdf crate
 
@@ -27,7 +28,7 @@ pub struct HkdfSha256 {}
 impl KdfTrait for HkdfSha256 {
     type HashImpl = Sha256;
 
-    // draft02 §8.2: HKDF-SHA256
+    // RFC 9180 §7.2: HKDF-SHA256
     const KDF_ID: u16 = 0x0001;
 }
 
@@ -37,7 +38,7 @@ pub struct HkdfSha384 {}
 impl KdfTrait for HkdfSha384 {
     type HashImpl = Sha384;
 
-    // draft02 §8.2: HKDF-SHA384
+    // RFC 9180 §7.2: HKDF-SHA384
     const KDF_ID: u16 = 0x0002;
 }
 
@@ -47,50 +48,54 @@ pub struct HkdfSha512 {}
 impl KdfTrait for HkdfSha512 {
     type HashImpl = Sha512;
 
-    // draft02 §8.2: HKDF-SHA512
+    // RFC 9180 §7.2: HKDF-SHA512
     const KDF_ID: u16 = 0x0003;
 }
 
-// def ExtractAndExpand(dh, kemContext):
-//   prk = LabeledExtract(zero(Nh), "dh", dh)
-//   return LabeledExpand(prk, "prk", kemContext, Nzz)
-/// Uses the given IKM to extract a secret, and then uses that secret, plus the given info string,
-/// to expand to the output buffer
+// def ExtractAndExpand(dh, kem_context):
+//   eae_prk = LabeledExtract("", "eae_prk", dh)
+//   shared_secret = LabeledExpand(eae_prk, "shared_secret",
+//                                 kem_context, Nsecret)
+//   return shared_secret
+/// Uses the given DH result to extract a secret, and then uses that secret, plus the given KEM
+/// context, to expand to the output buffer. `suite_id` is the KEM's suite ID.
 pub(crate) fn extract_and_expand<Kdf: KdfTrait>(
-    ikm: &[u8],
-    info: &[u8],
+    suite_id: &[u8],
+    dh: &[u8],
+    kem_context: &[u8],
     out: &mut [u8],
 ) -> Result<(), hkdf::InvalidLength> {
-    // The salt is a zero array of length Nh
-    let salt = static_zeros::<Kdf>();
-    // Extract using given IKM
-    let (_, hkdf_ctx) = labeled_extract::<Kdf>(&salt, b"dh", ikm);
-    // Expand using given info string
-    hkdf_ctx.labeled_expand(b"prk", info, out)
+    // Extract using given DH result. The salt is empty.
+    let (_, hkdf_ctx) = labeled_extract::<Kdf>(b"", suite_id, b"eae_prk", dh);
+    // Expand using given KEM context
+    hkdf_ctx.labeled_expand(suite_id, b"shared_secret", kem_context, out)
 }
 
-// def LabeledExtract(salt, label, IKM):
-//   labeledIKM = concat("RFCXXXX ", label, IKM)
-//   return Extract(salt, labeledIKM)
-/// Returns the HKDF context derived from `(salt=salt, ikm= "RFCXXXX"||label||ikm)`
+// def LabeledExtract(salt, label, ikm):
+//   labeled_ikm = concat("HPKE-v1", suite_id, label, ikm)
+//   return Extract(salt, labeled_ikm)
+/// Returns the HKDF context derived from `(salt=salt, ikm="HPKE-v1"||suite_id||label||ikm)`
 pub(crate) fn labeled_extract<Kdf: KdfTrait>(
     salt: &[u8],
+    suite_id: &[u8],
     label: &[u8],
     ikm: &[u8],
 ) -> (
     GenericArray<u8, <<Kdf as KdfTrait>::HashImpl as FixedOutput>::OutputSize>,
     hkdf::Hkdf<Kdf::HashImpl>,
 ) {
-    // Concat the inputs to create a new IKM
-    let labeled_ikm: Vec<u8> = [RFC_STR, label, ikm].concat();
+    // Concat the inputs to create a new IKM. The IKM is usually secret, so this gets wiped too.
+    let labeled_ikm: Zeroizing<Vec<u8>> =
+        Zeroizing::new([VERSION_LABEL, suite_id, label, ikm].concat());
     // Extract and the HKDF context
-    hkdf::Hkdf::<Kdf::HashImpl>::extract(Some(&salt), &labeled_ikm)
+    hkdf::Hkdf::<Kdf::HashImpl>::extract(Some(salt), &labeled_ikm)
 }
 
 // This trait only exists so I can implement it for hkdf::Hkdf
 pub(crate) trait LabeledExpand {
     fn labeled_expand(
         &self,
+        suite_id: &[u8],
         label: &[u8],
         info: &[u8],
         out: &mut [u8],
@@ -100,18 +105,22 @@ pub(crate) trait LabeledExpand {
 impl<D: Input + BlockInput + FixedOutput + Reset + Default + Clone> LabeledExpand
     for hkdf::Hkdf<D>
 {
-    // def LabeledExpand(PRK, label, info, L):
-    //   labeledInfo = concat(encode_big_endian(L, 2),
-    //                         "RFCXXXX ", label, info)
-    //   return Expand(PRK, labeledInfo, L)
+    // def LabeledExpand(prk, label, info, L):
+    //   labeled_info = concat(I2OSP(L, 2), "HPKE-v1", suite_id,
+    //                         label, info)
+    //   return Expand(prk, labeled_info, L)
     fn labeled_expand(
         &self,
+        suite_id: &[u8],
         label: &[u8],
         info: &[u8],
         out: &mut [u8],
     ) -> Result<(), hkdf::InvalidLength> {
-        // We need to write the length as a u16, so that's the de-facto upper bound on length
-        assert!(out.len() <= u16::MAX as usize);
+        // We need to write the length as a u16, so that's the de-facto upper bound on length. HKDF
+        // caps it lower than that anyway.
+        if out.len() > u16::MAX as usize {
+            return Err(hkdf::InvalidLength);
+        }
 
         // Encode the output length in the info string
         let mut len_buf = [0u8; 2];
@@ -119,7 +128,7 @@ impl<D: Input + BlockInput + FixedOutput + Reset + Default + Clone> LabeledExpan
             .write_u16::<BigEndian>(out.len() as u16)
             .unwrap();
 
-        let labeled_info: Vec<u8> = [&len_buf, RFC_STR, label, info].concat();
+        let labeled_info: Vec<u8> = [&len_buf, VERSION_LABEL, suite_id, label, info].concat();
         self.expand(&labeled_info, out)
     }
 }
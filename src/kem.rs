@@ -1,39 +1,185 @@
 use crate::{
-    kdf::{extract_and_expand, HkdfSha256, Kdf as KdfTrait},
-    kex::{KeyExchange, Marshallable, Unmarshallable, X25519},
+    kdf::{extract_and_expand, HkdfSha256, HkdfSha384, HkdfSha512, Kdf as KdfTrait},
+    kex::{KeyExchange, Marshallable, Unmarshallable, P256, P384, P521, X25519, X448},
+    util::{kem_suite_id, HexFmt},
     HpkeError,
 };
-use digest::generic_array::GenericArray;
+use core::{fmt, fmt::Debug, marker::PhantomData};
+use digest::{
+    generic_array::{typenum, ArrayLength, GenericArray},
+    Digest,
+};
 use rand::{CryptoRng, RngCore};
+use subtle::{Choice, ConstantTimeEq};
+use zeroize::{Zeroize, ZeroizeOnDrop, Zeroizing};
+
+/// Represents key encapsulation functionality (RFC 9180 §4). A KEM has a way to generate keypairs,
+/// and a way to encapsulate a fresh shared secret to a public key, which the holder of the
+/// matching private key can decapsulate. KEMs that support it can also tie the sender's identity
+/// to the shared secret. Private keys are secret, so they must be wiped when they're dropped, and
+/// must not be printed.
+pub trait Kem: Sized {
+    type PublicKey: Clone + ConstantTimeEq + Debug + Marshallable + Unmarshallable;
+    type PrivateKey: Clone + ConstantTimeEq + Debug + Marshallable + Unmarshallable + ZeroizeOnDrop;
+
+    /// The encapsulated key that the sender transmits to the recipient. This is `enc` in the spec.
+    type EncappedKey: ConstantTimeEq + Debug + Marshallable + Unmarshallable;
 
-/// Defines a combination of key exchange mechanism and a KDF, which together form a KEM
-pub trait Kem {
-    type Kex: KeyExchange;
-    type Kdf: KdfTrait;
+    /// The sender's identity keys in the auth modes. For a KEM with no `AuthEncap`, these should be
+    /// uninhabited types, so that an auth mode can't be constructed for it.
+    type AuthPrivateKey: Clone + Debug + Marshallable + Unmarshallable + ZeroizeOnDrop;
+    type AuthPublicKey: Clone + Debug + Marshallable + Unmarshallable;
+
+    /// The length of the shared secret output by encapsulation. This is `Nsecret` in the spec.
+    type NSecret: ArrayLength<u8>;
 
     const KEM_ID: u16;
+
+    fn gen_keypair<R: CryptoRng + RngCore>(csprng: &mut R) -> (Self::PrivateKey, Self::PublicKey);
+
+    /// Deterministically derives a keypair from the given input keying material. The IKM should
+    /// have at least as much entropy as a private key. This is `DeriveKeyPair` in the spec.
+    fn derive_keypair(ikm: &[u8]) -> (Self::PrivateKey, Self::PublicKey);
+
+    fn sk_to_pk(sk: &Self::PrivateKey) -> Self::PublicKey;
+
+    /// Derives a shared secret, and an encapsulated key that the owner of `pk_recip` can use to
+    /// derive the same shared secret. This is `Encap` in the spec.
+    ///
+    /// Return Value
+    /// ============
+    /// Returns a shared secret and encapped key on success. If an error happened during
+    /// encapsulation, returns `Err(HpkeError::InvalidKeyExchange)`.
+    fn encap<R: CryptoRng + RngCore>(
+        pk_recip: &Self::PublicKey,
+        csprng: &mut R,
+    ) -> Result<(SharedSecret<Self>, Self::EncappedKey), HpkeError>;
+
+    /// Derives the shared secret that was encapsulated to the public key of `sk_recip`. This is
+    /// `Decap` in the spec.
+    ///
+    /// Return Value
+    /// ============
+    /// Returns a shared secret on success. If an error happened during decapsulation, returns
+    /// `Err(HpkeError::InvalidKeyExchange)`.
+    fn decap(
+        sk_recip: &Self::PrivateKey,
+        encapped_key: &Self::EncappedKey,
+    ) -> Result<SharedSecret<Self>, HpkeError>;
+
+    /// Like `encap`, but also ties the sender's identity to the shared secret. This is
+    /// `AuthEncap` in the spec.
+    ///
+    /// Return Value
+    /// ============
+    /// Same as `encap`.
+    fn auth_encap<R: CryptoRng + RngCore>(
+        pk_recip: &Self::PublicKey,
+        sender_id_keypair: &(Self::AuthPrivateKey, Self::AuthPublicKey),
+        csprng: &mut R,
+    ) -> Result<(SharedSecret<Self>, Self::EncappedKey), HpkeError>;
+
+    /// Like `decap`, but also checks that the secret was encapsulated by the owner of
+    /// `pk_sender_id`. This is `AuthDecap` in the spec.
+    ///
+    /// Return Value
+    /// ============
+    /// Same as `decap`.
+    fn auth_decap(
+        sk_recip: &Self::PrivateKey,
+        pk_sender_id: &Self::AuthPublicKey,
+        encapped_key: &Self::EncappedKey,
+    ) -> Result<SharedSecret<Self>, HpkeError>;
 }
 
 // Kem is also used as a type parameter everywhere. To avoid confusion, alias it
 use Kem as KemTrait;
 
-/// Represents DHKEM(Curve25519, HKDF-SHA256)
-pub struct X25519HkdfSha256 {}
+pub mod ml_kem;
+pub use self::ml_kem::{MlKem1024, MlKem768};
 
-impl Kem for X25519HkdfSha256 {
-    type Kex = X25519;
-    type Kdf = HkdfSha256;
+/// The sender identity key type of a KEM with no `AuthEncap`. This has no values, so the auth
+/// modes can't be constructed for such a KEM:
+///
+/// ```compile_fail
+/// use hpke::{kdf::HkdfSha256, kem::MlKem768, Kem, OpModeS};
+///
+/// let mut csprng = rand::thread_rng();
+/// let (sk, pk) = MlKem768::gen_keypair(&mut csprng);
+/// let mode = OpModeS::<MlKem768, HkdfSha256>::Auth((sk, pk));
+/// ```
+#[derive(Clone, Debug)]
+pub enum NoAuth {}
+
+impl Marshallable for NoAuth {
+    type OutputSize = typenum::U0;
+
+    fn marshal(&self) -> GenericArray<u8, typenum::U0> {
+        match *self {}
+    }
+}
 
+impl Unmarshallable for NoAuth {
+    // There's nothing to decode to, so this always fails
+    fn unmarshal(_: &[u8]) -> Result<Self, HpkeError> {
+        Err(HpkeError::InvalidKeyExchange)
+    }
+}
+
+// There's nothing to wipe
+impl ZeroizeOnDrop for NoAuth {}
+
+/// The KEM ID of the DHKEM made from this key exchange and the KDF `Kdf`. Every `KeyExchange` in
+/// this crate has one for the KDF that RFC 9180 §7.1 pairs it with.
+pub trait DhKemId<Kdf: KdfTrait>: KeyExchange {
+    const KEM_ID: u16;
+}
+
+/// The DH-based KEM of RFC 9180 §4.1, made from a key exchange and a KDF
+pub struct DhKem<Kex: KeyExchange, Kdf: KdfTrait>(PhantomData<(Kex, Kdf)>);
+
+/// Represents DHKEM(Curve25519, HKDF-SHA256)
+pub type X25519HkdfSha256 = DhKem<X25519, HkdfSha256>;
+
+impl DhKemId<HkdfSha256> for X25519 {
     // Section 7.1: DHKEM(Curve25519, HKDF-SHA256)
     const KEM_ID: u16 = 0x0020;
 }
 
-/// Convenience types representing public/private keys corresponding to a KEM's underlying DH alg
-type KemPubkey<Kem> = <<Kem as KemTrait>::Kex as KeyExchange>::PublicKey;
-type KemPrivkey<Kem> = <<Kem as KemTrait>::Kex as KeyExchange>::PrivateKey;
+/// Represents DHKEM(Curve448, HKDF-SHA512)
+pub type X448HkdfSha512 = DhKem<X448, HkdfSha512>;
+
+impl DhKemId<HkdfSha512> for X448 {
+    // Section 7.1: DHKEM(Curve448, HKDF-SHA512)
+    const KEM_ID: u16 = 0x0021;
+}
+
+/// Represents DHKEM(P-256, HKDF-SHA256)
+pub type DhP256HkdfSha256 = DhKem<P256, HkdfSha256>;
+
+impl DhKemId<HkdfSha256> for P256 {
+    // Section 7.1: DHKEM(P-256, HKDF-SHA256)
+    const KEM_ID: u16 = 0x0010;
+}
+
+/// Represents DHKEM(P-384, HKDF-SHA384)
+pub type DhP384HkdfSha384 = DhKem<P384, HkdfSha384>;
 
-/// This holds the content of an encapsulated secret. It is output by the `encap` and `encap_auth`
-/// functions.
+impl DhKemId<HkdfSha384> for P384 {
+    // Section 7.1: DHKEM(P-384, HKDF-SHA384)
+    const KEM_ID: u16 = 0x0011;
+}
+
+/// Represents DHKEM(P-521, HKDF-SHA512)
+pub type DhP521HkdfSha512 = DhKem<P521, HkdfSha512>;
+
+impl DhKemId<HkdfSha512> for P521 {
+    // Section 7.1: DHKEM(P-521, HKDF-SHA512)
+    const KEM_ID: u16 = 0x0012;
+}
+
+/// This holds the content of an encapsulated secret in a DHKEM. It is output by `DhKem::encap` and
+/// `DhKem::auth_encap`.
 // This just wraps a pubkey, because that's all an encapsulated key is in a DH-KEM
 pub struct EncappedKey<Kex: KeyExchange>(Kex::PublicKey);
 
@@ -48,6 +194,21 @@ impl<Kex: KeyExchange> Marshallable for EncappedKey<Kex> {
     }
 }
 
+impl<Kex: KeyExchange> ConstantTimeEq for EncappedKey<Kex> {
+    fn ct_eq(&self, other: &EncappedKey<Kex>) -> Choice {
+        self.0.ct_eq(&other.0)
+    }
+}
+
+// Encapped keys are public, so they're printed in hex
+impl<Kex: KeyExchange> fmt::Debug for EncappedKey<Kex> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_tuple("EncappedKey")
+            .field(&HexFmt(&self.marshal()))
+            .finish()
+    }
+}
+
 impl<Kex: KeyExchange> Unmarshallable for EncappedKey<Kex> {
     // Pass to underlying unmarshal() impl
     fn unmarshal(encoded: &[u8]) -> Result<Self, HpkeError> {
@@ -56,198 +217,294 @@ impl<Kex: KeyExchange> Unmarshallable for EncappedKey<Kex> {
     }
 }
 
-/// A convenience type representing the fixed-size byte array of the same length as a serialized
-/// `KexResult`
-pub(crate) type SharedSecret<Kex> =
-    GenericArray<u8, <<Kex as KeyExchange>::KexResult as Marshallable>::OutputSize>;
-
-//  def Encap(pkR):
-//    skE, pkE = GenerateKeyPair()
-//    dh = DH(skE, pkR)
-//    enc = Marshal(pkE)
-//
-//    pkRm = Marshal(pkR)
-//    kemContext = concat(enc, pkRm)
-//
-//    zz = ExtractAndExpand(dh, kemContext)
-//    return zz, enc
-//
-// def AuthEncap(pkR, skS):
-//   skE, pkE = GenerateKeyPair()
-//   dh = concat(DH(skE, pkR), DH(skS, pkR))
-//   enc = Marshal(pkE)
-//
-//   pkRm = Marshal(pkR)
-//   pkSm = Marshal(pk(skS))
-//   kemContext = concat(enc, pkRm, pkSm)
-//
-//   zz = ExtractAndExpand(dh, kemContext)
-//   return zz, enc
-/// Derives a shared secret that the owner of the reciepint's pubkey can use to derive the same
-/// shared secret. If `sk_sender_id` is given, the sender's identity will be tied to the shared
-/// secret.
-///
-/// Return Value
-/// ============
-/// Returns a shared secret and encapped key on success. If an error happened during key exchange,
-/// returns `Err(HpkeError::InvalidKeyExchange)`.
-pub(crate) fn encap_with_eph<Kem: KemTrait>(
-    pk_recip: &KemPubkey<Kem>,
-    sender_id_keypair: Option<&(KemPrivkey<Kem>, KemPubkey<Kem>)>,
-    sk_eph: KemPrivkey<Kem>,
-) -> Result<(SharedSecret<Kem::Kex>, EncappedKey<Kem::Kex>), HpkeError> {
-    // Compute the shared secret from the ephemeral inputs
-    let kex_res_eph = Kem::Kex::kex(&sk_eph, pk_recip)?;
-
-    // The encapped key is the ephemeral pubkey
-    let encapped_key = {
-        let pk_eph = Kem::Kex::sk_to_pk(&sk_eph);
-        EncappedKey(pk_eph)
-    };
-
-    // The shared secret is either gonna be kex_res_eph, or that along with another shared secret
-    // that's tied to the sender's identity.
-    let shared_secret = if let Some((sk_sender_id, pk_sender_id)) = sender_id_keypair {
-        let kem_context = [
-            encapped_key.marshal(),
-            pk_recip.marshal(),
-            pk_sender_id.marshal(),
-        ]
-        .concat();
-        // We want to do an authed encap. Do KEX between the sender identity secret key and the
-        // recipient's pubkey
-        let kex_res_identity = Kem::Kex::kex(sk_sender_id, pk_recip)?;
-        // kex_res_eph || kex_res_identity
-        let concatted_secrets = [kex_res_eph.marshal(), kex_res_identity.marshal()].concat();
-
-        // The "authed shared secret" is derived from the KEX of the ephemeral input with the
-        // recipient pubkey, and the KEX of the identity input with the recipient pubkey. The
-        // HKDF-Expand call only errors if the output values are 255x the digest size of the hash
-        // function. Since these values are fixed at compile time, we don't worry about it.
-        let mut buf = <SharedSecret<Kem::Kex> as Default>::default();
-        extract_and_expand::<Kem::Kdf>(&concatted_secrets, &kem_context, &mut buf)
-            .expect("shared secret is way too big");
-        buf
-    } else {
-        let kem_context = [encapped_key.marshal(), pk_recip.marshal()].concat();
-        // The "unauthed shared secret" is derived from just the KEX of the ephemeral input with
-        // the recipient pubkey. The HKDF-Expand call only errors if the output values are 255x the
-        // digest size of the hash function. Since these values are fixed at compile time, we don't
-        // worry about it.
-        let mut buf = <SharedSecret<Kem::Kex> as Default>::default();
-        extract_and_expand::<Kem::Kdf>(&kex_res_eph.marshal(), &kem_context, &mut buf)
-            .expect("shared secret is way too big");
-        buf
-    };
+/// The fixed-size byte array of the shared secret output by a KEM. Its length is `Nsecret`, which
+/// is not necessarily the length of a serialized `KexResult`. This is wiped when it's dropped.
+pub struct SharedSecret<Kem: KemTrait>(pub GenericArray<u8, Kem::NSecret>);
 
-    Ok((shared_secret, encapped_key))
+impl<Kem: KemTrait> Default for SharedSecret<Kem> {
+    fn default() -> SharedSecret<Kem> {
+        SharedSecret(GenericArray::default())
+    }
 }
 
-/// Derives a shared secret and an ephemeral pubkey that the owner of the reciepint's pubkey can
-/// use to derive the same shared secret. If `sk_sender_id` is given, the sender's identity will be
-/// tied to the shared secret.
-/// All this does is generate an ephemeral keypair and pass to `encap_with_eph`.
-///
-/// Return Value
-/// ============
-/// Returns a shared secret and encapped key on success. If an error happened during key exchange,
-/// returns `Err(HpkeError::InvalidKeyExchange)`.
-pub(crate) fn encap<Kem, R>(
-    pk_recip: &KemPubkey<Kem>,
-    sender_id_keypair: Option<&(KemPrivkey<Kem>, KemPubkey<Kem>)>,
-    csprng: &mut R,
-) -> Result<(SharedSecret<Kem::Kex>, EncappedKey<Kem::Kex>), HpkeError>
-where
-    Kem: KemTrait,
-    R: CryptoRng + RngCore,
-{
-    // Generate a new ephemeral keypair
-    let (sk_eph, _) = Kem::Kex::gen_keypair(csprng);
-    // Now pass to encap_with_eph
-    encap_with_eph::<Kem>(pk_recip, sender_id_keypair, sk_eph)
+impl<Kem: KemTrait> Zeroize for SharedSecret<Kem> {
+    fn zeroize(&mut self) {
+        self.0.as_mut_slice().zeroize();
+    }
 }
 
-// def Decap(enc, skR):
-//   pkE = Unmarshal(enc)
-//   dh = DH(skR, pkE)
-//
-//   pkRm = Marshal(pk(skR))
-//   kemContext = concat(enc, pkRm)
-//
-//   zz = ExtractAndExpand(dh, kemContext)
-//   return zz
-//
-// def AuthDecap(enc, skR, pkS):
-//   pkE = Unmarshal(enc)
-//   dh = concat(DH(skR, pkE), DH(skR, pkS))
-//
-//   pkRm = Marshal(pk(skR))
-//   pkSm = Marshal(pkS)
-//   kemContext = concat(enc, pkRm, pkSm)
-//
-//   zz = ExtractAndExpand(dh, kemContext)
-//   return zz
-/// Derives a shared secret given the encapsulated key and the recipients secret key. If
-/// `pk_sender_id` is given, the sender's identity will be tied to the shared secret.
-///
-/// Return Value
-/// ============
-/// Returns a shared secret on success. If an error happened during key exchange, returns
-/// `Err(HpkeError::InvalidKeyExchange)`.
-pub(crate) fn decap<Kem: KemTrait>(
-    sk_recip: &KemPrivkey<Kem>,
-    pk_sender_id: Option<&KemPubkey<Kem>>,
-    encapped_key: &EncappedKey<Kem::Kex>,
-) -> Result<SharedSecret<Kem::Kex>, HpkeError> {
-    // Compute the shared secret from the ephemeral inputs
-    let kex_res_eph = Kem::Kex::kex(&sk_recip, &encapped_key.0)?;
-
-    // Compute the sender's pubkey from their privkey
-    let pk_recip = Kem::Kex::sk_to_pk(sk_recip);
-
-    // The shared secret is either gonna be kex_res_eph, or that along with another shared secret
-    // that's tied to the sender's identity.
-    if let Some(pk_sender_id) = pk_sender_id {
-        let kem_context = [
-            encapped_key.marshal(),
-            pk_recip.marshal(),
-            pk_sender_id.marshal(),
-        ]
-        .concat();
-        // We want to do an authed encap. Do KEX between the sender identity secret key and the
-        // recipient's pubkey
-        let kex_res_identity = Kem::Kex::kex(sk_recip, pk_sender_id)?;
-        // kex_res_eph || kex_res_identity
-        let concatted_secrets = [kex_res_eph.marshal(), kex_res_identity.marshal()].concat();
-
-        // The "authed shared secret" is derived from the KEX of the ephemeral input with the
-        // recipient pubkey, and the kex of the identity input with the recipient pubkey. The
-        // HKDF-Expand call only errors if the output values are 255x the digest size of the hash
-        // function. Since these values are fixed at compile time, we don't worry about it.
-        let mut shared_secret = <SharedSecret<Kem::Kex> as Default>::default();
-        extract_and_expand::<Kem::Kdf>(&concatted_secrets, &kem_context, &mut shared_secret)
+impl<Kem: KemTrait> Drop for SharedSecret<Kem> {
+    fn drop(&mut self) {
+        self.zeroize();
+    }
+}
+
+impl<Kem: KemTrait> ZeroizeOnDrop for SharedSecret<Kem> {}
+
+impl<Kex: DhKemId<Kdf>, Kdf: KdfTrait> Kem for DhKem<Kex, Kdf> {
+    type PublicKey = Kex::PublicKey;
+    type PrivateKey = Kex::PrivateKey;
+    type EncappedKey = EncappedKey<Kex>;
+
+    // In a DHKEM, the sender's identity is just another keypair of the same group
+    type AuthPrivateKey = Kex::PrivateKey;
+    type AuthPublicKey = Kex::PublicKey;
+
+    // RFC 9180 §7.1: Nsecret is the output size of the KDF's hash for every DHKEM
+    type NSecret = <Kdf::HashImpl as Digest>::OutputSize;
+
+    const KEM_ID: u16 = <Kex as DhKemId<Kdf>>::KEM_ID;
+
+    fn gen_keypair<R: CryptoRng + RngCore>(csprng: &mut R) -> (Kex::PrivateKey, Kex::PublicKey) {
+        Kex::gen_keypair(csprng)
+    }
+
+    fn derive_keypair(ikm: &[u8]) -> (Kex::PrivateKey, Kex::PublicKey) {
+        // RFC 9180 §7.1.3: The labeled KDF calls use the KEM's KDF and suite ID
+        Kex::derive_keypair::<Kdf>(&kem_suite_id::<Self>(), ikm)
+    }
+
+    fn sk_to_pk(sk: &Kex::PrivateKey) -> Kex::PublicKey {
+        Kex::sk_to_pk(sk)
+    }
+
+    fn encap<R: CryptoRng + RngCore>(
+        pk_recip: &Kex::PublicKey,
+        csprng: &mut R,
+    ) -> Result<(SharedSecret<Self>, EncappedKey<Kex>), HpkeError> {
+        // Generate a new ephemeral keypair
+        let (sk_eph, _) = Kex::gen_keypair(csprng);
+        Self::encap_with_eph(pk_recip, None, sk_eph)
+    }
+
+    fn decap(
+        sk_recip: &Kex::PrivateKey,
+        encapped_key: &EncappedKey<Kex>,
+    ) -> Result<SharedSecret<Self>, HpkeError> {
+        Self::decap_with_sender_id(sk_recip, None, encapped_key)
+    }
+
+    fn auth_encap<R: CryptoRng + RngCore>(
+        pk_recip: &Kex::PublicKey,
+        sender_id_keypair: &(Kex::PrivateKey, Kex::PublicKey),
+        csprng: &mut R,
+    ) -> Result<(SharedSecret<Self>, EncappedKey<Kex>), HpkeError> {
+        // Generate a new ephemeral keypair
+        let (sk_eph, _) = Kex::gen_keypair(csprng);
+        Self::encap_with_eph(pk_recip, Some(sender_id_keypair), sk_eph)
+    }
+
+    fn auth_decap(
+        sk_recip: &Kex::PrivateKey,
+        pk_sender_id: &Kex::PublicKey,
+        encapped_key: &EncappedKey<Kex>,
+    ) -> Result<SharedSecret<Self>, HpkeError> {
+        Self::decap_with_sender_id(sk_recip, Some(pk_sender_id), encapped_key)
+    }
+}
+
+impl<Kex: DhKemId<Kdf>, Kdf: KdfTrait> DhKem<Kex, Kdf> {
+    //  def Encap(pkR):
+    //    skE, pkE = GenerateKeyPair()
+    //    dh = DH(skE, pkR)
+    //    enc = Marshal(pkE)
+    //
+    //    pkRm = Marshal(pkR)
+    //    kem_context = concat(enc, pkRm)
+    //
+    //    shared_secret = ExtractAndExpand(dh, kem_context)
+    //    return shared_secret, enc
+    //
+    // def AuthEncap(pkR, skS):
+    //   skE, pkE = GenerateKeyPair()
+    //   dh = concat(DH(skE, pkR), DH(skS, pkR))
+    //   enc = Marshal(pkE)
+    //
+    //   pkRm = Marshal(pkR)
+    //   pkSm = Marshal(pk(skS))
+    //   kem_context = concat(enc, pkRm, pkSm)
+    //
+    //   shared_secret = ExtractAndExpand(dh, kem_context)
+    //   return shared_secret, enc
+    /// Derives a shared secret that the owner of the reciepint's pubkey can use to derive the same
+    /// shared secret, using the given ephemeral private key. If `sender_id_keypair` is given, the
+    /// sender's identity will be tied to the shared secret.
+    ///
+    /// Return Value
+    /// ============
+    /// Returns a shared secret and encapped key on success. If an error happened during key
+    /// exchange, returns `Err(HpkeError::InvalidKeyExchange)`.
+    pub(crate) fn encap_with_eph(
+        pk_recip: &Kex::PublicKey,
+        sender_id_keypair: Option<&(Kex::PrivateKey, Kex::PublicKey)>,
+        sk_eph: Kex::PrivateKey,
+    ) -> Result<(SharedSecret<Self>, EncappedKey<Kex>), HpkeError> {
+        // Compute the shared secret from the ephemeral inputs
+        let kex_res_eph = Kex::kex(&sk_eph, pk_recip)?;
+
+        // The encapped key is the ephemeral pubkey
+        let encapped_key = {
+            let pk_eph = Kex::sk_to_pk(&sk_eph);
+            EncappedKey(pk_eph)
+        };
+
+        // The shared secret is either gonna be kex_res_eph, or that along with another shared
+        // secret that's tied to the sender's identity.
+        let shared_secret = if let Some((sk_sender_id, pk_sender_id)) = sender_id_keypair {
+            let kem_context = [
+                encapped_key.marshal(),
+                pk_recip.marshal(),
+                pk_sender_id.marshal(),
+            ]
+            .concat();
+            // We want to do an authed encap. Do KEX between the sender identity secret key and
+            // the recipient's pubkey
+            let kex_res_identity = Kex::kex(sk_sender_id, pk_recip)?;
+            // kex_res_eph || kex_res_identity. The marshalled halves are secret too, so they're
+            // wiped once they've been copied.
+            let (mut eph_bytes, mut identity_bytes) =
+                (kex_res_eph.marshal(), kex_res_identity.marshal());
+            let concatted_secrets =
+                Zeroizing::new([eph_bytes.as_slice(), identity_bytes.as_slice()].concat());
+            eph_bytes.as_mut_slice().zeroize();
+            identity_bytes.as_mut_slice().zeroize();
+
+            // The "authed shared secret" is derived from the KEX of the ephemeral input with the
+            // recipient pubkey, and the KEX of the identity input with the recipient pubkey. The
+            // HKDF-Expand call only errors if the output values are 255x the digest size of the
+            // hash function. Since these values are fixed at compile time, we don't worry about
+            // it.
+            let mut buf = <SharedSecret<Self> as Default>::default();
+            extract_and_expand::<Kdf>(
+                &kem_suite_id::<Self>(),
+                &concatted_secrets,
+                &kem_context,
+                &mut buf.0,
+            )
+            .expect("shared secret is way too big");
+            buf
+        } else {
+            let kem_context = [encapped_key.marshal(), pk_recip.marshal()].concat();
+            // The "unauthed shared secret" is derived from just the KEX of the ephemeral input
+            // with the recipient pubkey. The HKDF-Expand call only errors if the output values are
+            // 255x the digest size of the hash function. Since these values are fixed at compile
+            // time, we don't worry about it.
+            let mut eph_bytes = kex_res_eph.marshal();
+            let mut buf = <SharedSecret<Self> as Default>::default();
+            extract_and_expand::<Kdf>(
+                &kem_suite_id::<Self>(),
+                &eph_bytes,
+                &kem_context,
+                &mut buf.0,
+            )
+            .expect("shared secret is way too big");
+            eph_bytes.as_mut_slice().zeroize();
+            buf
+        };
+
+        Ok((shared_secret, encapped_key))
+    }
+
+    // def Decap(enc, skR):
+    //   pkE = Unmarshal(enc)
+    //   dh = DH(skR, pkE)
+    //
+    //   pkRm = Marshal(pk(skR))
+    //   kem_context = concat(enc, pkRm)
+    //
+    //   shared_secret = ExtractAndExpand(dh, kem_context)
+    //   return shared_secret
+    //
+    // def AuthDecap(enc, skR, pkS):
+    //   pkE = Unmarshal(enc)
+    //   dh = concat(DH(skR, pkE), DH(skR, pkS))
+    //
+    //   pkRm = Marshal(pk(skR))
+    //   pkSm = Marshal(pkS)
+    //   kem_context = concat(enc, pkRm, pkSm)
+    //
+    //   shared_secret = ExtractAndExpand(dh, kem_context)
+    //   return shared_secret
+    /// Derives a shared secret given the encapsulated key and the recipients secret key. If
+    /// `pk_sender_id` is given, the sender's identity will be tied to the shared secret.
+    ///
+    /// Return Value
+    /// ============
+    /// Returns a shared secret on success. If an error happened during key exchange, returns
+    /// `Err(HpkeError::InvalidKeyExchange)`.
+    fn decap_with_sender_id(
+        sk_recip: &Kex::PrivateKey,
+        pk_sender_id: Option<&Kex::PublicKey>,
+        encapped_key: &EncappedKey<Kex>,
+    ) -> Result<SharedSecret<Self>, HpkeError> {
+        // Compute the shared secret from the ephemeral inputs
+        let kex_res_eph = Kex::kex(sk_recip, &encapped_key.0)?;
+
+        // Compute the sender's pubkey from their privkey
+        let pk_recip = Kex::sk_to_pk(sk_recip);
+
+        // The shared secret is either gonna be kex_res_eph, or that along with another shared
+        // secret that's tied to the sender's identity.
+        if let Some(pk_sender_id) = pk_sender_id {
+            let kem_context = [
+                encapped_key.marshal(),
+                pk_recip.marshal(),
+                pk_sender_id.marshal(),
+            ]
+            .concat();
+            // We want to do an authed encap. Do KEX between the sender identity secret key and
+            // the recipient's pubkey
+            let kex_res_identity = Kex::kex(sk_recip, pk_sender_id)?;
+            // kex_res_eph || kex_res_identity. The marshalled halves are secret too, so they're
+            // wiped once they've been copied.
+            let (mut eph_bytes, mut identity_bytes) =
+                (kex_res_eph.marshal(), kex_res_identity.marshal());
+            let concatted_secrets =
+                Zeroizing::new([eph_bytes.as_slice(), identity_bytes.as_slice()].concat());
+            eph_bytes.as_mut_slice().zeroize();
+            identity_bytes.as_mut_slice().zeroize();
+
+            // The "authed shared secret" is derived from the KEX of the ephemeral input with the
+            // recipient pubkey, and the kex of the identity input with the recipient pubkey. The
+            // HKDF-Expand call only errors if the output values are 255x the digest size of the
+            // hash function. Since these values are fixed at compile time, we don't worry about
+            // it.
+            let mut shared_secret = <SharedSecret<Self> as Default>::default();
+            extract_and_expand::<Kdf>(
+                &kem_suite_id::<Self>(),
+                &concatted_secrets,
+                &kem_context,
+                &mut shared_secret.0,
+            )
             .expect("shared secret is way too big");
-        Ok(shared_secret)
-    } else {
-        let kem_context = [encapped_key.marshal(), pk_recip.marshal()].concat();
-        // The "unauthed shared secret" is derived from just the KEX of the ephemeral input with the
-        // recipient pubkey. The HKDF-Expand call only errors if the output values are 255x the
-        // digest size of the hash function. Since these values are fixed at compile time, we don't
-        // worry about it.
-        let mut shared_secret = <SharedSecret<Kem::Kex> as Default>::default();
-        extract_and_expand::<Kem::Kdf>(&kex_res_eph.marshal(), &kem_context, &mut shared_secret)
+            Ok(shared_secret)
+        } else {
+            let kem_context = [encapped_key.marshal(), pk_recip.marshal()].concat();
+            // The "unauthed shared secret" is derived from just the KEX of the ephemeral input
+            // with the recipient pubkey. The HKDF-Expand call only errors if the output values are
+            // 255x the digest size of the hash function. Since these values are fixed at compile
+            // time, we don't worry about it.
+            let mut eph_bytes = kex_res_eph.marshal();
+            let mut shared_secret = <SharedSecret<Self> as Default>::default();
+            extract_and_expand::<Kdf>(
+                &kem_suite_id::<Self>(),
+                &eph_bytes,
+                &kem_context,
+                &mut shared_secret.0,
+            )
             .expect("shared secret is way too big");
-        Ok(shared_secret)
+            eph_bytes.as_mut_slice().zeroize();
+            Ok(shared_secret)
+        }
     }
 }
 
 #[cfg(test)]
 mod tests {
-    use super::{decap, encap, EncappedKey, Marshallable, Unmarshallable};
-    use crate::{
-        kem::{Kem, X25519HkdfSha256},
-        kex::KeyExchange,
-    };
+    use super::{Marshallable, Unmarshallable};
+    use crate::kem::{Kem, X25519HkdfSha256};
+
+    use core::mem::ManuallyDrop;
 
     /// Tests that encap and decap produce the same shared secret when composed
     #[test]
@@ -255,39 +512,55 @@ mod tests {
         type Ke = X25519HkdfSha256;
 
         let mut csprng = rand::thread_rng();
-        let (sk_recip, pk_recip) = <Ke as Kem>::Kex::gen_keypair(&mut csprng);
+        let (sk_recip, pk_recip) = Ke::gen_keypair(&mut csprng);
 
         // Encapsulate a random shared secret
-        let (auth_shared_secret, encapped_key) =
-            encap::<Ke, _>(&pk_recip, None, &mut csprng).unwrap();
+        let (auth_shared_secret, encapped_key) = Ke::encap(&pk_recip, &mut csprng).unwrap();
 
         // Decap it
-        let decapped_auth_shared_secret = decap::<Ke>(&sk_recip, None, &encapped_key).unwrap();
+        let decapped_auth_shared_secret = Ke::decap(&sk_recip, &encapped_key).unwrap();
 
         // Ensure that the encapsulated secret is what decap() derives
-        assert_eq!(auth_shared_secret, decapped_auth_shared_secret);
+        assert_eq!(auth_shared_secret.0, decapped_auth_shared_secret.0);
 
         //
         // Now do it with the auth, i.e., using the sender's identity keys
         //
 
         // Make a sender identity keypair
-        let (sk_sender_id, pk_sender_id) = <Ke as Kem>::Kex::gen_keypair(&mut csprng);
+        let (sk_sender_id, pk_sender_id) = Ke::gen_keypair(&mut csprng);
 
         // Encapsulate a random shared secret
-        let (auth_shared_secret, encapped_key) = encap::<Ke, _>(
+        let (auth_shared_secret, encapped_key) = Ke::auth_encap(
             &pk_recip,
-            Some(&(sk_sender_id, pk_sender_id.clone())),
+            &(sk_sender_id, pk_sender_id.clone()),
             &mut csprng,
         )
         .unwrap();
 
         // Decap it
         let decapped_auth_shared_secret =
-            decap::<Ke>(&sk_recip, Some(&pk_sender_id), &encapped_key).unwrap();
+            Ke::auth_decap(&sk_recip, &pk_sender_id, &encapped_key).unwrap();
 
         // Ensure that the encapsulated secret is what decap() derives
-        assert_eq!(auth_shared_secret, decapped_auth_shared_secret);
+        assert_eq!(auth_shared_secret.0, decapped_auth_shared_secret.0);
+    }
+
+    /// Tests that a shared secret is wiped when it's dropped
+    #[test]
+    fn test_shared_secret_zeroize_on_drop() {
+        type Ke = X25519HkdfSha256;
+
+        let mut csprng = rand::thread_rng();
+        let (_, pk_recip) = Ke::gen_keypair(&mut csprng);
+        let (shared_secret, _) = Ke::encap(&pk_recip, &mut csprng).unwrap();
+
+        // Run the destructor without freeing the memory, so we can look at what it left behind
+        let mut shared_secret = ManuallyDrop::new(shared_secret);
+        assert!(shared_secret.0.iter().any(|&b| b != 0));
+        // Safety: the secret is never dropped again, and a byte array is valid after its destructor
+        unsafe { core::ptr::drop_in_place(&mut *shared_secret) };
+        assert!(shared_secret.0.iter().all(|&b| b == 0));
     }
 
     /// Tests that an unmarshal-marshal round-trip on an encapped key ends up at the same value
@@ -298,14 +571,13 @@ mod tests {
         // Encapsulate a random shared secret
         let encapped_key = {
             let mut csprng = rand::thread_rng();
-            let (_, pk_recip) = <Ke as Kem>::Kex::gen_keypair(&mut csprng);
-            encap::<Ke, _>(&pk_recip, None, &mut csprng).unwrap().1
+            let (_, pk_recip) = Ke::gen_keypair(&mut csprng);
+            Ke::encap(&pk_recip, &mut csprng).unwrap().1
         };
         // Marshal it
         let encapped_key_bytes = encapped_key.marshal();
         // Unmarshal it
-        let new_encapped_key =
-            EncappedKey::<<Ke as Kem>::Kex>::unmarshal(&encapped_key_bytes).unwrap();
+        let new_encapped_key = <Ke as Kem>::EncappedKey::unmarshal(&encapped_key_bytes).unwrap();
 
         assert!(
             new_encapped_key.0 == encapped_key.0,
@@ -0,0 +1,463 @@
+// ML-KEM (FIPS 203) is implemented by the RustCrypto ml-kem crate. That uses hybrid-array rather
+// than our generic_array, so everything gets converted through byte slices. The parameter sets only
+// differ in their sizes, so we implement all of them with one macro.
+
+pub use self::{ml_kem_1024::MlKem1024, ml_kem_768::MlKem768};
+
+// Implements a `Kem` for an ML-KEM parameter set. The parameters are:
+//   * $mod_name: the name of the module to put everything in
+//   * $kem_name: the name of the `Kem` type
+//   * $param_name: the parameter set's name, for documentation
+//   * $inner: the ml-kem crate's type for the parameter set
+//   * $pubkey_size: the size of an encoded encapsulation key (Npk)
+//   * $encapped_key_size: the size of a ciphertext (Nenc)
+//   * $kem_id: the KEM's ID in the HPKE KEM registry
+macro_rules! ml_kem {
+    (
+        $mod_name:ident,
+        $kem_name:ident,
+        $param_name:expr,
+        $inner:ty,
+        $pubkey_size:ty,
+        $encapped_key_size:ty,
+        $kem_id:expr
+    ) => {
+        pub mod $mod_name {
+            use crate::{
+                kdf::VERSION_LABEL,
+                kem::{Kem, NoAuth, SharedSecret},
+                kex::{Marshallable, Unmarshallable},
+                util::{kem_suite_id, marshalled_ct_eq, HexFmt, Redacted},
+                HpkeError,
+            };
+
+            use core::{convert::TryFrom, fmt};
+            use digest::generic_array::{typenum, GenericArray};
+            use ml_kem::{
+                array::Array, kem::Decapsulate, Ciphertext, EncapsulateDeterministic, Encoded,
+                EncodedSizeUser, KemCore, B32,
+            };
+            use rand::{CryptoRng, RngCore};
+            use sha3::{
+                digest::{ExtendableOutput, Update, XofReader},
+                Shake256,
+            };
+            use subtle::{Choice, ConstantTimeEq};
+            use zeroize::{Zeroize, ZeroizeOnDrop};
+
+            type DecapsulationKey = <$inner as KemCore>::DecapsulationKey;
+            type EncapsulationKey = <$inner as KemCore>::EncapsulationKey;
+
+            #[doc = concat!("An ", $param_name, " public key, i.e., an encapsulation key")]
+            #[derive(Clone)]
+            pub struct PublicKey(EncapsulationKey);
+
+            #[doc = concat!(
+                "An ", $param_name, " private key. This is the 64-byte seed `d || z` that the ",
+                "decapsulation key is expanded from."
+            )]
+            #[derive(Clone)]
+            pub struct PrivateKey {
+                seed: GenericArray<u8, typenum::U64>,
+                dk: DecapsulationKey,
+            }
+
+            #[doc = concat!(
+                "The encapsulated key of ", $param_name, ", i.e., an ML-KEM ciphertext. It is ",
+                "output by `", stringify!($kem_name), "::encap`."
+            )]
+            pub struct EncappedKey(Ciphertext<$inner>);
+
+            // The underlying DecapsulationKey wipes itself when it's dropped, so we only need to
+            // wipe the seed
+            impl Drop for PrivateKey {
+                fn drop(&mut self) {
+                    self.seed.as_mut_slice().zeroize();
+                }
+            }
+
+            impl ZeroizeOnDrop for PrivateKey {}
+
+            impl PrivateKey {
+                // FIPS 203 §6.1: ML-KEM.KeyGen_internal(d, z), where the seed is d || z
+                /// Expands a seed into a keypair
+                fn from_seed(seed: GenericArray<u8, typenum::U64>) -> (PrivateKey, PublicKey) {
+                    let mut d = B32::try_from(&seed[..32]).expect("d is 32 bytes");
+                    let mut z = B32::try_from(&seed[32..]).expect("z is 32 bytes");
+                    let (dk, ek) = <$inner as KemCore>::generate_deterministic(&d, &z);
+                    d.as_mut_slice().zeroize();
+                    z.as_mut_slice().zeroize();
+
+                    (PrivateKey { seed, dk }, PublicKey(ek))
+                }
+            }
+
+            impl ConstantTimeEq for PublicKey {
+                fn ct_eq(&self, other: &PublicKey) -> Choice {
+                    marshalled_ct_eq(self, other)
+                }
+            }
+
+            impl ConstantTimeEq for PrivateKey {
+                fn ct_eq(&self, other: &PrivateKey) -> Choice {
+                    marshalled_ct_eq(self, other)
+                }
+            }
+
+            impl ConstantTimeEq for EncappedKey {
+                fn ct_eq(&self, other: &EncappedKey) -> Choice {
+                    marshalled_ct_eq(self, other)
+                }
+            }
+
+            impl fmt::Debug for PublicKey {
+                fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+                    f.debug_tuple("PublicKey")
+                        .field(&HexFmt(&self.marshal()))
+                        .finish()
+                }
+            }
+
+            impl fmt::Debug for PrivateKey {
+                fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+                    f.debug_tuple("PrivateKey").field(&Redacted).finish()
+                }
+            }
+
+            // Encapped keys are public, so they're printed in hex
+            impl fmt::Debug for EncappedKey {
+                fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+                    f.debug_tuple("EncappedKey")
+                        .field(&HexFmt(&self.marshal()))
+                        .finish()
+                }
+            }
+
+            impl Marshallable for PublicKey {
+                type OutputSize = $pubkey_size;
+
+                // Pubkeys are marshalled as encoded encapsulation keys
+                fn marshal(&self) -> GenericArray<u8, Self::OutputSize> {
+                    GenericArray::clone_from_slice(&self.0.as_bytes())
+                }
+            }
+
+            impl Unmarshallable for PublicKey {
+                // Unmarshalling does the modulus check of FIPS 203 §7.2. Decoding reduces every
+                // coefficient mod q, so an encoding is canonical iff it re-encodes to itself.
+                fn unmarshal(encoded: &[u8]) -> Result<Self, HpkeError> {
+                    let encoded = Encoded::<EncapsulationKey>::try_from(encoded)
+                        .map_err(|_| HpkeError::InvalidMarshalledLength)?;
+                    let ek = EncapsulationKey::from_bytes(&encoded);
+
+                    if ek.as_bytes() == encoded {
+                        Ok(PublicKey(ek))
+                    } else {
+                        Err(HpkeError::InvalidKeyExchange)
+                    }
+                }
+
+                // Every PublicKey is the decoding of a canonical encoding, so it has already been
+                // validated
+                fn validate(&self) -> Result<(), HpkeError> {
+                    Ok(())
+                }
+            }
+
+            impl Marshallable for PrivateKey {
+                type OutputSize = typenum::U64;
+
+                // Private keys are marshalled as their seeds
+                fn marshal(&self) -> GenericArray<u8, Self::OutputSize> {
+                    self.seed.clone()
+                }
+            }
+
+            impl Unmarshallable for PrivateKey {
+                // Every 64-byte string is a valid seed
+                fn unmarshal(encoded: &[u8]) -> Result<Self, HpkeError> {
+                    if encoded.len() != Self::size() {
+                        return Err(HpkeError::InvalidMarshalledLength);
+                    }
+
+                    let (sk, _) = PrivateKey::from_seed(GenericArray::clone_from_slice(encoded));
+                    Ok(sk)
+                }
+            }
+
+            impl Marshallable for EncappedKey {
+                type OutputSize = $encapped_key_size;
+
+                fn marshal(&self) -> GenericArray<u8, Self::OutputSize> {
+                    GenericArray::clone_from_slice(&self.0)
+                }
+            }
+
+            impl Unmarshallable for EncappedKey {
+                // ML-KEM decapsulation implicitly rejects malformed ciphertexts, so any string of
+                // the right length is allowed
+                fn unmarshal(encoded: &[u8]) -> Result<Self, HpkeError> {
+                    Array::try_from(encoded)
+                        .map(EncappedKey)
+                        .map_err(|_| HpkeError::InvalidMarshalledLength)
+                }
+            }
+
+            #[doc = concat!("Represents ", $param_name)]
+            pub struct $kem_name {}
+
+            impl $kem_name {
+                // FIPS 203 §7.2: (K, c) = ML-KEM.Encaps_internal(ek, m)
+                /// Does an encapsulation with the given randomness `m`. This is separate from
+                /// `encap` so that it can be checked against known-answer tests.
+                pub(crate) fn encap_with_randomness(
+                    pk_recip: &PublicKey,
+                    m: &B32,
+                ) -> Result<(SharedSecret<Self>, EncappedKey), HpkeError> {
+                    let (ct, mut key) = pk_recip
+                        .0
+                        .encapsulate_deterministic(m)
+                        .map_err(|_| HpkeError::InvalidKeyExchange)?;
+
+                    let mut shared_secret = <SharedSecret<Self> as Default>::default();
+                    shared_secret.0.copy_from_slice(&key);
+                    key.as_mut_slice().zeroize();
+
+                    Ok((shared_secret, EncappedKey(ct)))
+                }
+            }
+
+            impl Kem for $kem_name {
+                type PublicKey = PublicKey;
+                type PrivateKey = PrivateKey;
+                type EncappedKey = EncappedKey;
+
+                // ML-KEM has no AuthEncap, so the auth modes can't be used with it
+                type AuthPrivateKey = NoAuth;
+                type AuthPublicKey = NoAuth;
+
+                // The shared secret is the ML-KEM shared key
+                type NSecret = typenum::U32;
+
+                const KEM_ID: u16 = $kem_id;
+
+                #[doc = concat!("Generates an ", $param_name, " keypair")]
+                fn gen_keypair<R: CryptoRng + RngCore>(csprng: &mut R) -> (PrivateKey, PublicKey) {
+                    let mut seed = GenericArray::default();
+                    csprng.fill_bytes(&mut seed);
+                    PrivateKey::from_seed(seed)
+                }
+
+                // draft-ietf-hpke-pq:
+                //   def DeriveKeyPair(ikm):
+                //     seed = SHAKE256.LabeledDerive(ikm, "DeriveKeyPair", "", 64)
+                //     (ek, dk) = ML-KEM.KeyGen_internal(seed[0:32], seed[32:64])
+                //     return (dk, ek)
+                // where LabeledDerive(ikm, label, context, L) is
+                //   SHAKE256(concat(ikm, "HPKE-v1", suite_id, I2OSP(len(label), 2), label,
+                //                   I2OSP(L, 2), context), L)
+                #[doc = concat!(
+                    "Deterministically derives an ", $param_name, " keypair from the given ",
+                    "input keying material. `ikm` can be any length, but should have at least ",
+                    "32 bytes of entropy."
+                )]
+                fn derive_keypair(ikm: &[u8]) -> (PrivateKey, PublicKey) {
+                    const LABEL: &[u8] = b"DeriveKeyPair";
+
+                    let mut seed = GenericArray::<u8, typenum::U64>::default();
+                    let mut hasher = Shake256::default();
+                    hasher.update(ikm);
+                    hasher.update(VERSION_LABEL);
+                    hasher.update(&kem_suite_id::<Self>());
+                    hasher.update(&(LABEL.len() as u16).to_be_bytes());
+                    hasher.update(LABEL);
+                    hasher.update(&(seed.len() as u16).to_be_bytes());
+                    hasher.finalize_xof().read(&mut seed);
+
+                    PrivateKey::from_seed(seed)
+                }
+
+                fn sk_to_pk(sk: &PrivateKey) -> PublicKey {
+                    PublicKey(sk.dk.encapsulation_key().clone())
+                }
+
+                // FIPS 203 §7.2: (K, c) = ML-KEM.Encaps(ek). The randomness m is drawn from our
+                // own CSPRNG.
+                fn encap<R: CryptoRng + RngCore>(
+                    pk_recip: &PublicKey,
+                    csprng: &mut R,
+                ) -> Result<(SharedSecret<Self>, EncappedKey), HpkeError> {
+                    let mut m = B32::default();
+                    csprng.fill_bytes(&mut m);
+                    let res = Self::encap_with_randomness(pk_recip, &m);
+                    m.as_mut_slice().zeroize();
+
+                    res
+                }
+
+                // FIPS 203 §7.3: K = ML-KEM.Decaps(dk, c)
+                fn decap(
+                    sk_recip: &PrivateKey,
+                    encapped_key: &EncappedKey,
+                ) -> Result<SharedSecret<Self>, HpkeError> {
+                    let mut key = sk_recip
+                        .dk
+                        .decapsulate(&encapped_key.0)
+                        .map_err(|_| HpkeError::InvalidKeyExchange)?;
+
+                    let mut shared_secret = <SharedSecret<Self> as Default>::default();
+                    shared_secret.0.copy_from_slice(&key);
+                    key.as_mut_slice().zeroize();
+
+                    Ok(shared_secret)
+                }
+
+                // NoAuth is uninhabited, so these can never be called
+                fn auth_encap<R: CryptoRng + RngCore>(
+                    _: &PublicKey,
+                    sender_id_keypair: &(NoAuth, NoAuth),
+                    _: &mut R,
+                ) -> Result<(SharedSecret<Self>, EncappedKey), HpkeError> {
+                    match sender_id_keypair.0 {}
+                }
+
+                fn auth_decap(
+                    _: &PrivateKey,
+                    pk_sender_id: &NoAuth,
+                    _: &EncappedKey,
+                ) -> Result<SharedSecret<Self>, HpkeError> {
+                    match *pk_sender_id {}
+                }
+            }
+        }
+    };
+}
+
+// ML-KEM-768 has Npk = 1184, Nenc = 1088
+ml_kem!(
+    ml_kem_768,
+    MlKem768,
+    "ML-KEM-768",
+    ml_kem::MlKem768,
+    typenum::Sum<typenum::U1024, typenum::U160>,
+    typenum::Sum<typenum::U1024, typenum::U64>,
+    0x0041
+);
+
+// ML-KEM-1024 has Npk = 1568, Nenc = 1568
+ml_kem!(
+    ml_kem_1024,
+    MlKem1024,
+    "ML-KEM-1024",
+    ml_kem::MlKem1024,
+    typenum::Sum<typenum::U1024, typenum::U544>,
+    typenum::Sum<typenum::U1024, typenum::U544>,
+    0x0042
+);
+
+#[cfg(test)]
+mod tests {
+    use super::{MlKem1024, MlKem768};
+    use crate::{
+        kem::Kem as KemTrait,
+        kex::{Marshallable, Unmarshallable},
+        HpkeError,
+    };
+
+    use subtle::ConstantTimeEq;
+
+    /// Tests that derive_keypair is deterministic and takes ikm of any length, and that the keys and
+    /// encapped keys have the sizes in the HPKE registry
+    fn test_derive_keypair<Kem: KemTrait>(npk: usize, nenc: usize) {
+        let ikm = [0x2au8; 64];
+        let (sk1, pk1) = Kem::derive_keypair(&ikm);
+        let (sk2, pk2) = Kem::derive_keypair(&ikm);
+        assert!(bool::from(sk1.ct_eq(&sk2)));
+        assert!(bool::from(pk1.ct_eq(&pk2)));
+
+        // The public key is the one the private key expands to
+        assert!(bool::from(Kem::sk_to_pk(&sk1).ct_eq(&pk1)));
+
+        // Any length of ikm works, and different ikm gives different keys
+        let (sk3, _) = Kem::derive_keypair(&ikm[..32]);
+        assert!(!bool::from(sk1.ct_eq(&sk3)));
+
+        let mut csprng = rand::thread_rng();
+        let (_, encapped_key) = Kem::encap(&pk1, &mut csprng).unwrap();
+        assert_eq!(pk1.marshal().len(), npk);
+        assert_eq!(encapped_key.marshal().len(), nenc);
+    }
+
+    /// Tests that encap and decap produce the same shared secret, and that unmarshalled keys work
+    /// the same as the originals
+    fn test_encap_correctness<Kem: KemTrait>() {
+        let mut csprng = rand::thread_rng();
+        let (sk_recip, pk_recip) = Kem::gen_keypair(&mut csprng);
+        let sk_recip = Kem::PrivateKey::unmarshal(&sk_recip.marshal()).unwrap();
+        let pk_recip = Kem::PublicKey::unmarshal(&pk_recip.marshal()).unwrap();
+
+        let (shared_secret, encapped_key) = Kem::encap(&pk_recip, &mut csprng).unwrap();
+        let encapped_key = Kem::EncappedKey::unmarshal(&encapped_key.marshal()).unwrap();
+        let decapped_shared_secret = Kem::decap(&sk_recip, &encapped_key).unwrap();
+        assert_eq!(shared_secret.0, decapped_shared_secret.0);
+
+        // Decapsulating with a different key gives a different secret
+        let (other_sk, _) = Kem::gen_keypair(&mut csprng);
+        let other_shared_secret = Kem::decap(&other_sk, &encapped_key).unwrap();
+        assert_ne!(shared_secret.0, other_shared_secret.0);
+    }
+
+    /// Tests that public keys with a coefficient that isn't reduced mod q are rejected
+    fn test_pubkey_modulus_check<Kem: KemTrait>() {
+        let mut csprng = rand::thread_rng();
+        let (_, pk) = Kem::gen_keypair(&mut csprng);
+
+        // Set the first 12-bit coefficient to 0xfff, which is at least q = 3329
+        let mut pk_bytes = pk.marshal();
+        pk_bytes[0] = 0xff;
+        pk_bytes[1] |= 0x0f;
+
+        match Kem::PublicKey::unmarshal(&pk_bytes) {
+            Err(HpkeError::InvalidKeyExchange) => {} // Good, this isn't canonical
+            Err(e) => panic!("unmarshal() should have failed validation. Got {}", e),
+            Ok(_) => panic!("unmarshal() should have failed validation. It succeeded"),
+        }
+
+        // Wrong lengths are rejected too
+        match Kem::PublicKey::unmarshal(&pk_bytes[1..]) {
+            Err(HpkeError::InvalidMarshalledLength) => {}
+            Err(e) => panic!("unmarshal() should have failed on length. Got {}", e),
+            Ok(_) => panic!("unmarshal() should have failed on length. It succeeded"),
+        }
+    }
+
+    #[test]
+    fn test_derive_keypair_ml_kem_768() {
+        test_derive_keypair::<MlKem768>(1184, 1088);
+    }
+
+    #[test]
+    fn test_derive_keypair_ml_kem_1024() {
+        test_derive_keypair::<MlKem1024>(1568, 1568);
+    }
+
+    #[test]
+    fn test_encap_correctness_ml_kem_768() {
+        test_encap_correctness::<MlKem768>();
+    }
+
+    #[test]
+    fn test_encap_correctness_ml_kem_1024() {
+        test_encap_correctness::<MlKem1024>();
+    }
+
+    #[test]
+    fn test_pubkey_modulus_check_ml_kem_768() {
+        test_pubkey_modulus_check::<MlKem768>();
+    }
+
+    #[test]
+    fn test_pubkey_modulus_check_ml_kem_1024() {
+        test_pubkey_modulus_check::<MlKem1024>();
+    }
+}
@@ -1,7 +1,11 @@
-use crate::HpkeError;
+use crate::{kdf::Kdf as KdfTrait, HpkeError};
 
+use core::fmt::Debug;
 use digest::generic_array::{typenum::marker_traits::Unsigned, ArrayLength, GenericArray};
+
 use rand::{CryptoRng, RngCore};
+use subtle::ConstantTimeEq;
+use zeroize::ZeroizeOnDrop;
 
 /// Implemented by types that have a fixed-length byte representation
 pub trait Marshallable {
@@ -17,31 +21,131 @@ pub trait Marshallable {
 
 /// Implemented by types that can be deserialized from byte representation
 pub trait Unmarshallable: Marshallable + Sized {
+    /// Deserializes a value. Anything this returns has passed `validate`.
     fn unmarshal(encoded: &[u8]) -> Result<Self, HpkeError>;
+
+    /// Checks that this value is safe to use. For public keys, this rejects non-canonical
+    /// encodings, and points that are low-order or not on the curve. By default, nothing is
+    /// checked.
+    ///
+    /// Return Value
+    /// ============
+    /// Returns `Ok(())` if the value is valid. Otherwise, returns
+    /// `Err(HpkeError::InvalidKeyExchange)`.
+    fn validate(&self) -> Result<(), HpkeError> {
+        Ok(())
+    }
 }
 
-/// This trait captures the requirements of a DH-based KEM (draft02 §5.1). It must have a way to
-/// generate keypairs, perform the DH computation, and marshall/umarshall DH pubkeys
+/// This trait captures the requirements of a DH-based KEM (RFC 9180 §4.1). It must have a way to
+/// generate keypairs, perform the DH computation, and marshall/umarshall DH pubkeys. Private keys
+/// and DH results are secret, so they must be wiped when they're dropped, and must not be printed.
 pub trait KeyExchange {
-    type PublicKey: Clone + Marshallable + Unmarshallable;
-    type PrivateKey: Clone + Marshallable + Unmarshallable;
-    type KexResult: Marshallable;
+    type PublicKey: Clone + ConstantTimeEq + Debug + Marshallable + Unmarshallable;
+    type PrivateKey: Clone + ConstantTimeEq + Debug + Marshallable + Unmarshallable + ZeroizeOnDrop;
+    type KexResult: Marshallable + ZeroizeOnDrop;
 
     fn gen_keypair<R: CryptoRng + RngCore>(csprng: &mut R) -> (Self::PrivateKey, Self::PublicKey);
 
+    /// Deterministically derives a keypair from the given input keying material, using the given
+    /// KDF and suite ID in the labeled KDF calls. This is called by `Kem::derive_keypair`, which
+    /// is what you should use instead.
+    fn derive_keypair<Kdf: KdfTrait>(
+        suite_id: &[u8],
+        ikm: &[u8],
+    ) -> (Self::PrivateKey, Self::PublicKey);
+
     fn sk_to_pk(sk: &Self::PrivateKey) -> Self::PublicKey;
 
+    /// Checks that `pk` is the public key of `sk`. Use this to vet a keypair that was imported
+    /// from elsewhere.
+    ///
+    /// Return Value
+    /// ============
+    /// Returns `Ok(())` if the keys match. Otherwise, returns `Err(HpkeError::InvalidKeyExchange)`.
+    fn validate_keypair(sk: &Self::PrivateKey, pk: &Self::PublicKey) -> Result<(), HpkeError> {
+        if Self::sk_to_pk(sk).ct_eq(pk).into() {
+            Ok(())
+        } else {
+            Err(HpkeError::InvalidKeyExchange)
+        }
+    }
+
     fn kex(sk: &Self::PrivateKey, pk: &Self::PublicKey) -> Result<Self::KexResult, HpkeError>;
 }
 
+// Implements ConstantTimeEq and Debug for the PublicKey, PrivateKey, and KexResult types in scope.
+// Public keys are printed in hex, and everything else is redacted.
+macro_rules! impl_key_traits {
+    () => {
+        impl subtle::ConstantTimeEq for PublicKey {
+            fn ct_eq(&self, other: &PublicKey) -> subtle::Choice {
+                $crate::util::marshalled_ct_eq(self, other)
+            }
+        }
+
+        impl subtle::ConstantTimeEq for PrivateKey {
+            fn ct_eq(&self, other: &PrivateKey) -> subtle::Choice {
+                $crate::util::marshalled_ct_eq(self, other)
+            }
+        }
+
+        impl core::fmt::Debug for PublicKey {
+            fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+                f.debug_tuple("PublicKey")
+                    .field(&$crate::util::HexFmt(&self.marshal()))
+                    .finish()
+            }
+        }
+
+        impl core::fmt::Debug for PrivateKey {
+            fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+                f.debug_tuple("PrivateKey")
+                    .field(&$crate::util::Redacted)
+                    .finish()
+            }
+        }
+
+        impl core::fmt::Debug for KexResult {
+            fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+                f.debug_tuple("KexResult")
+                    .field(&$crate::util::Redacted)
+                    .finish()
+            }
+        }
+    };
+}
+
+/// Returns whether the little-endian integer `u` is less than the little-endian integer `p`,
+/// where both have the same length. A Montgomery u-coordinate is only canonically encoded if it's
+/// less than the field modulus.
+fn is_canonical_le(u: &[u8], p: &[u8]) -> bool {
+    // Compare from the most significant byte down
+    for (u_byte, p_byte) in u.iter().rev().zip(p.iter().rev()) {
+        if u_byte != p_byte {
+            return u_byte < p_byte;
+        }
+    }
+
+    // u == p
+    false
+}
+
+pub mod ecdh_nistp;
+pub use ecdh_nistp::{P256, P384, P521};
+
 pub use x25519::X25519;
 pub mod x25519 {
-    use super::{KeyExchange, Marshallable, Unmarshallable};
-    use crate::HpkeError;
+    use super::{is_canonical_le, KeyExchange, Marshallable, Unmarshallable};
+    use crate::{
+        kdf::{labeled_extract, Kdf as KdfTrait, LabeledExpand},
+        HpkeError,
+    };
 
     use digest::generic_array::{typenum, GenericArray};
     use rand::{CryptoRng, RngCore};
     use subtle::ConstantTimeEq;
+    use zeroize::{Zeroize, ZeroizeOnDrop};
 
     // We wrap the types in order to abstract away the dalek dep
 
@@ -55,6 +159,50 @@ pub mod x25519 {
     // A bare DH computation result
     pub struct KexResult(x25519_dalek::SharedSecret);
 
+    // Dalek wipes its secrets when they're dropped, so we don't need a Drop impl of our own
+    impl ZeroizeOnDrop for PrivateKey {}
+    impl ZeroizeOnDrop for KexResult {}
+
+    impl_key_traits!();
+
+    /// The field modulus 2^255 - 19, in little-endian
+    const MODULUS: [u8; 32] = [
+        0xed, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff,
+        0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff,
+        0xff, 0x7f,
+    ];
+
+    /// The canonical encodings of the points of small order, as listed by libsodium. A DH with
+    /// any of these has the same result no matter what the private key is.
+    const SMALL_ORDER_POINTS: [[u8; 32]; 5] = [
+        // u = 0
+        [0x00; 32],
+        // u = 1
+        [
+            0x01, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+            0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+            0x00, 0x00, 0x00, 0x00,
+        ],
+        // A point of order 8
+        [
+            0xe0, 0xeb, 0x7a, 0x7c, 0x3b, 0x41, 0xb8, 0xae, 0x16, 0x56, 0xe3, 0xfa, 0xf1, 0x9f,
+            0xc4, 0x6a, 0xda, 0x09, 0x8d, 0xeb, 0x9c, 0x32, 0xb1, 0xfd, 0x86, 0x62, 0x05, 0x16,
+            0x5f, 0x49, 0xb8, 0x00,
+        ],
+        // Another point of order 8
+        [
+            0x5f, 0x9c, 0x95, 0xbc, 0xa3, 0x50, 0x8c, 0x24, 0xb1, 0xd0, 0xb1, 0x55, 0x9c, 0x83,
+            0xef, 0x5b, 0x04, 0x44, 0x5c, 0xc4, 0x58, 0x1c, 0x8e, 0x86, 0xd8, 0x22, 0x4e, 0xdd,
+            0xd0, 0x9f, 0x11, 0x57,
+        ],
+        // u = p - 1
+        [
+            0xec, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff,
+            0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff,
+            0xff, 0xff, 0xff, 0x7f,
+        ],
+    ];
+
     // Oh I love me an excuse to break out type-level integers
     impl Marshallable for PublicKey {
         type OutputSize = typenum::U32;
@@ -75,7 +223,21 @@ pub mod x25519 {
                 // Copy to a fixed-size array
                 let mut arr = [0u8; 32];
                 arr.copy_from_slice(encoded);
-                Ok(PublicKey(x25519_dalek::PublicKey::from(arr)))
+                let pk = PublicKey(x25519_dalek::PublicKey::from(arr));
+                pk.validate()?;
+                Ok(pk)
+            }
+        }
+
+        // RFC 7748 §5 says to ignore the top bit and reduce u-coordinates mod p. An honest party
+        // never sends either kind of non-canonical encoding, so we reject them instead. This
+        // also means the small-order points only have one encoding each.
+        fn validate(&self) -> Result<(), HpkeError> {
+            let bytes = self.0.as_bytes();
+            if !is_canonical_le(bytes, &MODULUS) || SMALL_ORDER_POINTS.contains(bytes) {
+                Err(HpkeError::InvalidKeyExchange)
+            } else {
+                Ok(())
             }
         }
     }
@@ -95,16 +257,18 @@ pub mod x25519 {
                 // Privkeys must be 32 bytes
                 Err(HpkeError::InvalidMarshalledLength)
             } else {
-                // Copy to a fixed-size array
+                // Copy to a fixed-size array. Dalek copies it again, so wipe ours afterwards.
                 let mut arr = [0u8; 32];
                 arr.copy_from_slice(encoded);
-                Ok(PrivateKey(x25519_dalek::StaticSecret::from(arr)))
+                let sk = x25519_dalek::StaticSecret::from(arr);
+                arr.zeroize();
+                Ok(PrivateKey(sk))
             }
         }
     }
 
     impl Marshallable for KexResult {
-        // §7.1: DHKEM(Curve25519) Nzz = 32
+        // RFC 9180 §7.1: DHKEM(X25519) Ndh = 32
         type OutputSize = typenum::U32;
 
         // Dalek lets us convert shared secrets to to [u8; 32]
@@ -129,6 +293,28 @@ pub mod x25519 {
             (PrivateKey(sk), PublicKey(pk))
         }
 
+        // RFC 9180 §7.1.3
+        // def DeriveKeyPair(ikm):
+        //   dkp_prk = LabeledExtract("", "dkp_prk", ikm)
+        //   sk = LabeledExpand(dkp_prk, "sk", "", Nsk)
+        //   return (sk, pk(sk))
+        /// Deterministically derives an X25519 keypair from the given input keying material
+        fn derive_keypair<Kdf: KdfTrait>(suite_id: &[u8], ikm: &[u8]) -> (PrivateKey, PublicKey) {
+            let (_, hkdf_ctx) = labeled_extract::<Kdf>(b"", suite_id, b"dkp_prk", ikm);
+
+            // The expanded bytes are clamped when they're converted to a scalar. This only errors
+            // if Nsk is 255x the digest size of the hash function, which it isn't.
+            let mut buf = [0u8; 32];
+            hkdf_ctx
+                .labeled_expand(suite_id, b"sk", b"", &mut buf)
+                .expect("Nsk is way too big");
+            let sk = x25519_dalek::StaticSecret::from(buf);
+            let pk = x25519_dalek::PublicKey::from(&sk);
+            buf.zeroize();
+
+            (PrivateKey(sk), PublicKey(pk))
+        }
+
         /// Converts an X25519 private key to a public key
         fn sk_to_pk(sk: &PrivateKey) -> PublicKey {
             PublicKey(x25519_dalek::PublicKey::from(&sk.0))
@@ -150,11 +336,15 @@ pub mod x25519 {
 
     #[cfg(test)]
     mod tests {
-        use crate::kex::{
-            x25519::{PrivateKey, PublicKey, X25519},
-            KeyExchange, Marshallable, Unmarshallable,
+        use crate::{
+            kex::{
+                x25519::{PrivateKey, PublicKey, MODULUS, SMALL_ORDER_POINTS, X25519},
+                KeyExchange, Marshallable, Unmarshallable,
+            },
+            HpkeError,
         };
         use rand::RngCore;
+        use subtle::ConstantTimeEq;
 
         // We need this in our marshal-unmarshal tests
         impl PartialEq for PrivateKey {
@@ -177,10 +367,13 @@ pub mod x25519 {
 
             let mut csprng = rand::thread_rng();
 
-            // Fill a buffer with randomness
+            // Fill a buffer with randomness. The top bit has to be clear, or else the encoding
+            // isn't canonical. The result is less than p, and not a low-order point, with
+            // overwhelming probability.
             let orig_bytes = {
                 let mut buf = vec![0u8; <Kex as KeyExchange>::PublicKey::size()];
                 csprng.fill_bytes(buf.as_mut_slice());
+                buf[31] &= 0x7f;
                 buf
             };
 
@@ -193,6 +386,48 @@ pub mod x25519 {
             assert_eq!(orig_bytes.as_slice(), pk_bytes.as_slice());
         }
 
+        /// Tests that unmarshalling rejects small-order points and non-canonical encodings
+        #[test]
+        fn test_pubkey_validation() {
+            type Kex = X25519;
+
+            let mut csprng = rand::thread_rng();
+            let (sk, pk) = Kex::gen_keypair(&mut csprng);
+
+            for point in SMALL_ORDER_POINTS.iter() {
+                assert!(matches!(
+                    PublicKey::unmarshal(point),
+                    Err(HpkeError::InvalidKeyExchange)
+                ));
+
+                // Make sure these really are small-order, i.e., the DH result is zero
+                let bad_pk = PublicKey(x25519_dalek::PublicKey::from(*point));
+                assert!(Kex::kex(&sk, &bad_pk).is_err());
+            }
+
+            // p itself, and a valid key with the top bit set, aren't canonical
+            assert!(PublicKey::unmarshal(&MODULUS).is_err());
+            let mut pk_bytes = pk.marshal();
+            pk_bytes[31] |= 0x80;
+            assert!(PublicKey::unmarshal(&pk_bytes).is_err());
+        }
+
+        /// Tests that validate_keypair accepts matching keys and rejects mismatched ones
+        #[test]
+        fn test_validate_keypair() {
+            type Kex = X25519;
+
+            let mut csprng = rand::thread_rng();
+            let (sk1, pk1) = Kex::gen_keypair(&mut csprng);
+            let (_, pk2) = Kex::gen_keypair(&mut csprng);
+
+            assert!(Kex::validate_keypair(&sk1, &pk1).is_ok());
+            assert!(matches!(
+                Kex::validate_keypair(&sk1, &pk2),
+                Err(HpkeError::InvalidKeyExchange)
+            ));
+        }
+
         /// Tests that an unmarshal-marshal round-trip on a DH keypair ends up at the same values
         #[test]
         fn test_dh_marshal_correctness() {
@@ -212,5 +447,312 @@ pub mod x25519 {
             assert!(new_sk == sk, "private key doesn't marshal correctly");
             assert!(new_pk == pk, "public key doesn't marshal correctly");
         }
+
+        /// Tests that keys compare correctly in constant time, and that only public keys are
+        /// printed by Debug
+        #[test]
+        fn test_ct_eq_and_debug() {
+            type Kex = X25519;
+
+            let mut csprng = rand::thread_rng();
+            let (sk1, pk1) = Kex::gen_keypair(&mut csprng);
+            let (sk2, pk2) = Kex::gen_keypair(&mut csprng);
+
+            assert!(bool::from(sk1.ct_eq(&sk1.clone())));
+            assert!(bool::from(pk1.ct_eq(&pk1.clone())));
+            assert!(!bool::from(sk1.ct_eq(&sk2)));
+            assert!(!bool::from(pk1.ct_eq(&pk2)));
+
+            // The pubkey is printed in hex. The privkey shows up nowhere.
+            let sk_hex = hex::encode(sk1.marshal());
+            let pk_hex = hex::encode(pk1.marshal());
+            let debug_str = format!("{:?}", (&sk1, &pk1));
+            assert_eq!(
+                debug_str,
+                format!("(PrivateKey([redacted]), PublicKey({}))", pk_hex)
+            );
+            assert!(!debug_str.contains(&sk_hex));
+        }
+    }
+}
+
+pub use x448::X448;
+pub mod x448 {
+    use super::{is_canonical_le, KeyExchange, Marshallable, Unmarshallable};
+    use crate::{
+        kdf::{labeled_extract, Kdf as KdfTrait, LabeledExpand},
+        HpkeError,
+    };
+
+    use digest::generic_array::{typenum, GenericArray};
+    use rand::{CryptoRng, RngCore};
+    use subtle::ConstantTimeEq;
+    use zeroize::{Zeroize, ZeroizeOnDrop};
+
+    // We wrap the types in order to abstract away the x448 dep. Its types don't implement Clone,
+    // so we do that ourselves. Its secrets can't be wiped either, so we hold on to their bytes
+    // instead, and only make an x448 type when we need to do arithmetic.
+
+    /// An X448 public key
+    pub struct PublicKey(::x448::PublicKey);
+    /// An X448 private key
+    #[derive(Clone, Zeroize, ZeroizeOnDrop)]
+    pub struct PrivateKey([u8; 56]);
+
+    // A bare DH computation result. This is the u-coordinate of the shared point.
+    #[derive(Zeroize, ZeroizeOnDrop)]
+    pub struct KexResult([u8; 56]);
+
+    impl_key_traits!();
+
+    /// The field modulus 2^448 - 2^224 - 1, in little-endian
+    const MODULUS: [u8; 56] = {
+        let mut p = [0xff; 56];
+        p[28] = 0xfe;
+        p
+    };
+
+    impl PrivateKey {
+        // The bytes of a Secret are already clamped
+        fn from_secret(sk: &::x448::Secret) -> PrivateKey {
+            PrivateKey(*sk.as_bytes())
+        }
+
+        // Clamping an already clamped scalar is a no-op
+        fn to_secret(&self) -> ::x448::Secret {
+            ::x448::Secret::from(self.0)
+        }
+    }
+
+    impl Clone for PublicKey {
+        fn clone(&self) -> PublicKey {
+            // This only fails if the input isn't 56 bytes
+            PublicKey(::x448::PublicKey::from_bytes_unchecked(self.0.as_bytes()).unwrap())
+        }
+    }
+
+    impl Marshallable for PublicKey {
+        // §7.1: DHKEM(Curve448) Npk = 56
+        type OutputSize = typenum::U56;
+
+        fn marshal(&self) -> GenericArray<u8, typenum::U56> {
+            GenericArray::clone_from_slice(self.0.as_bytes())
+        }
+    }
+
+    impl Unmarshallable for PublicKey {
+        fn unmarshal(encoded: &[u8]) -> Result<Self, HpkeError> {
+            // This only fails if the pubkey isn't 56 bytes
+            let pk = ::x448::PublicKey::from_bytes_unchecked(encoded)
+                .map(PublicKey)
+                .ok_or(HpkeError::InvalidMarshalledLength)?;
+            pk.validate()?;
+            Ok(pk)
+        }
+
+        // Same as with X25519, we reject u-coordinates that aren't reduced mod p, and the
+        // low-order points. The x448 crate's checked constructor does the latter.
+        fn validate(&self) -> Result<(), HpkeError> {
+            let bytes = self.0.as_bytes();
+            if !is_canonical_le(bytes, &MODULUS) || ::x448::PublicKey::from_bytes(bytes).is_none() {
+                Err(HpkeError::InvalidKeyExchange)
+            } else {
+                Ok(())
+            }
+        }
+    }
+
+    impl Marshallable for PrivateKey {
+        // §7.1: DHKEM(Curve448) Nsk = 56
+        type OutputSize = typenum::U56;
+
+        fn marshal(&self) -> GenericArray<u8, typenum::U56> {
+            GenericArray::clone_from_slice(&self.0)
+        }
+    }
+
+    impl Unmarshallable for PrivateKey {
+        // The scalar gets clamped on the way in
+        fn unmarshal(encoded: &[u8]) -> Result<Self, HpkeError> {
+            // This only fails if the privkey isn't 56 bytes
+            ::x448::Secret::from_bytes(encoded)
+                .map(|sk| PrivateKey::from_secret(&sk))
+                .ok_or(HpkeError::InvalidMarshalledLength)
+        }
+    }
+
+    impl Marshallable for KexResult {
+        // The DH result is a Curve448 u-coordinate
+        type OutputSize = typenum::U56;
+
+        fn marshal(&self) -> GenericArray<u8, typenum::U56> {
+            GenericArray::clone_from_slice(&self.0)
+        }
+    }
+
+    /// Dummy type which implements the `KeyExchange` trait
+    pub struct X448 {}
+
+    impl KeyExchange for X448 {
+        type PublicKey = PublicKey;
+        type PrivateKey = PrivateKey;
+        type KexResult = KexResult;
+
+        /// Generates an X448 keypair
+        fn gen_keypair<R: CryptoRng + RngCore>(csprng: &mut R) -> (PrivateKey, PublicKey) {
+            let sk = ::x448::Secret::new(csprng);
+            let pk = ::x448::PublicKey::from(&sk);
+
+            (PrivateKey::from_secret(&sk), PublicKey(pk))
+        }
+
+        // RFC 9180 §7.1.3
+        // def DeriveKeyPair(ikm):
+        //   dkp_prk = LabeledExtract("", "dkp_prk", ikm)
+        //   sk = LabeledExpand(dkp_prk, "sk", "", Nsk)
+        //   return (sk, pk(sk))
+        /// Deterministically derives an X448 keypair from the given input keying material
+        fn derive_keypair<Kdf: KdfTrait>(suite_id: &[u8], ikm: &[u8]) -> (PrivateKey, PublicKey) {
+            let (_, hkdf_ctx) = labeled_extract::<Kdf>(b"", suite_id, b"dkp_prk", ikm);
+
+            // The expanded bytes are clamped when they're converted to a scalar. This only errors
+            // if Nsk is 255x the digest size of the hash function, which it isn't.
+            let mut buf = [0u8; 56];
+            hkdf_ctx
+                .labeled_expand(suite_id, b"sk", b"", &mut buf)
+                .expect("Nsk is way too big");
+            let sk = ::x448::Secret::from(buf);
+            let pk = ::x448::PublicKey::from(&sk);
+            buf.zeroize();
+
+            (PrivateKey::from_secret(&sk), PublicKey(pk))
+        }
+
+        /// Converts an X448 private key to a public key
+        fn sk_to_pk(sk: &PrivateKey) -> PublicKey {
+            PublicKey(::x448::PublicKey::from(&sk.to_secret()))
+        }
+
+        /// Does the DH operation. Returns `HpkeError::InvalidKeyExchange` if and only if the DH
+        /// result was all zeros. This is required by the HPKE spec.
+        fn kex(sk: &PrivateKey, pk: &PublicKey) -> Result<KexResult, HpkeError> {
+            // The x448 crate refuses to do DH with a low-order point. Those are exactly the points
+            // that would give an all-zero result, so treat it the same way.
+            let res = sk
+                .to_secret()
+                .as_diffie_hellman(&pk.0)
+                .ok_or(HpkeError::InvalidKeyExchange)?;
+            // "Senders and recipients MUST check whether the shared secret is the all-zero value
+            // and abort if so"
+            if res.as_bytes().ct_eq(&[0u8; 56]).into() {
+                Err(HpkeError::InvalidKeyExchange)
+            } else {
+                Ok(KexResult(*res.as_bytes()))
+            }
+        }
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use crate::{
+            kex::{
+                x448::{PrivateKey, PublicKey, MODULUS, X448},
+                KeyExchange, Marshallable, Unmarshallable,
+            },
+            HpkeError,
+        };
+        use rand::RngCore;
+
+        // We need this in our marshal-unmarshal tests
+        impl PartialEq for PrivateKey {
+            fn eq(&self, other: &PrivateKey) -> bool {
+                self.0 == other.0
+            }
+        }
+
+        // We need this in our marshal-unmarshal tests
+        impl PartialEq for PublicKey {
+            fn eq(&self, other: &PublicKey) -> bool {
+                self.0.as_bytes()[..] == other.0.as_bytes()[..]
+            }
+        }
+
+        /// Tests that an unmarshal-marshal round-trip ends up at the same pubkey
+        #[test]
+        fn test_pubkey_marshal_correctness() {
+            type Kex = X448;
+
+            let mut csprng = rand::thread_rng();
+
+            // Fill a buffer with randomness
+            let orig_bytes = {
+                let mut buf = vec![0u8; <Kex as KeyExchange>::PublicKey::size()];
+                csprng.fill_bytes(buf.as_mut_slice());
+                buf
+            };
+
+            // Make a pubkey with those random bytes. Note, that unmarshal does not clamp the input
+            // bytes. This is why this test passes.
+            let pk = <Kex as KeyExchange>::PublicKey::unmarshal(&orig_bytes).unwrap();
+            let pk_bytes = pk.marshal();
+
+            // See if the re-marshalled bytes are the same as the input
+            assert_eq!(orig_bytes.as_slice(), pk_bytes.as_slice());
+        }
+
+        /// Tests that an unmarshal-marshal round-trip on a DH keypair ends up at the same values
+        #[test]
+        fn test_dh_marshal_correctness() {
+            type Kex = X448;
+
+            let mut csprng = rand::thread_rng();
+
+            // Make a random keypair and marshal it
+            let (sk, pk) = Kex::gen_keypair(&mut csprng);
+            let (sk_bytes, pk_bytes) = (sk.marshal(), pk.marshal());
+
+            // Now unmarshal those bytes
+            let new_sk = <Kex as KeyExchange>::PrivateKey::unmarshal(&sk_bytes).unwrap();
+            let new_pk = <Kex as KeyExchange>::PublicKey::unmarshal(&pk_bytes).unwrap();
+
+            // See if the unmarshalled values are the same as the initial ones
+            assert!(new_sk == sk, "private key doesn't marshal correctly");
+            assert!(new_pk == pk, "public key doesn't marshal correctly");
+        }
+
+        /// Tests that unmarshalling rejects low-order points and non-canonical encodings
+        #[test]
+        fn test_pubkey_validation() {
+            // u = 0, u = 1, and u = p - 1 are the low-order points
+            let mut p_minus_one = MODULUS;
+            p_minus_one[0] -= 1;
+            let mut one = [0u8; 56];
+            one[0] = 1;
+
+            for bad_bytes in &[[0u8; 56], one, p_minus_one, MODULUS] {
+                assert!(matches!(
+                    PublicKey::unmarshal(bad_bytes),
+                    Err(HpkeError::InvalidKeyExchange)
+                ));
+            }
+        }
+
+        /// Tests that doing DH with the all-zero point is rejected, even if it gets past
+        /// unmarshalling
+        #[test]
+        fn test_zero_kex_rejected() {
+            type Kex = X448;
+
+            let mut csprng = rand::thread_rng();
+            let (sk, _) = Kex::gen_keypair(&mut csprng);
+
+            // The zero point has order 1, so the DH result is always zero
+            let zero_pk = PublicKey(::x448::PublicKey::from_bytes_unchecked(&[0u8; 56]).unwrap());
+            match Kex::kex(&sk, &zero_pk) {
+                Err(HpkeError::InvalidKeyExchange) => {} // Good, this should be rejected
+                Err(e) => panic!("kex() should have failed. Got {}", e),
+                Ok(_) => panic!("kex() should have failed. It succeeded"),
+            }
+        }
     }
 }
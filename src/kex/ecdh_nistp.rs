@@ -0,0 +1,293 @@
+// The NIST curves are all implemented by the RustCrypto elliptic curve crates. Those use a newer
+// generic_array than we do, so everything gets converted through byte slices. The curves only
+// differ in their sizes, so we implement all of them with one macro.
+
+pub use self::{p256::P256, p384::P384, p521::P521};
+
+// Implements a `KeyExchange` for a NIST curve. The parameters are:
+//   * $mod_name: the name of the module to put everything in
+//   * $curve: the RustCrypto crate which implements the curve
+//   * $dh_name: the name of the `KeyExchange` type
+//   * $curve_name: the curve's name, for documentation
+//   * $pubkey_size: the size of an uncompressed point, i.e., 2·Nsk + 1
+//   * $privkey_size: the size of a marshalled scalar (Nsk)
+//   * $bitmask: the mask applied to the most significant byte of a random scalar, so that it has
+//     the same bit length as the curve order
+macro_rules! nistp_dhkex {
+    (
+        $mod_name:ident,
+        $curve:ident,
+        $dh_name:ident,
+        $curve_name:expr,
+        $pubkey_size:ty,
+        $privkey_size:ty,
+        $bitmask:expr
+    ) => {
+        pub mod $mod_name {
+            use crate::{
+                kdf::{labeled_extract, Kdf as KdfTrait, LabeledExpand},
+                kex::{KeyExchange, Marshallable, Unmarshallable},
+                HpkeError,
+            };
+
+            use digest::generic_array::{typenum, GenericArray};
+            use rand::{CryptoRng, RngCore};
+            use $curve::elliptic_curve::sec1::ToEncodedPoint;
+            use zeroize::{Zeroize, ZeroizeOnDrop};
+
+            #[doc = concat!("A ", $curve_name, " public key")]
+            #[derive(Clone)]
+            pub struct PublicKey($curve::PublicKey);
+            #[doc = concat!("A ", $curve_name, " private key")]
+            #[derive(Clone)]
+            pub struct PrivateKey($curve::SecretKey);
+
+            // A bare DH computation result
+            pub struct KexResult(GenericArray<u8, $privkey_size>);
+
+            // The underlying SecretKey wipes itself when it's dropped
+            impl ZeroizeOnDrop for PrivateKey {}
+
+            impl Zeroize for KexResult {
+                fn zeroize(&mut self) {
+                    self.0.as_mut_slice().zeroize();
+                }
+            }
+
+            impl Drop for KexResult {
+                fn drop(&mut self) {
+                    self.zeroize();
+                }
+            }
+
+            impl ZeroizeOnDrop for KexResult {}
+
+            impl_key_traits!();
+
+            impl Marshallable for PublicKey {
+                // A fixed-size uncompressed point is 1 tag byte, followed by the x and y
+                // coordinates
+                type OutputSize = $pubkey_size;
+
+                // Pubkeys are marshalled in the uncompressed SEC1 encoding
+                fn marshal(&self) -> GenericArray<u8, Self::OutputSize> {
+                    GenericArray::clone_from_slice(self.0.to_encoded_point(false).as_bytes())
+                }
+            }
+
+            impl Unmarshallable for PublicKey {
+                // Unmarshalling checks that the point is on the curve and is not the point at
+                // infinity
+                fn unmarshal(encoded: &[u8]) -> Result<Self, HpkeError> {
+                    // Only uncompressed points are allowed. Those have a fixed length.
+                    if encoded.len() != Self::size() {
+                        return Err(HpkeError::InvalidMarshalledLength);
+                    }
+
+                    $curve::PublicKey::from_sec1_bytes(encoded)
+                        .map(PublicKey)
+                        .map_err(|_| HpkeError::InvalidKeyExchange)
+                }
+
+                // The curve crate only makes public keys out of points on the curve, other than
+                // the point at infinity. So every PublicKey has already been validated.
+                fn validate(&self) -> Result<(), HpkeError> {
+                    Ok(())
+                }
+            }
+
+            impl Marshallable for PrivateKey {
+                type OutputSize = $privkey_size;
+
+                // Private keys are marshalled as fixed-length big-endian scalars
+                fn marshal(&self) -> GenericArray<u8, Self::OutputSize> {
+                    GenericArray::clone_from_slice(&self.0.to_bytes())
+                }
+            }
+
+            impl Unmarshallable for PrivateKey {
+                // Unmarshalling checks that the scalar is nonzero and less than the curve order
+                fn unmarshal(encoded: &[u8]) -> Result<Self, HpkeError> {
+                    if encoded.len() != Self::size() {
+                        return Err(HpkeError::InvalidMarshalledLength);
+                    }
+
+                    $curve::SecretKey::from_slice(encoded)
+                        .map(PrivateKey)
+                        .map_err(|_| HpkeError::InvalidKeyExchange)
+                }
+            }
+
+            impl Marshallable for KexResult {
+                // The DH result is a field element, so it's the same size as a scalar
+                type OutputSize = $privkey_size;
+
+                // The DH result is the x-coordinate of the shared point
+                fn marshal(&self) -> GenericArray<u8, Self::OutputSize> {
+                    self.0.clone()
+                }
+            }
+
+            /// Dummy type which implements the `KeyExchange` trait
+            pub struct $dh_name {}
+
+            impl KeyExchange for $dh_name {
+                type PublicKey = PublicKey;
+                type PrivateKey = PrivateKey;
+                type KexResult = KexResult;
+
+                #[doc = concat!("Generates a ", $curve_name, " keypair")]
+                fn gen_keypair<R: CryptoRng + RngCore>(csprng: &mut R) -> (PrivateKey, PublicKey) {
+                    // Sample random bytestrings until we get one that's a valid scalar. The mask
+                    // makes the bytestring the same bit length as the curve order, so the odds of
+                    // needing more than one try are negligible.
+                    let mut buf = <GenericArray<u8, $privkey_size> as Default>::default();
+                    let sk = loop {
+                        csprng.fill_bytes(&mut buf);
+                        buf[0] &= $bitmask;
+                        if let Ok(sk) = $curve::SecretKey::from_slice(&buf) {
+                            break sk;
+                        }
+                    };
+                    let pk = sk.public_key();
+                    buf.as_mut_slice().zeroize();
+
+                    (PrivateKey(sk), PublicKey(pk))
+                }
+
+                // RFC 9180 §7.1.3
+                // def DeriveKeyPair(ikm):
+                //   dkp_prk = LabeledExtract("", "dkp_prk", ikm)
+                //   sk = 0
+                //   counter = 0
+                //   while sk == 0 or sk >= order:
+                //     if counter > 255:
+                //       raise DeriveKeyPairError
+                //     bytes = LabeledExpand(dkp_prk, "candidate",
+                //                           I2OSP(counter, 1), Nsk)
+                //     bytes[0] = bytes[0] & bitmask
+                //     sk = OS2IP(bytes)
+                //     counter = counter + 1
+                //   return (sk, pk(sk))
+                #[doc = concat!("Deterministically derives a ", $curve_name, " keypair from IKM")]
+                fn derive_keypair<Kdf: KdfTrait>(
+                    suite_id: &[u8],
+                    ikm: &[u8],
+                ) -> (PrivateKey, PublicKey) {
+                    let (_, hkdf_ctx) = labeled_extract::<Kdf>(b"", suite_id, b"dkp_prk", ikm);
+
+                    let mut buf = <GenericArray<u8, $privkey_size> as Default>::default();
+                    for counter in 0u8..=255 {
+                        // This only errors if Nsk is 255x the digest size of the hash function,
+                        // which it isn't
+                        hkdf_ctx
+                            .labeled_expand(suite_id, b"candidate", &[counter], &mut buf)
+                            .expect("Nsk is way too big");
+                        buf[0] &= $bitmask;
+
+                        // from_slice() rejects zero and anything at least the curve order
+                        if let Ok(sk) = $curve::SecretKey::from_slice(&buf) {
+                            let pk = sk.public_key();
+                            buf.as_mut_slice().zeroize();
+                            return (PrivateKey(sk), PublicKey(pk));
+                        }
+                    }
+
+                    // Every candidate is valid with probability at least 1/2, so 256 failures in a
+                    // row never happens in practice
+                    panic!("DeriveKeyPair exhausted its counter");
+                }
+
+                #[doc = concat!("Converts a ", $curve_name, " private key to a public key")]
+                fn sk_to_pk(sk: &PrivateKey) -> PublicKey {
+                    PublicKey(sk.0.public_key())
+                }
+
+                /// Does the DH operation. Since both keys were validated on unmarshalling, and the
+                /// private key is nonzero, the shared point is never the point at infinity. So
+                /// this never errors.
+                fn kex(sk: &PrivateKey, pk: &PublicKey) -> Result<KexResult, HpkeError> {
+                    let res =
+                        $curve::ecdh::diffie_hellman(sk.0.to_nonzero_scalar(), pk.0.as_affine());
+                    // RFC 9180 §7.1.1: The DH result is the x-coordinate of the shared point
+                    Ok(KexResult(GenericArray::clone_from_slice(
+                        res.raw_secret_bytes(),
+                    )))
+                }
+            }
+
+            #[cfg(test)]
+            mod tests {
+                use super::{$dh_name, PrivateKey, PublicKey};
+                use crate::{
+                    kex::{KeyExchange, Marshallable, Unmarshallable},
+                    HpkeError,
+                };
+
+                // We need this in our marshal-unmarshal tests
+                impl PartialEq for PrivateKey {
+                    fn eq(&self, other: &PrivateKey) -> bool {
+                        self.0.to_bytes() == other.0.to_bytes()
+                    }
+                }
+
+                // We need this in our marshal-unmarshal tests
+                impl PartialEq for PublicKey {
+                    fn eq(&self, other: &PublicKey) -> bool {
+                        self.0 == other.0
+                    }
+                }
+
+                /// Tests that a marshal-unmarshal round-trip on a DH keypair ends up at the same
+                /// values
+                #[test]
+                fn test_dh_marshal_correctness() {
+                    type Kex = $dh_name;
+
+                    let mut csprng = rand::thread_rng();
+
+                    // Make a random keypair and marshal it
+                    let (sk, pk) = Kex::gen_keypair(&mut csprng);
+                    let (sk_bytes, pk_bytes) = (sk.marshal(), pk.marshal());
+
+                    // Now unmarshal those bytes
+                    let new_sk = <Kex as KeyExchange>::PrivateKey::unmarshal(&sk_bytes).unwrap();
+                    let new_pk = <Kex as KeyExchange>::PublicKey::unmarshal(&pk_bytes).unwrap();
+
+                    // See if the unmarshalled values are the same as the initial ones
+                    assert!(new_sk == sk, "private key doesn't marshal correctly");
+                    assert!(new_pk == pk, "public key doesn't marshal correctly");
+                }
+
+                /// Tests that unmarshalling rejects points that aren't on the curve
+                #[test]
+                fn test_pubkey_validation() {
+                    type Kex = $dh_name;
+
+                    let mut csprng = rand::thread_rng();
+                    let (_, pk) = Kex::gen_keypair(&mut csprng);
+
+                    // Flip a bit in the y-coordinate. This moves the point off the curve.
+                    let mut pk_bytes = pk.marshal();
+                    let last_idx = pk_bytes.len() - 1;
+                    pk_bytes[last_idx] ^= 1;
+
+                    match <Kex as KeyExchange>::PublicKey::unmarshal(&pk_bytes) {
+                        Err(HpkeError::InvalidKeyExchange) => {} // Good, this isn't a valid point
+                        Err(e) => panic!("unmarshal() should have failed validation. Got {}", e),
+                        Ok(_) => panic!("unmarshal() should have failed validation. It succeeded"),
+                    }
+                }
+            }
+        }
+    };
+}
+
+// RFC 9180 §7.1: DHKEM(P-256) has Npk = 65, Nsk = 32
+nistp_dhkex!(p256, p256, P256, "P-256", typenum::U65, typenum::U32, 0xFF);
+
+// RFC 9180 §7.1: DHKEM(P-384) has Npk = 97, Nsk = 48
+nistp_dhkex!(p384, p384, P384, "P-384", typenum::U97, typenum::U48, 0xFF);
+
+// RFC 9180 §7.1: DHKEM(P-521) has Npk = 133, Nsk = 66
+nistp_dhkex!(p521, p521, P521, "P-521", typenum::U133, typenum::U66, 0x01);
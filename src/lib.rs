@@ -16,11 +16,6 @@ extern crate alloc;
 // Re-export this version of generic_array, since that's what's used everywhere in this crate
 pub use digest::generic_array;
 
-// kat_tests requries "std" and serde
-#[cfg(all(test, feature = "std"))]
-#[macro_use]
-extern crate serde_derive;
-
 // kat_tests requries "std" for file IO
 #[cfg(all(test, feature = "std"))]
 mod kat_tests;
@@ -31,17 +26,32 @@ mod test_util;
 //-------- Modules and exports--------//
 
 pub mod aead;
+#[cfg(feature = "alloc")]
+pub mod agile;
+pub mod ciphersuite;
+#[cfg(feature = "alloc")]
+pub mod envelope;
+#[cfg(feature = "jwk")]
+pub mod jwk;
 pub mod kdf;
 pub mod kem;
 pub mod kex;
+#[cfg(feature = "alloc")]
+pub mod multi_recipient;
 pub mod op_mode;
+#[cfg(feature = "pkcs8")]
+pub mod pkcs8;
 mod prelude;
+#[cfg(feature = "serde")]
+mod serde_impls;
 pub mod setup;
 pub mod single_shot;
+#[cfg(feature = "std")]
+pub mod streaming;
 mod util;
 
 #[doc(inline)]
-pub use crate::aead::AeadCtx;
+pub use crate::aead::{AeadCtxR, AeadCtxS};
 #[doc(inline)]
 pub use kem::{EncappedKey, Kem};
 #[doc(inline)]
@@ -51,7 +61,12 @@ pub use op_mode::{OpModeR, OpModeS, Psk, PskBundle};
 #[doc(inline)]
 pub use setup::{setup_receiver, setup_sender};
 #[doc(inline)]
-pub use single_shot::{single_shot_open, single_shot_seal};
+pub use single_shot::{
+    single_shot_open, single_shot_receive_export, single_shot_seal, single_shot_send_export,
+};
+#[cfg(feature = "alloc")]
+#[doc(inline)]
+pub use single_shot::{single_shot_open_to_vec, single_shot_seal_to_vec};
 
 //-------- Top-level types --------//
 
@@ -70,6 +85,22 @@ pub enum HpkeError {
     InvalidKdfLength,
     /// The unmarshaller was given the wrong number of bytes
     InvalidMarshalledLength,
+    /// A PSK mode was given an empty PSK or PSK ID
+    InvalidPsk,
+    /// An envelope had an unknown version or mode
+    InvalidEnvelope,
+    /// An envelope was sealed under a different ciphersuite than the one it's being opened with
+    SuiteMismatch,
+    /// An encoded key was for a different algorithm than the one it was decoded as
+    UnknownOid,
+    /// An encoded key was not valid DER, or did not have the expected structure
+    MalformedKeyEncoding,
+    /// A JWK was for a different key type or curve, was missing a member, or had mismatched keys
+    InvalidJwk,
+    /// A sequence number was already used, or was too old to open
+    InvalidSeq,
+    /// A multi-recipient ciphertext had no recipients, or none matching the given key
+    InvalidRecipients,
 }
 
 impl core::fmt::Display for HpkeError {
@@ -81,6 +112,14 @@ impl core::fmt::Display for HpkeError {
             HpkeError::InvalidKeyExchange => "Key exchange validation error",
             HpkeError::InvalidKdfLength => "Too many bytes requested from KDF",
             HpkeError::InvalidMarshalledLength => "Cannot unmarshal byte sequence of this length",
+            HpkeError::InvalidPsk => "Inconsistent or missing PSK inputs",
+            HpkeError::InvalidEnvelope => "Invalid envelope header",
+            HpkeError::SuiteMismatch => "Envelope ciphersuite does not match",
+            HpkeError::UnknownOid => "Unknown algorithm OID in encoded key",
+            HpkeError::MalformedKeyEncoding => "Malformed key encoding",
+            HpkeError::InvalidJwk => "Invalid JWK",
+            HpkeError::InvalidSeq => "Sequence number reused or outside the replay window",
+            HpkeError::InvalidRecipients => "No matching recipient",
         };
         f.write_str(kind)
     }
@@ -0,0 +1,509 @@
+//! Encrypts a single payload for many recipients. The payload is encrypted once, under a random
+//! content key, and that content key is then sealed to each recipient with HPKE. The result
+//! serializes as
+//!
+//! ```text
+//! count (4 bytes)
+//!     || count × (pk_recip (Npk bytes) || enc (Npk bytes) || wrapped_key (Nk + Nt bytes))
+//!     || ciphertext || tag (Nt bytes)
+//! ```
+//!
+//! All integers are big-endian. Every wrapped key is sealed with an AAD that commits to the full
+//! recipient list and to the payload ciphertext, so a recipient can't swap out the payload or the
+//! list of recipients that the others see without being detected.
+
+use crate::prelude::*;
+use crate::{
+    aead::{Aead, AeadKey, AeadNonce, AeadTag, SealingAead},
+    kdf::Kdf as KdfTrait,
+    kem::Kem as KemTrait,
+    kex::{Marshallable, Unmarshallable},
+    op_mode::{OpModeR, OpModeS},
+    single_shot::{single_shot_open_to_vec, single_shot_seal_to_vec},
+    HpkeError,
+};
+
+use core::{convert::TryFrom, marker::PhantomData};
+
+use aead::{Aead as BaseAead, NewAead as BaseNewAead};
+use byteorder::{BigEndian, ByteOrder};
+use digest::{generic_array::GenericArray, Digest};
+use rand::{CryptoRng, RngCore};
+use subtle::ConstantTimeEq;
+use zeroize::Zeroizing;
+
+/// The length of the recipient count
+const COUNT_LEN: usize = 4;
+
+/// A copy of the content key, sealed to a single recipient
+pub struct WrappedKey<A: Aead, Kem: KemTrait> {
+    pk_recip: Kem::PublicKey,
+    encapped_key: Kem::EncappedKey,
+    wrapped_key: Vec<u8>,
+    marker: PhantomData<A>,
+}
+
+impl<A: Aead, Kem: KemTrait> WrappedKey<A, Kem> {
+    /// Returns the public key of the recipient this key was sealed to
+    pub fn pk_recip(&self) -> &Kem::PublicKey {
+        &self.pk_recip
+    }
+
+    /// Returns the encapsulated key this key was sealed with
+    pub fn encapped_key(&self) -> &Kem::EncappedKey {
+        &self.encapped_key
+    }
+
+    /// Returns the sealed content key, as `ciphertext || tag`
+    pub fn wrapped_key(&self) -> &[u8] {
+        &self.wrapped_key
+    }
+
+    /// The length of `pk_recip || enc || wrapped_key`
+    fn size() -> usize {
+        Kem::PublicKey::size()
+            + Kem::EncappedKey::size()
+            + AeadKey::<A>::default().len()
+            + AeadTag::<A>::size()
+    }
+}
+
+/// A payload that has been encrypted once, along with a copy of its content key for each of its
+/// recipients
+pub struct MultiRecipientCiphertext<A: Aead, Kdf: KdfTrait, Kem: KemTrait> {
+    recipients: Vec<WrappedKey<A, Kem>>,
+    ciphertext_and_tag: Vec<u8>,
+    marker: PhantomData<Kdf>,
+}
+
+impl<A: Aead, Kdf: KdfTrait, Kem: KemTrait> MultiRecipientCiphertext<A, Kdf, Kem> {
+    /// Returns the wrapped content keys, one per recipient, in the order the recipients were given
+    /// to `seal_multi_recipient`
+    pub fn recipients(&self) -> &[WrappedKey<A, Kem>] {
+        &self.recipients
+    }
+
+    /// Returns the `ciphertext || tag` of the payload
+    pub fn ciphertext_and_tag(&self) -> &[u8] {
+        &self.ciphertext_and_tag
+    }
+
+    /// Serializes this ciphertext
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut out = Vec::with_capacity(
+            COUNT_LEN
+                + self.recipients.len() * WrappedKey::<A, Kem>::size()
+                + self.ciphertext_and_tag.len(),
+        );
+
+        let mut count = [0u8; COUNT_LEN];
+        // seal_multi_recipient and from_bytes make sure this fits
+        BigEndian::write_u32(&mut count, self.recipients.len() as u32);
+        out.extend_from_slice(&count);
+
+        for recip in self.recipients.iter() {
+            out.extend_from_slice(&recip.pk_recip.marshal());
+            out.extend_from_slice(&recip.encapped_key.marshal());
+            out.extend_from_slice(&recip.wrapped_key);
+        }
+        out.extend_from_slice(&self.ciphertext_and_tag);
+
+        out
+    }
+
+    /// Deserializes a multi-recipient ciphertext
+    ///
+    /// Return Value
+    /// ============
+    /// Returns `Ok(ciphertext)` on success. If `bytes` is too short to hold the recipients it
+    /// declares and a tag, returns `Err(HpkeError::InvalidMarshalledLength)`. If it declares no
+    /// recipients, returns `Err(HpkeError::InvalidRecipients)`. If a public key or encapsulated key
+    /// is invalid, returns the error from unmarshalling it.
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, HpkeError> {
+        if bytes.len() < COUNT_LEN {
+            return Err(HpkeError::InvalidMarshalledLength);
+        }
+        let (count, mut rest) = bytes.split_at(COUNT_LEN);
+        let count = BigEndian::read_u32(count) as usize;
+        if count == 0 {
+            return Err(HpkeError::InvalidRecipients);
+        }
+
+        // Check the length up front, so that a bogus count can't make us allocate
+        let entries_len = count
+            .checked_mul(WrappedKey::<A, Kem>::size())
+            .ok_or(HpkeError::InvalidMarshalledLength)?;
+        if rest.len() < entries_len + AeadTag::<A>::size() {
+            return Err(HpkeError::InvalidMarshalledLength);
+        }
+
+        let pk_size = Kem::PublicKey::size();
+        let enc_size = Kem::EncappedKey::size();
+        let wrapped_size = WrappedKey::<A, Kem>::size() - pk_size - enc_size;
+
+        let mut recipients = Vec::with_capacity(count);
+        for _ in 0..count {
+            let (pk_bytes, after_pk) = rest.split_at(pk_size);
+            let (enc_bytes, after_enc) = after_pk.split_at(enc_size);
+            let (wrapped_key, after_wrapped) = after_enc.split_at(wrapped_size);
+            rest = after_wrapped;
+
+            recipients.push(WrappedKey {
+                pk_recip: Kem::PublicKey::unmarshal(pk_bytes)?,
+                encapped_key: Kem::EncappedKey::unmarshal(enc_bytes)?,
+                wrapped_key: wrapped_key.to_vec(),
+                marker: PhantomData,
+            });
+        }
+
+        Ok(MultiRecipientCiphertext {
+            recipients,
+            ciphertext_and_tag: rest.to_vec(),
+            marker: PhantomData,
+        })
+    }
+}
+
+/// Computes the AAD that every content key is wrapped with. This is
+/// `Hash(count || pk_recip_1 || ... || pk_recip_count || ciphertext || tag)`.
+fn wrapping_aad<Kdf: KdfTrait, Kem: KemTrait>(
+    pk_recips: &[&Kem::PublicKey],
+    ciphertext_and_tag: &[u8],
+) -> GenericArray<u8, <Kdf::HashImpl as Digest>::OutputSize> {
+    let mut count = [0u8; COUNT_LEN];
+    BigEndian::write_u32(&mut count, pk_recips.len() as u32);
+
+    let mut hash = <Kdf::HashImpl as Digest>::new();
+    Digest::input(&mut hash, count);
+    for pk in pk_recips.iter() {
+        Digest::input(&mut hash, pk.marshal());
+    }
+    Digest::input(&mut hash, ciphertext_and_tag);
+    hash.result()
+}
+
+/// Encrypts `plaintext` once under a fresh content key, and then does a `single_shot_seal_to_vec`
+/// of the content key to every public key in `pk_recips`. The same `mode` and `info` are used for
+/// every recipient, and `aad` is the associated data of the payload. See
+/// `single_shot::single_shot_seal_to_vec` for more detail.
+///
+/// The recipients' public keys are included in the output in the clear, so that each recipient can
+/// find its own wrapped key.
+///
+/// Return Value
+/// ============
+/// Returns `Ok(ciphertext)` on success. If `pk_recips` is empty, has more than `u32::MAX`
+/// entries, or has the same public key more than once, returns
+/// `Err(HpkeError::InvalidRecipients)`. If an unspecified error happened during encryption, returns
+/// `Err(HpkeError::Encryption)`. Otherwise, errors are the same as in `single_shot_seal_to_vec`.
+pub fn seal_multi_recipient<A, Kdf, Kem, R>(
+    mode: &OpModeS<Kem, Kdf>,
+    pk_recips: &[Kem::PublicKey],
+    info: &[u8],
+    plaintext: &[u8],
+    aad: &[u8],
+    csprng: &mut R,
+) -> Result<MultiRecipientCiphertext<A, Kdf, Kem>, HpkeError>
+where
+    A: SealingAead,
+    Kdf: KdfTrait,
+    Kem: KemTrait,
+    R: CryptoRng + RngCore,
+{
+    if pk_recips.is_empty() || u32::try_from(pk_recips.len()).is_err() {
+        return Err(HpkeError::InvalidRecipients);
+    }
+    // A repeated recipient would get the same content key sealed to it twice, under the same PSK
+    // in the PSK modes. There's no reason to allow it.
+    for (i, pk) in pk_recips.iter().enumerate() {
+        if pk_recips[..i]
+            .iter()
+            .any(|other| bool::from(other.ct_eq(pk)))
+        {
+            return Err(HpkeError::InvalidRecipients);
+        }
+    }
+
+    // Every content key is used for exactly one encryption, so a fixed nonce is fine. As in
+    // open_multi_recipient, the key is wiped when it goes out of scope, and the AEAD is made from a
+    // copy that it's responsible for wiping.
+    let mut content_key = Zeroizing::new(vec![0u8; AeadKey::<A>::default().len()]);
+    csprng.fill_bytes(&mut content_key);
+    let encryptor = <A::AeadImpl as BaseNewAead>::new(GenericArray::clone_from_slice(&content_key));
+    let nonce = AeadNonce::<A>::default();
+
+    // Encrypt the payload in place, then append the tag
+    let mut ciphertext_and_tag = Vec::with_capacity(plaintext.len() + AeadTag::<A>::size());
+    ciphertext_and_tag.extend_from_slice(plaintext);
+    let tag_res = encryptor.encrypt_in_place_detached(&nonce, aad, &mut ciphertext_and_tag);
+    let tag = match tag_res {
+        Err(_) => return Err(HpkeError::Encryption),
+        Ok(t) => t,
+    };
+    ciphertext_and_tag.extend_from_slice(&tag);
+
+    // Now wrap the content key for everyone
+    let pk_refs: Vec<_> = pk_recips.iter().collect();
+    let wrap_aad = wrapping_aad::<Kdf, Kem>(&pk_refs, &ciphertext_and_tag);
+    let wrap_res: Result<Vec<_>, HpkeError> = pk_recips
+        .iter()
+        .map(|pk_recip| {
+            let (encapped_key, wrapped_key) = single_shot_seal_to_vec::<A, Kdf, Kem, R>(
+                mode,
+                pk_recip,
+                info,
+                &content_key,
+                &wrap_aad,
+                csprng,
+            )?;
+            Ok(WrappedKey {
+                pk_recip: pk_recip.clone(),
+                encapped_key,
+                wrapped_key,
+                marker: PhantomData,
+            })
+        })
+        .collect();
+
+    Ok(MultiRecipientCiphertext {
+        recipients: wrap_res?,
+        ciphertext_and_tag,
+        marker: PhantomData,
+    })
+}
+
+/// Finds the wrapped key belonging to `sk_recip`, unwraps the content key with a
+/// `single_shot_open_to_vec`, and decrypts the payload with it. `mode`, `info`, and `aad` must be
+/// the same as what the sender used. See `single_shot::single_shot_open_to_vec` for more detail.
+///
+/// Return Value
+/// ============
+/// Returns `Ok(plaintext)` on success. If the public key of `sk_recip` is not one of the
+/// recipients, returns `Err(HpkeError::InvalidRecipients)`. If the recipient list or payload was
+/// modified, or if the payload fails to decrypt, returns `Err(HpkeError::InvalidTag)`. Otherwise,
+/// errors are the same as in `single_shot_open_to_vec`.
+pub fn open_multi_recipient<A, Kdf, Kem>(
+    ciphertext: &MultiRecipientCiphertext<A, Kdf, Kem>,
+    mode: &OpModeR<Kem, Kdf>,
+    sk_recip: &Kem::PrivateKey,
+    info: &[u8],
+    aad: &[u8],
+) -> Result<Vec<u8>, HpkeError>
+where
+    A: SealingAead,
+    Kdf: KdfTrait,
+    Kem: KemTrait,
+{
+    let pk_recip = Kem::sk_to_pk(sk_recip);
+    let entry = ciphertext
+        .recipients
+        .iter()
+        .find(|r| bool::from(r.pk_recip.ct_eq(&pk_recip)))
+        .ok_or(HpkeError::InvalidRecipients)?;
+
+    // Unwrap the content key. This fails if anything covered by the wrapping AAD has changed.
+    let pk_refs: Vec<_> = ciphertext.recipients.iter().map(|r| &r.pk_recip).collect();
+    let wrap_aad = wrapping_aad::<Kdf, Kem>(&pk_refs, &ciphertext.ciphertext_and_tag);
+    let content_key = Zeroizing::new(single_shot_open_to_vec::<A, Kdf, Kem>(
+        mode,
+        sk_recip,
+        &entry.encapped_key,
+        info,
+        &entry.wrapped_key,
+        &wrap_aad,
+    )?);
+    if content_key.len() != AeadKey::<A>::default().len() {
+        return Err(HpkeError::InvalidMarshalledLength);
+    }
+    let decryptor = <A::AeadImpl as BaseNewAead>::new(GenericArray::clone_from_slice(&content_key));
+
+    // Split the payload into the ciphertext and the tag, and decrypt a copy of the ciphertext
+    let tag_idx = ciphertext
+        .ciphertext_and_tag
+        .len()
+        .checked_sub(AeadTag::<A>::size())
+        .ok_or(HpkeError::InvalidTag)?;
+    let (payload, tag) = ciphertext.ciphertext_and_tag.split_at(tag_idx);
+    let mut plaintext = payload.to_vec();
+    decryptor
+        .decrypt_in_place_detached(
+            &AeadNonce::<A>::default(),
+            aad,
+            &mut plaintext,
+            GenericArray::from_slice(tag),
+        )
+        .map_err(|_| HpkeError::InvalidTag)?;
+
+    Ok(plaintext)
+}
+
+#[cfg(test)]
+mod test {
+    use super::{open_multi_recipient, seal_multi_recipient, MultiRecipientCiphertext};
+    use crate::prelude::*;
+    use crate::{
+        aead::ChaCha20Poly1305,
+        kdf::HkdfSha256,
+        kem::{Kem as KemTrait, X25519HkdfSha256},
+        test_util::{gen_op_mode_pair, OpModeKind},
+        HpkeError,
+    };
+
+    type A = ChaCha20Poly1305;
+    type Kd = HkdfSha256;
+    type Ke = X25519HkdfSha256;
+
+    /// Tests that every recipient can open a serialized multi-recipient ciphertext
+    #[test]
+    fn test_multi_recipient_correctness() {
+        let mut csprng = rand::thread_rng();
+
+        let msg = b"Someone's in the kitchen with Dinah";
+        let aad = b"someone's in the kitchen I know";
+        let info = b"test_multi_recipient_correctness";
+
+        let keypairs: Vec<_> = (0..5).map(|_| Ke::gen_keypair(&mut csprng)).collect();
+        let pk_recips: Vec<_> = keypairs.iter().map(|(_, pk)| pk.clone()).collect();
+        let (sender_mode, receiver_mode) = gen_op_mode_pair::<Ke, Kd>(OpModeKind::Auth);
+
+        // Seal and serialize
+        let ciphertext = seal_multi_recipient::<A, Kd, Ke, _>(
+            &sender_mode,
+            &pk_recips,
+            info,
+            msg,
+            aad,
+            &mut csprng,
+        )
+        .unwrap();
+        let ciphertext_bytes = ciphertext.to_bytes();
+
+        // Deserialize and open as each recipient
+        let new_ciphertext =
+            MultiRecipientCiphertext::<A, Kd, Ke>::from_bytes(&ciphertext_bytes).unwrap();
+        assert_eq!(new_ciphertext.recipients().len(), keypairs.len());
+        assert_eq!(new_ciphertext.to_bytes(), ciphertext_bytes);
+
+        for (sk_recip, _) in keypairs.iter() {
+            let decrypted =
+                open_multi_recipient(&new_ciphertext, &receiver_mode, sk_recip, info, aad).unwrap();
+            assert_eq!(&decrypted[..], &msg[..]);
+        }
+
+        // Someone who isn't on the list can't open it
+        let (sk_other, _) = Ke::gen_keypair(&mut csprng);
+        match open_multi_recipient(&new_ciphertext, &receiver_mode, &sk_other, info, aad) {
+            Err(HpkeError::InvalidRecipients) => {} // Good, this isn't a recipient
+            Err(e) => panic!(
+                "open_multi_recipient() should have failed. Instead got {}",
+                e
+            ),
+            _ => panic!("open_multi_recipient() should have failed. Instead it succeeded"),
+        }
+    }
+
+    /// Tests that every recipient can open a PSK-mode ciphertext, where the same PSK is used for
+    /// every recipient's wrapped key, and that a repeated recipient is rejected
+    #[test]
+    fn test_multi_recipient_psk() {
+        let mut csprng = rand::thread_rng();
+
+        let msg = b"Strummin' on the old banjo";
+        let info = b"test_multi_recipient_psk";
+
+        let keypairs: Vec<_> = (0..3).map(|_| Ke::gen_keypair(&mut csprng)).collect();
+        let mut pk_recips: Vec<_> = keypairs.iter().map(|(_, pk)| pk.clone()).collect();
+        let (sender_mode, receiver_mode) = gen_op_mode_pair::<Ke, Kd>(OpModeKind::Psk);
+
+        let ciphertext = seal_multi_recipient::<A, Kd, Ke, _>(
+            &sender_mode,
+            &pk_recips,
+            info,
+            msg,
+            b"",
+            &mut csprng,
+        )
+        .unwrap();
+        for (sk_recip, _) in keypairs.iter() {
+            let decrypted =
+                open_multi_recipient(&ciphertext, &receiver_mode, sk_recip, info, b"").unwrap();
+            assert_eq!(&decrypted[..], &msg[..]);
+        }
+
+        // Sealing to the same recipient twice is an error
+        pk_recips.push(pk_recips[1].clone());
+        let res = seal_multi_recipient::<A, Kd, Ke, _>(
+            &sender_mode,
+            &pk_recips,
+            info,
+            msg,
+            b"",
+            &mut csprng,
+        );
+        match res {
+            Err(HpkeError::InvalidRecipients) => {} // Good, there's a duplicate recipient
+            Err(e) => panic!(
+                "seal_multi_recipient() should have failed. Instead got {}",
+                e
+            ),
+            _ => panic!("seal_multi_recipient() should have failed. Instead it succeeded"),
+        }
+    }
+
+    /// Tests that modifying the recipient list or the payload makes opening fail
+    #[test]
+    fn test_multi_recipient_tampering() {
+        let mut csprng = rand::thread_rng();
+
+        let info = b"test_multi_recipient_tampering";
+        let keypairs: Vec<_> = (0..3).map(|_| Ke::gen_keypair(&mut csprng)).collect();
+        let pk_recips: Vec<_> = keypairs.iter().map(|(_, pk)| pk.clone()).collect();
+        let (sender_mode, receiver_mode) = gen_op_mode_pair::<Ke, Kd>(OpModeKind::Base);
+        let sk_recip = &keypairs[0].0;
+
+        let ciphertext_bytes = seal_multi_recipient::<A, Kd, Ke, _>(
+            &sender_mode,
+            &pk_recips,
+            info,
+            b"hello",
+            b"",
+            &mut csprng,
+        )
+        .unwrap()
+        .to_bytes();
+        let open = |bytes: &[u8]| {
+            let ciphertext = MultiRecipientCiphertext::<A, Kd, Ke>::from_bytes(bytes).unwrap();
+            open_multi_recipient(&ciphertext, &receiver_mode, sk_recip, info, b"")
+        };
+
+        // Dropping the last recipient changes the recipient list that's bound to the wrapped keys
+        let entry_len = (ciphertext_bytes.len() - 4 - 5 - 16) / 3;
+        let mut dropped = Vec::new();
+        dropped.extend_from_slice(&[0, 0, 0, 2]);
+        dropped.extend_from_slice(&ciphertext_bytes[4..4 + 2 * entry_len]);
+        dropped.extend_from_slice(&ciphertext_bytes[4 + 3 * entry_len..]);
+
+        // Flipping a bit in the payload changes the payload that's bound to the wrapped keys
+        let mut flipped = ciphertext_bytes.clone();
+        *flipped.last_mut().unwrap() ^= 1;
+
+        for bad_bytes in &[dropped, flipped] {
+            match open(bad_bytes) {
+                Err(HpkeError::InvalidTag) => {} // Good, this was tampered with
+                Err(e) => panic!(
+                    "open_multi_recipient() should have failed. Instead got {}",
+                    e
+                ),
+                _ => panic!("open_multi_recipient() should have failed. Instead it succeeded"),
+            }
+        }
+
+        // Truncating it by a byte cuts into the tag
+        let truncated = &ciphertext_bytes[..4 + 3 * entry_len + 15];
+        match MultiRecipientCiphertext::<A, Kd, Ke>::from_bytes(truncated) {
+            Err(HpkeError::InvalidMarshalledLength) => {} // Good, this is too short
+            Err(e) => panic!("from_bytes() should have failed. Instead got {}", e),
+            _ => panic!("from_bytes() should have failed. Instead it succeeded"),
+        }
+    }
+}
@@ -4,14 +4,23 @@
 
 #[cfg(not(feature = "std"))]
 mod reexports {
-    pub use alloc::string::String;
     pub use alloc::vec::Vec;
+
+    // Only the agile module uses this
+    #[cfg(feature = "alloc")]
+    pub use alloc::boxed::Box;
+
+    // Only the pkcs8 and jwk modules use this
+    #[cfg(any(feature = "pkcs8", feature = "jwk"))]
+    pub use alloc::string::String;
 }
 
 #[cfg(feature = "std")]
 mod reexports {
+    pub use std::{boxed::Box, vec::Vec};
+
+    #[cfg(any(feature = "pkcs8", feature = "jwk"))]
     pub use std::string::String;
-    pub use std::vec::Vec;
 }
 
 pub use self::reexports::*;
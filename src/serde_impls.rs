@@ -0,0 +1,438 @@
+//! `Serialize` and `Deserialize` impls for every marshallable type in this crate, plus PSKs. In
+//! human-readable formats (e.g., JSON), bytes are encoded as lowercase hex strings. In binary
+//! formats (e.g., CBOR or bincode), they are encoded as raw bytes.
+
+use crate::prelude::*;
+use crate::{
+    aead::{Aead, AeadTag},
+    kdf::Kdf as KdfTrait,
+    kem::{
+        ml_kem::{ml_kem_1024, ml_kem_768},
+        EncappedKey,
+    },
+    kex::{ecdh_nistp, x25519, x448, KeyExchange, Marshallable, Unmarshallable},
+    op_mode::{Psk, PskBundle},
+};
+
+use core::fmt;
+
+use serde::{
+    de::{self, Deserialize, Deserializer, MapAccess, SeqAccess, Visitor},
+    ser::{Serialize, SerializeStruct, Serializer},
+};
+use zeroize::Zeroizing;
+
+/// Wraps a byte slice so that it serializes as hex or raw bytes, depending on the format
+struct BytesRef<'a>(&'a [u8]);
+
+impl<'a> Serialize for BytesRef<'a> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        if serializer.is_human_readable() {
+            // The bytes might be a private key, so the hex is wiped once it's been serialized
+            let mut hex = Zeroizing::new(Vec::with_capacity(2 * self.0.len()));
+            for b in self.0 {
+                hex.push(hex_char(b >> 4));
+                hex.push(hex_char(b & 0x0f));
+            }
+            // Every byte in hex is an ASCII character, so this can't fail
+            serializer.serialize_str(core::str::from_utf8(&hex).unwrap())
+        } else {
+            serializer.serialize_bytes(self.0)
+        }
+    }
+}
+
+/// The owned counterpart of `BytesRef`. This deserializes from hex or raw bytes, depending on the
+/// format. The bytes might be a private key, so they're wiped when this is dropped.
+struct ByteBuf(Zeroizing<Vec<u8>>);
+
+// Encodes a nibble as a lowercase hex digit. This is done with arithmetic rather than a table
+// lookup or a branch, so that the time it takes doesn't depend on the nibble.
+fn hex_char(n: u8) -> u8 {
+    let n = i16::from(n);
+    // For n > 9, (9 - n) >> 8 is all ones, which adds the gap between '9' + 1 and 'a'
+    (n + i16::from(b'0') + (((9 - n) >> 8) & i16::from(b'a' - b'0' - 10))) as u8
+}
+
+// Decodes a single hex digit. Both upper and lower case are accepted. Returns the value of the
+// digit, or -1 if it isn't one. Like `hex_char`, this doesn't branch on its input. Each range
+// check is (lower_bound - 1 - c) & (c - upper_bound - 1), which is negative exactly when c is in
+// the range, and so is all ones after the shift.
+fn hex_digit(c: u8) -> i16 {
+    let c = i16::from(c);
+    let mut ret = -1;
+    // '0'..='9' map to 0..=9
+    ret += (((0x2f - c) & (c - 0x3a)) >> 8) & (c - 0x2f);
+    // 'A'..='F' map to 10..=15
+    ret += (((0x40 - c) & (c - 0x47)) >> 8) & (c - 0x36);
+    // 'a'..='f' map to 10..=15
+    ret += (((0x60 - c) & (c - 0x67)) >> 8) & (c - 0x56);
+    ret
+}
+
+struct HexVisitor;
+
+impl<'de> Visitor<'de> for HexVisitor {
+    type Value = ByteBuf;
+
+    fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("a hex string")
+    }
+
+    fn visit_str<E: de::Error>(self, v: &str) -> Result<ByteBuf, E> {
+        let v = v.as_bytes();
+        let pairs = v.chunks_exact(2);
+        if !pairs.remainder().is_empty() {
+            return Err(E::invalid_length(v.len(), &"an even number of hex digits"));
+        }
+
+        // Invalid digits are only checked for at the end, so we don't stop early on a secret
+        let mut buf = Zeroizing::new(Vec::with_capacity(v.len() / 2));
+        let mut invalid = 0i16;
+        for pair in pairs {
+            let (hi, lo) = (hex_digit(pair[0]), hex_digit(pair[1]));
+            invalid |= hi | lo;
+            buf.push(((hi << 4) | lo) as u8);
+        }
+        if invalid < 0 {
+            return Err(E::custom("invalid hex digit"));
+        }
+
+        Ok(ByteBuf(buf))
+    }
+}
+
+struct BytesVisitor;
+
+impl<'de> Visitor<'de> for BytesVisitor {
+    type Value = ByteBuf;
+
+    fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("a byte string")
+    }
+
+    fn visit_bytes<E: de::Error>(self, v: &[u8]) -> Result<ByteBuf, E> {
+        Ok(ByteBuf(Zeroizing::new(v.to_vec())))
+    }
+
+    fn visit_byte_buf<E: de::Error>(self, v: Vec<u8>) -> Result<ByteBuf, E> {
+        Ok(ByteBuf(Zeroizing::new(v)))
+    }
+
+    // Some binary formats encode bytes as a sequence of u8s
+    fn visit_seq<A: SeqAccess<'de>>(self, mut seq: A) -> Result<ByteBuf, A::Error> {
+        let mut buf = Vec::with_capacity(seq.size_hint().unwrap_or(0));
+        while let Some(b) = seq.next_element()? {
+            buf.push(b);
+        }
+
+        Ok(ByteBuf(Zeroizing::new(buf)))
+    }
+}
+
+impl<'de> Deserialize<'de> for ByteBuf {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<ByteBuf, D::Error> {
+        if deserializer.is_human_readable() {
+            deserializer.deserialize_str(HexVisitor)
+        } else {
+            deserializer.deserialize_byte_buf(BytesVisitor)
+        }
+    }
+}
+
+// Implements Serialize and Deserialize for a type that's Marshallable and Unmarshallable. The
+// serialized form is the marshalled form, and deserialization fails if unmarshalling fails.
+macro_rules! impl_serde {
+    (<$($gen:ident: $bound:path)?> $ty:ty) => {
+        impl<$($gen: $bound)?> Serialize for $ty {
+            fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+                BytesRef(&self.marshal()).serialize(serializer)
+            }
+        }
+
+        impl<'de, $($gen: $bound)?> Deserialize<'de> for $ty {
+            fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+                let buf = ByteBuf::deserialize(deserializer)?;
+                <$ty as Unmarshallable>::unmarshal(&buf.0).map_err(de::Error::custom)
+            }
+        }
+    };
+    ($ty:ty) => {
+        impl_serde!(<> $ty);
+    };
+}
+
+impl_serde!(x25519::PublicKey);
+impl_serde!(x25519::PrivateKey);
+impl_serde!(x448::PublicKey);
+impl_serde!(x448::PrivateKey);
+impl_serde!(ecdh_nistp::p256::PublicKey);
+impl_serde!(ecdh_nistp::p256::PrivateKey);
+impl_serde!(ecdh_nistp::p384::PublicKey);
+impl_serde!(ecdh_nistp::p384::PrivateKey);
+impl_serde!(ecdh_nistp::p521::PublicKey);
+impl_serde!(ecdh_nistp::p521::PrivateKey);
+impl_serde!(<Kex: KeyExchange> EncappedKey<Kex>);
+impl_serde!(ml_kem_768::PublicKey);
+impl_serde!(ml_kem_768::PrivateKey);
+impl_serde!(ml_kem_768::EncappedKey);
+impl_serde!(ml_kem_1024::PublicKey);
+impl_serde!(ml_kem_1024::PrivateKey);
+impl_serde!(ml_kem_1024::EncappedKey);
+impl_serde!(<A: Aead> AeadTag<A>);
+
+impl<Kdf: KdfTrait> Serialize for Psk<Kdf> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        BytesRef(&self.bytes).serialize(serializer)
+    }
+}
+
+impl<'de, Kdf: KdfTrait> Deserialize<'de> for Psk<Kdf> {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let mut buf = ByteBuf::deserialize(deserializer)?;
+        Ok(Psk::from_bytes(core::mem::take(&mut *buf.0)))
+    }
+}
+
+// A PskBundle is serialized as the struct { psk, psk_id }
+impl<Kdf: KdfTrait> Serialize for PskBundle<Kdf> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut state = serializer.serialize_struct("PskBundle", 2)?;
+        state.serialize_field("psk", &self.psk)?;
+        state.serialize_field("psk_id", &BytesRef(&self.psk_id))?;
+        state.end()
+    }
+}
+
+const PSK_BUNDLE_FIELDS: &[&str] = &["psk", "psk_id"];
+
+/// The field names of a PskBundle
+enum PskBundleField {
+    Psk,
+    PskId,
+}
+
+struct PskBundleFieldVisitor;
+
+impl<'de> Visitor<'de> for PskBundleFieldVisitor {
+    type Value = PskBundleField;
+
+    fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("`psk` or `psk_id`")
+    }
+
+    fn visit_str<E: de::Error>(self, v: &str) -> Result<PskBundleField, E> {
+        match v {
+            "psk" => Ok(PskBundleField::Psk),
+            "psk_id" => Ok(PskBundleField::PskId),
+            _ => Err(E::unknown_field(v, PSK_BUNDLE_FIELDS)),
+        }
+    }
+}
+
+impl<'de> Deserialize<'de> for PskBundleField {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        deserializer.deserialize_identifier(PskBundleFieldVisitor)
+    }
+}
+
+struct PskBundleVisitor<Kdf: KdfTrait>(core::marker::PhantomData<Kdf>);
+
+impl<'de, Kdf: KdfTrait> Visitor<'de> for PskBundleVisitor<Kdf> {
+    type Value = PskBundle<Kdf>;
+
+    fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("struct PskBundle")
+    }
+
+    fn visit_seq<A: SeqAccess<'de>>(self, mut seq: A) -> Result<PskBundle<Kdf>, A::Error> {
+        let psk: Psk<Kdf> = seq
+            .next_element()?
+            .ok_or_else(|| de::Error::invalid_length(0, &self))?;
+        let psk_id: ByteBuf = seq
+            .next_element()?
+            .ok_or_else(|| de::Error::invalid_length(1, &self))?;
+
+        Ok(PskBundle {
+            psk,
+            psk_id: psk_id.0.to_vec(),
+        })
+    }
+
+    fn visit_map<A: MapAccess<'de>>(self, mut map: A) -> Result<PskBundle<Kdf>, A::Error> {
+        let mut psk: Option<Psk<Kdf>> = None;
+        let mut psk_id: Option<ByteBuf> = None;
+
+        while let Some(key) = map.next_key()? {
+            match key {
+                PskBundleField::Psk => {
+                    if psk.is_some() {
+                        return Err(de::Error::duplicate_field("psk"));
+                    }
+                    psk = Some(map.next_value()?);
+                }
+                PskBundleField::PskId => {
+                    if psk_id.is_some() {
+                        return Err(de::Error::duplicate_field("psk_id"));
+                    }
+                    psk_id = Some(map.next_value()?);
+                }
+            }
+        }
+
+        Ok(PskBundle {
+            psk: psk.ok_or_else(|| de::Error::missing_field("psk"))?,
+            psk_id: psk_id
+                .ok_or_else(|| de::Error::missing_field("psk_id"))?
+                .0
+                .to_vec(),
+        })
+    }
+}
+
+impl<'de, Kdf: KdfTrait> Deserialize<'de> for PskBundle<Kdf> {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        deserializer.deserialize_struct(
+            "PskBundle",
+            PSK_BUNDLE_FIELDS,
+            PskBundleVisitor(core::marker::PhantomData),
+        )
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::{
+        aead::{AeadTag, ChaCha20Poly1305},
+        kdf::HkdfSha256,
+        kem::{EncappedKey, Kem, MlKem1024, MlKem768},
+        kex::{KeyExchange, Marshallable, Unmarshallable, P256, X25519, X448},
+        op_mode::{Psk, PskBundle},
+    };
+
+    use serde_test::{assert_ser_tokens, Configure, Token};
+
+    // Serializes and deserializes the given value in JSON (human-readable) and bincode (binary),
+    // and checks that the marshalled forms are unchanged
+    macro_rules! assert_roundtrip {
+        ($ty:ty, $val:expr) => {{
+            let val: $ty = $val;
+            let marshalled = val.marshal();
+
+            let json = serde_json::to_string(&val).unwrap();
+            assert_eq!(json, format!("\"{}\"", hex::encode(&marshalled)));
+            let from_json: $ty = serde_json::from_str(&json).unwrap();
+            assert_eq!(from_json.marshal(), marshalled);
+
+            let bin = bincode::serialize(&val).unwrap();
+            let from_bin: $ty = bincode::deserialize(&bin).unwrap();
+            assert_eq!(from_bin.marshal(), marshalled);
+        }};
+    }
+
+    /// Tests that keys, encapsulated keys, and tags survive a serialization round trip
+    #[test]
+    fn test_marshallable_roundtrip() {
+        let mut csprng = rand::thread_rng();
+
+        macro_rules! test_kex {
+            ($kex:ty) => {{
+                let (sk, pk) = <$kex>::gen_keypair(&mut csprng);
+                assert_roundtrip!(<$kex as KeyExchange>::PrivateKey, sk);
+                assert_roundtrip!(<$kex as KeyExchange>::PublicKey, pk.clone());
+
+                let encapped_key = EncappedKey::<$kex>::unmarshal(&pk.marshal()).unwrap();
+                assert_roundtrip!(EncappedKey<$kex>, encapped_key);
+            }};
+        }
+
+        test_kex!(X25519);
+        test_kex!(X448);
+        test_kex!(P256);
+
+        // ML-KEM isn't a KeyExchange, so its types are tested through the Kem trait
+        macro_rules! test_kem {
+            ($kem:ty) => {{
+                let (sk, pk) = <$kem>::gen_keypair(&mut csprng);
+                let (_, encapped_key) = <$kem>::encap(&pk, &mut csprng).unwrap();
+                assert_roundtrip!(<$kem as Kem>::PrivateKey, sk);
+                assert_roundtrip!(<$kem as Kem>::PublicKey, pk);
+                assert_roundtrip!(<$kem as Kem>::EncappedKey, encapped_key);
+            }};
+        }
+
+        test_kem!(MlKem768);
+        test_kem!(MlKem1024);
+
+        let tag = AeadTag::<ChaCha20Poly1305>::unmarshal(&[0xab; 16]).unwrap();
+        assert_roundtrip!(AeadTag<ChaCha20Poly1305>, tag);
+    }
+
+    /// Tests that bytes are hex in human-readable formats and raw bytes otherwise
+    #[test]
+    fn test_serde_tokens() {
+        let tag = AeadTag::<ChaCha20Poly1305>::unmarshal(&[0xab; 16]).unwrap();
+        assert_ser_tokens(
+            &(&tag).readable(),
+            &[Token::Str("abababababababababababababababab")],
+        );
+        assert_ser_tokens(&(&tag).compact(), &[Token::Bytes(&[0xab; 16])]);
+    }
+
+    /// Tests that the constant-time hex encoder and decoder agree with the usual definitions
+    #[test]
+    fn test_hex_digits() {
+        use super::{hex_char, hex_digit};
+
+        for n in 0u8..16 {
+            assert_eq!(hex_char(n), b"0123456789abcdef"[n as usize]);
+        }
+        for c in 0u8..=255 {
+            let expected = (c as char).to_digit(16).map_or(-1, |d| d as i16);
+            assert_eq!(hex_digit(c), expected, "wrong value for {:#04x}", c);
+        }
+    }
+
+    /// Tests that deserializing invalid values fails
+    #[test]
+    fn test_deserialize_invalid() {
+        // Wrong length
+        assert!(serde_json::from_str::<AeadTag<ChaCha20Poly1305>>("\"abab\"").is_err());
+        // Odd number of hex digits
+        assert!(serde_json::from_str::<AeadTag<ChaCha20Poly1305>>("\"aba\"").is_err());
+        // Not hex
+        assert!(serde_json::from_str::<AeadTag<ChaCha20Poly1305>>(
+            "\"zzzzzzzzzzzzzzzzzzzzzzzzzzzzzzzz\""
+        )
+        .is_err());
+        // Not on the curve
+        let bad_point = format!("\"04{}\"", "00".repeat(64));
+        assert!(serde_json::from_str::<<P256 as KeyExchange>::PublicKey>(&bad_point).is_err());
+    }
+
+    /// Tests that PSK bundles survive a serialization round trip
+    #[test]
+    fn test_psk_bundle_roundtrip() {
+        let bundle = PskBundle::<HkdfSha256> {
+            psk: Psk::from_bytes(vec![1u8; 32]),
+            psk_id: b"hello".to_vec(),
+        };
+
+        let json = serde_json::to_string(&bundle).unwrap();
+        assert_eq!(
+            json,
+            format!(
+                "{{\"psk\":\"{}\",\"psk_id\":\"68656c6c6f\"}}",
+                "01".repeat(32)
+            )
+        );
+        let from_json: PskBundle<HkdfSha256> = serde_json::from_str(&json).unwrap();
+        assert_eq!(&from_json.psk.bytes[..], &bundle.psk.bytes[..]);
+        assert_eq!(from_json.psk_id, bundle.psk_id);
+
+        let bin = bincode::serialize(&bundle).unwrap();
+        let from_bin: PskBundle<HkdfSha256> = bincode::deserialize(&bin).unwrap();
+        assert_eq!(&from_bin.psk.bytes[..], &bundle.psk.bytes[..]);
+        assert_eq!(from_bin.psk_id, bundle.psk_id);
+    }
+}
@@ -1,90 +1,94 @@
 use crate::prelude::*;
 use crate::{
-    aead::{Aead, AeadCtx},
+    aead::{Aead, AeadCtx, AeadCtxR, AeadCtxS, ExportOnly},
     kdf::{labeled_extract, Kdf as KdfTrait, LabeledExpand},
-    kem::{self, EncappedKey, Kem as KemTrait, SharedSecret},
-    kex::KeyExchange,
+    kem::{Kem as KemTrait, SharedSecret},
     op_mode::{OpMode, OpModeR, OpModeS},
-    util::static_zeros,
+    util::full_suite_id,
     HpkeError,
 };
 
-use byteorder::{BigEndian, WriteBytesExt};
 use digest::{generic_array::GenericArray, Digest};
 use rand::{CryptoRng, RngCore};
+use zeroize::Zeroize;
 
-/* struct {
-        // Mode and algorithms
-        uint8 mode;
-        uint16 kem_id;
-        uint16 kdf_id;
-        uint16 aead_id;
-
-        // Cryptographic hash of application-supplied pskID
-        opaque pskID_hash[Nh];
-
-        // Cryptographic hash of application-supplied info
-        opaque info_hash[Nh];
-    } HPKEContext;
-*/
-
-/// Secret generated in `derive_enc_ctx` and stored in `AeadCtx`
+/// Secret generated in `derive_enc_ctx` and stored in `AeadCtxS` and `AeadCtxR`
 pub(crate) type ExporterSecret<K> =
     GenericArray<u8, <<K as KdfTrait>::HashImpl as Digest>::OutputSize>;
 
-// This is the KeySchedule function defined in draft02 §6.1. It runs a KDF over all the parameters,
+// RFC 9180 §5.1:
+//     def VerifyPSKInputs(mode, psk, psk_id):
+//       got_psk = (psk != default_psk)
+//       got_psk_id = (psk_id != default_psk_id)
+//       if got_psk != got_psk_id:
+//         raise Exception("Inconsistent PSK inputs")
+//       if got_psk and (mode in [mode_base, mode_auth]):
+//         raise Exception("PSK input provided when not needed")
+//       if (not got_psk) and (mode in [mode_psk, mode_auth_psk]):
+//         raise Exception("Missing required PSK input")
+/// Checks that the mode has a PSK and PSK ID if and only if it's a PSK mode
+///
+/// Return Value
+/// ============
+/// Returns `Ok(())` if the PSK inputs are consistent with the mode. Otherwise, returns
+/// `Err(HpkeError::InvalidPsk)`.
+fn verify_psk_inputs<Kem: KemTrait, O: OpMode<Kem>>(mode: &O) -> Result<(), HpkeError> {
+    let got_psk = !mode.get_psk_bytes().is_empty();
+    let got_psk_id = !mode.get_psk_id().is_empty();
+    // The PSK modes are mode_psk = 0x01 and mode_auth_psk = 0x03
+    let is_psk_mode = mode.mode_id() & 0x01 == 0x01;
+
+    if got_psk != got_psk_id || got_psk != is_psk_mode {
+        Err(HpkeError::InvalidPsk)
+    } else {
+        Ok(())
+    }
+}
+
+// This is the KeySchedule function defined in RFC 9180 §5.1. It runs a KDF over all the parameters,
 // inputs, and secrets, and spits out a key-nonce pair to be used for symmetric encryption
 fn derive_enc_ctx<A, Kdf, Kem, O>(
     mode: &O,
-    shared_secret: SharedSecret<Kem::Kex>,
+    shared_secret: SharedSecret<Kem>,
     info: &[u8],
 ) -> AeadCtx<A, Kdf>
 where
     A: Aead,
     Kdf: KdfTrait,
     Kem: KemTrait,
-    O: OpMode<Kem::Kex>,
+    O: OpMode<Kem>,
 {
+    // All the labeled KDF calls in the key schedule are bound to the full ciphersuite
+    let suite_id = full_suite_id::<A, Kdf, Kem>();
+
     // In KeySchedule(),
-    //     ciphersuite = concat(encode_big_endian(kem_id, 2),
-    //                          encode_big_endian(kdf_id, 2),
-    //                          encode_big_endian(aead_id, 2))
-    //     pskID_hash = LabeledExtract(zero(Nh), "pskID", pskID)
-    //     info_hash = LabeledExtract(zero(Nh), "info", info)
-    //     context = concat(ciphersuite, mode, pskID_hash, info_hash)
-    let context_bytes: Vec<u8> = {
-        let mut buf = Vec::new();
-
-        // This relies on <Vec<u8> as Write>, which never errors, so unwrap() is justified
-        buf.write_u16::<BigEndian>(Kem::KEM_ID).unwrap();
-        buf.write_u16::<BigEndian>(Kdf::KDF_ID).unwrap();
-        buf.write_u16::<BigEndian>(A::AEAD_ID).unwrap();
-
-        buf.write_u8(mode.mode_id()).unwrap();
-
-        let zeros = static_zeros::<Kdf>();
-        let (psk_id_hash, _) = labeled_extract::<Kdf>(zeros, b"pskID_hash", mode.get_psk_id());
-        let (info_hash, _) = labeled_extract::<Kdf>(zeros, b"info", info);
-
-        buf.extend(psk_id_hash.as_slice());
-        buf.extend(info_hash.as_slice());
-
-        buf
+    //   psk_id_hash = LabeledExtract("", "psk_id_hash", psk_id)
+    //   info_hash = LabeledExtract("", "info_hash", info)
+    //   key_schedule_context = concat(mode, psk_id_hash, info_hash)
+    let key_schedule_context: Vec<u8> = {
+        let (psk_id_hash, _) =
+            labeled_extract::<Kdf>(b"", &suite_id, b"psk_id_hash", mode.get_psk_id());
+        let (info_hash, _) = labeled_extract::<Kdf>(b"", &suite_id, b"info_hash", info);
+
+        [
+            &[mode.mode_id()],
+            psk_id_hash.as_slice(),
+            info_hash.as_slice(),
+        ]
+        .concat()
     };
 
     // In KeySchedule(),
-    //   extracted_psk = LabeledExtract(zero(Nh), "psk", psk)
-    //   secret = LabeledExtract(extracted_psk, "zz", zz)
-    //   key = LabeledExpand(secret, "key", context, Nk)
-    //   nonce = LabeledExpand(secret, "nonce", context, Nn)
-    //   exporter_secret = LabeledExpand(secret, "exp", context, Nh)
-    //   return Context(key, nonce, exporter_secret)
+    //   secret = LabeledExtract(shared_secret, "secret", psk)
+    //   key = LabeledExpand(secret, "key", key_schedule_context, Nk)
+    //   base_nonce = LabeledExpand(secret, "base_nonce", key_schedule_context, Nn)
+    //   exporter_secret = LabeledExpand(secret, "exp", key_schedule_context, Nh)
+    //   return Context<ROLE>(key, base_nonce, 0, exporter_secret)
     //
     // Instead of `secret` we derive an HKDF context which we run .expand() on to derive the
     // key-nonce pair.
-    let (extracted_psk, _) =
-        labeled_extract::<Kdf>(static_zeros::<Kdf>(), b"psk_hash", mode.get_psk_bytes());
-    let (_, secret_ctx) = labeled_extract::<Kdf>(&extracted_psk, b"zz", &shared_secret);
+    let (_, secret_ctx) =
+        labeled_extract::<Kdf>(&shared_secret.0, &suite_id, b"secret", mode.get_psk_bytes());
 
     // Empty fixed-size buffers
     let mut key = crate::aead::AeadKey::<A>::default();
@@ -94,97 +98,128 @@ where
     // Fill the key, nonce, and exporter secret. This only errors if the output values are 255x the
     // digest size of the hash function. Since these values are fixed at compile time, we don't
     // worry about it.
+    // RFC 9180 §5.1: The export-only AEAD has no key or nonce, so we don't derive them
+    if A::AEAD_ID != ExportOnly::AEAD_ID {
+        secret_ctx
+            .labeled_expand(&suite_id, b"key", &key_schedule_context, key.as_mut_slice())
+            .expect("aead key len is way too big");
+        secret_ctx
+            .labeled_expand(
+                &suite_id,
+                b"base_nonce",
+                &key_schedule_context,
+                nonce.as_mut_slice(),
+            )
+            .expect("nonce len is way too big");
+    }
     secret_ctx
-        .labeled_expand(b"key", &context_bytes, key.as_mut_slice())
-        .expect("aead key len is way too big");
-    secret_ctx
-        .labeled_expand(b"nonce", &context_bytes, nonce.as_mut_slice())
-        .expect("nonce len is way too big");
-    secret_ctx
-        .labeled_expand(b"exp", &context_bytes, exporter_secret.as_mut_slice())
+        .labeled_expand(
+            &suite_id,
+            b"exp",
+            &key_schedule_context,
+            exporter_secret.as_mut_slice(),
+        )
         .expect("exporter secret len is way too big");
 
-    AeadCtx::new(key, nonce, exporter_secret)
+    let ctx = AeadCtx::new(&key, &nonce, &exporter_secret, suite_id);
+
+    // The context has its own copies now
+    key.as_mut_slice().zeroize();
+    nonce.as_mut_slice().zeroize();
+    exporter_secret.as_mut_slice().zeroize();
+
+    ctx
 }
 
-// From draft02 §6.5:
-//     def SetupAuthPSKI(pkR, info, psk, pskID, skI):
-//       zz, enc = AuthEncap(pkR, skI)
-//       pkIm = Marshal(pk(skI))
-//       return enc, KeySchedule(mode_psk_auth, pkR, zz, enc, info,
-//                               psk, pskID, pkIm)
+// From RFC 9180 §5.1.4:
+//     def SetupAuthPSKS(pkR, info, psk, psk_id, skS):
+//       shared_secret, enc = AuthEncap(pkR, skS)
+//       return enc, KeyScheduleS(mode_auth_psk, shared_secret, info,
+//                                psk, psk_id)
 /// Initiates an encryption context to the given recipient. Does an "authenticated" encapsulation
-/// if `sk_sender_id` is set. This ties the sender identity to the shared secret.
+/// if the mode has a sender identity keypair. This ties the sender identity to the shared secret.
 ///
 /// Return Value
 /// ============
-/// On success, returns an encapsulated public key (intended to be sent to the recipient), and an
-/// encryption context. If an error happened during key exchange, returns
-/// `Err(HpkeError::InvalidKeyExchange)`. This is the only possible error.
+/// On success, returns an encapsulated public key (intended to be sent to the recipient), and the
+/// sender's encryption context. If the mode is a PSK mode with an empty PSK or PSK ID, returns
+/// `Err(HpkeError::InvalidPsk)`. If an error happened during key exchange, returns
+/// `Err(HpkeError::InvalidKeyExchange)`.
 pub fn setup_sender<A, Kdf, Kem, R>(
-    mode: &OpModeS<Kem::Kex, Kdf>,
-    pk_recip: &<Kem::Kex as KeyExchange>::PublicKey,
+    mode: &OpModeS<Kem, Kdf>,
+    pk_recip: &Kem::PublicKey,
     info: &[u8],
     csprng: &mut R,
-) -> Result<(EncappedKey<Kem::Kex>, AeadCtx<A, Kdf>), HpkeError>
+) -> Result<(Kem::EncappedKey, AeadCtxS<A, Kdf>), HpkeError>
 where
     A: Aead,
     Kdf: KdfTrait,
     Kem: KemTrait,
     R: CryptoRng + RngCore,
 {
-    // If the identity key is set, use it
-    let sender_id_keypair = mode.get_sender_id_keypair();
-    // Do the encapsulation
-    let (shared_secret, encapped_key) = kem::encap::<Kem, _>(pk_recip, sender_id_keypair, csprng)?;
+    verify_psk_inputs(mode)?;
+
+    // Do the encapsulation. If the identity key is set, use it.
+    let (shared_secret, encapped_key) = match mode.get_sender_id_keypair() {
+        Some(sender_id_keypair) => Kem::auth_encap(pk_recip, sender_id_keypair, csprng)?,
+        None => Kem::encap(pk_recip, csprng)?,
+    };
     // Use everything to derive an encryption context
     let enc_ctx = derive_enc_ctx::<_, _, Kem, _>(mode, shared_secret, info);
 
-    Ok((encapped_key, enc_ctx))
+    Ok((encapped_key, enc_ctx.into()))
 }
 
-//  From draft02 §6.5:
-//     def SetupAuthPSKR(enc, skR, info, psk, pskID, pkI):
-//       zz = AuthDecap(enc, skR, pkI)
-//       pkIm = Marshal(pkI)
-//       return KeySchedule(mode_psk_auth, pk(skR), zz, enc, info,
-//                          psk, pskID, pkIm)
+// From RFC 9180 §5.1.4:
+//     def SetupAuthPSKR(enc, skR, info, psk, psk_id, pkS):
+//       shared_secret = AuthDecap(enc, skR, pkS)
+//       return KeyScheduleR(mode_auth_psk, shared_secret, info,
+//                           psk, psk_id)
 /// Initiates an encryption context given a private key `sk` and an encapsulated key which was
 /// encapsulated to `sk`'s corresponding public key
 ///
 /// Return Value
 /// ============
-/// On success, returns an encryption context. If an error happened during key exchange, returns
-/// `Err(HpkeError::InvalidKeyExchange)`. This is the only possible error.
+/// On success, returns the receiver's encryption context. If the mode is a PSK mode with an empty
+/// PSK or PSK ID, returns `Err(HpkeError::InvalidPsk)`. If an error happened during key exchange,
+/// returns `Err(HpkeError::InvalidKeyExchange)`.
 pub fn setup_receiver<A, Kdf, Kem>(
-    mode: &OpModeR<Kem::Kex, Kdf>,
-    sk_recip: &<Kem::Kex as KeyExchange>::PrivateKey,
-    encapped_key: &EncappedKey<Kem::Kex>,
+    mode: &OpModeR<Kem, Kdf>,
+    sk_recip: &Kem::PrivateKey,
+    encapped_key: &Kem::EncappedKey,
     info: &[u8],
-) -> Result<AeadCtx<A, Kdf>, HpkeError>
+) -> Result<AeadCtxR<A, Kdf>, HpkeError>
 where
     A: Aead,
     Kdf: KdfTrait,
     Kem: KemTrait,
 {
-    // If the identity key is set, use it
-    let pk_sender_id: Option<&<Kem::Kex as KeyExchange>::PublicKey> = mode.get_pk_sender_id();
-    // Do the decapsulation
-    let shared_secret = kem::decap::<Kem>(sk_recip, pk_sender_id, encapped_key)?;
+    verify_psk_inputs(mode)?;
+
+    // Do the decapsulation. If the identity key is set, use it.
+    let shared_secret = match mode.get_pk_sender_id() {
+        Some(pk_sender_id) => Kem::auth_decap(sk_recip, pk_sender_id, encapped_key)?,
+        None => Kem::decap(sk_recip, encapped_key)?,
+    };
 
     // Use everything to derive an encryption context
-    Ok(derive_enc_ctx::<_, _, Kem, _>(mode, shared_secret, info))
+    Ok(derive_enc_ctx::<_, _, Kem, _>(mode, shared_secret, info).into())
 }
 
 #[cfg(test)]
 mod test {
     use super::{setup_receiver, setup_sender};
-    use crate::test_util::{aead_ctx_eq, gen_op_mode_pair, OpModeKind};
+    use crate::prelude::*;
+    use crate::test_util::{aead_ctx_eq, gen_op_mode_pair, gen_psk_bundle, OpModeKind};
     use crate::{
-        aead::{AesGcm128, AesGcm256, ChaCha20Poly1305},
+        aead::{AesGcm128, AesGcm256, ChaCha20Poly1305, ExportOnly},
         kdf::{HkdfSha256, HkdfSha384, HkdfSha512},
-        kem::{Kem as KemTrait, X25519HkdfSha256},
-        kex::KeyExchange,
+        kem::{
+            DhP256HkdfSha256, DhP384HkdfSha384, DhP521HkdfSha512, Kem as KemTrait, MlKem1024,
+            MlKem768, X25519HkdfSha256, X448HkdfSha512,
+        },
+        op_mode::{OpModeR, OpModeS, Psk, PskBundle},
+        HpkeError,
     };
 
     /// This tests that `setup_sender` and `setup_receiver` derive the same context. We do this by
@@ -196,14 +231,13 @@ mod test {
                 type A = $aead_ty;
                 type Kdf = $kdf_ty;
                 type Kem = $kem_ty;
-                type Kex = <Kem as KemTrait>::Kex;
 
                 let mut csprng = rand::thread_rng();
 
                 let info = b"why would you think in a million years that that would actually work";
 
                 // Generate the receiver's long-term keypair
-                let (sk_recip, pk_recip) = <Kex as KeyExchange>::gen_keypair(&mut csprng);
+                let (sk_recip, pk_recip) = <Kem as KemTrait>::gen_keypair(&mut csprng);
 
                 // Try a full setup for all the op modes
                 for op_mode_kind in &[
@@ -213,7 +247,7 @@ mod test {
                     OpModeKind::AuthPsk,
                 ] {
                     // Generate a mutually agreeing op mode pair
-                    let (sender_mode, receiver_mode) = gen_op_mode_pair::<Kex, Kdf>(*op_mode_kind);
+                    let (sender_mode, receiver_mode) = gen_op_mode_pair::<Kem, Kdf>(*op_mode_kind);
 
                     // Construct the sender's encryption context, and get an encapped key
                     let (encapped_key, mut aead_ctx1) = setup_sender::<A, _, Kem, _>(
@@ -294,6 +328,124 @@ mod test {
         HkdfSha512,
         X25519HkdfSha256
     );
+    test_setup_correctness!(
+        test_setup_correctness_chacha_sha256_p256,
+        ChaCha20Poly1305,
+        HkdfSha256,
+        DhP256HkdfSha256
+    );
+    test_setup_correctness!(
+        test_setup_correctness_aes128_sha256_p256,
+        AesGcm128,
+        HkdfSha256,
+        DhP256HkdfSha256
+    );
+    test_setup_correctness!(
+        test_setup_correctness_aes256_sha384_p384,
+        AesGcm256,
+        HkdfSha384,
+        DhP384HkdfSha384
+    );
+    test_setup_correctness!(
+        test_setup_correctness_aes256_sha512_p521,
+        AesGcm256,
+        HkdfSha512,
+        DhP521HkdfSha512
+    );
+    test_setup_correctness!(
+        test_setup_correctness_chacha_sha512_x448,
+        ChaCha20Poly1305,
+        HkdfSha512,
+        X448HkdfSha512
+    );
+
+    /// Like `test_setup_correctness`, but for a KEM with no `AuthEncap`, which can only be used in
+    /// the Base and PSK modes
+    fn test_setup_correctness_no_auth<Kem: KemTrait>() {
+        type A = AesGcm256;
+        type Kdf = HkdfSha256;
+
+        let mut csprng = rand::thread_rng();
+
+        let info = b"post-quantum";
+        let (sk_recip, pk_recip) = Kem::gen_keypair(&mut csprng);
+
+        let psk_bundle = gen_psk_bundle::<Kdf>();
+        let mode_pairs = [
+            (OpModeS::<Kem, Kdf>::Base, OpModeR::<Kem, Kdf>::Base),
+            (OpModeS::Psk(psk_bundle.clone()), OpModeR::Psk(psk_bundle)),
+        ];
+
+        for (sender_mode, receiver_mode) in mode_pairs {
+            let (encapped_key, mut aead_ctx1) =
+                setup_sender::<A, _, Kem, _>(&sender_mode, &pk_recip, &info[..], &mut csprng)
+                    .unwrap();
+            let mut aead_ctx2 =
+                setup_receiver::<A, _, Kem>(&receiver_mode, &sk_recip, &encapped_key, &info[..])
+                    .unwrap();
+
+            assert!(aead_ctx_eq(&mut aead_ctx1, &mut aead_ctx2));
+        }
+    }
+
+    #[test]
+    fn test_setup_correctness_ml_kem_768() {
+        test_setup_correctness_no_auth::<MlKem768>();
+    }
+
+    #[test]
+    fn test_setup_correctness_ml_kem_1024() {
+        test_setup_correctness_no_auth::<MlKem1024>();
+    }
+
+    /// Tests that `setup_sender` and `setup_receiver` derive the same exporter secret for an
+    /// export-only context
+    #[test]
+    fn test_setup_export_only() {
+        type Kem = X25519HkdfSha256;
+        type Kdf = HkdfSha256;
+
+        let mut csprng = rand::thread_rng();
+
+        let info = b"export only";
+        let (sk_recip, pk_recip) = <Kem as KemTrait>::gen_keypair(&mut csprng);
+
+        for op_mode_kind in &[
+            OpModeKind::Base,
+            OpModeKind::Auth,
+            OpModeKind::Psk,
+            OpModeKind::AuthPsk,
+        ] {
+            let (sender_mode, receiver_mode) = gen_op_mode_pair::<Kem, Kdf>(*op_mode_kind);
+
+            // Set up both sides of the export-only context
+            let (encapped_key, sender_ctx) = setup_sender::<ExportOnly, _, Kem, _>(
+                &sender_mode,
+                &pk_recip,
+                &info[..],
+                &mut csprng,
+            )
+            .unwrap();
+            let receiver_ctx = setup_receiver::<ExportOnly, _, Kem>(
+                &receiver_mode,
+                &sk_recip,
+                &encapped_key,
+                &info[..],
+            )
+            .unwrap();
+
+            // Both sides should export the same secret
+            let mut secret1 = [0u8; 32];
+            let mut secret2 = [0u8; 32];
+            sender_ctx
+                .export(b"test_setup_export_only", &mut secret1)
+                .unwrap();
+            receiver_ctx
+                .export(b"test_setup_export_only", &mut secret2)
+                .unwrap();
+            assert_eq!(secret1, secret2);
+        }
+    }
 
     /// Tests that using different input data gives you different encryption contexts
     #[test]
@@ -301,17 +453,16 @@ mod test {
         type A = ChaCha20Poly1305;
         type Kdf = HkdfSha256;
         type Kem = X25519HkdfSha256;
-        type Kex = <Kem as KemTrait>::Kex;
 
         let mut csprng = rand::thread_rng();
 
         let info = b"why would you think in a million years that that would actually work";
 
         // Generate the receiver's long-term keypair
-        let (sk_recip, pk_recip) = <Kex as KeyExchange>::gen_keypair(&mut csprng);
+        let (sk_recip, pk_recip) = <Kem as KemTrait>::gen_keypair(&mut csprng);
 
         // Generate a mutually agreeing op mode pair
-        let (sender_mode, receiver_mode) = gen_op_mode_pair::<Kex, Kdf>(OpModeKind::Base);
+        let (sender_mode, receiver_mode) = gen_op_mode_pair::<Kem, Kdf>(OpModeKind::Base);
 
         // Construct the sender's encryption context normally
         let (encapped_key, aead_ctx1) =
@@ -325,7 +476,7 @@ mod test {
         assert!(!aead_ctx_eq(&mut aead_ctx1.clone(), &mut aead_ctx2));
 
         // Now make a receiver with the wrong secret key and ensure it doesn't match the sender
-        let (bad_sk, _) = <Kex as KeyExchange>::gen_keypair(&mut csprng);
+        let (bad_sk, _) = <Kem as KemTrait>::gen_keypair(&mut csprng);
         let mut aead_ctx2 =
             setup_receiver::<_, _, Kem>(&receiver_mode, &bad_sk, &encapped_key, &info[..]).unwrap();
         assert!(!aead_ctx_eq(&mut aead_ctx1.clone(), &mut aead_ctx2));
@@ -347,4 +498,64 @@ mod test {
                 .unwrap();
         assert!(aead_ctx_eq(&mut aead_ctx1.clone(), &mut aead_ctx2));
     }
+
+    /// Tests that the PSK modes reject an empty PSK or PSK ID, as in RFC 9180 §5.1 VerifyPSKInputs
+    #[test]
+    fn test_setup_rejects_empty_psk_inputs() {
+        type A = ChaCha20Poly1305;
+        type Kdf = HkdfSha256;
+        type Kem = X25519HkdfSha256;
+
+        let mut csprng = rand::thread_rng();
+        let info = b"verify psk inputs";
+
+        let (sk_recip, pk_recip) = <Kem as KemTrait>::gen_keypair(&mut csprng);
+        let (sk_sender_id, pk_sender_id) = <Kem as KemTrait>::gen_keypair(&mut csprng);
+        let (encapped_key, _) =
+            setup_sender::<A, Kdf, Kem, _>(&OpModeS::Base, &pk_recip, &info[..], &mut csprng)
+                .unwrap();
+
+        // One bundle is missing its PSK, and the other is missing its PSK ID
+        let bad_bundles = [
+            PskBundle::<Kdf> {
+                psk: Psk::from_bytes(Vec::new()),
+                psk_id: b"preshared key attempt #5, take 2. action".to_vec(),
+            },
+            PskBundle::<Kdf> {
+                psk: Psk::from_bytes(vec![0x42; 32]),
+                psk_id: Vec::new(),
+            },
+        ];
+
+        for bundle in &bad_bundles {
+            let sender_modes = [
+                OpModeS::<Kem, Kdf>::Psk(bundle.clone()),
+                OpModeS::AuthPsk((sk_sender_id.clone(), pk_sender_id.clone()), bundle.clone()),
+            ];
+            for sender_mode in &sender_modes {
+                match setup_sender::<A, _, Kem, _>(sender_mode, &pk_recip, &info[..], &mut csprng) {
+                    Err(HpkeError::InvalidPsk) => {}
+                    Err(e) => panic!("setup_sender() should have failed. Got {}", e),
+                    Ok(_) => panic!("setup_sender() should have failed. It succeeded"),
+                }
+            }
+
+            let receiver_modes = [
+                OpModeR::<Kem, Kdf>::Psk(bundle.clone()),
+                OpModeR::AuthPsk(pk_sender_id.clone(), bundle.clone()),
+            ];
+            for receiver_mode in &receiver_modes {
+                match setup_receiver::<A, _, Kem>(
+                    receiver_mode,
+                    &sk_recip,
+                    &encapped_key,
+                    &info[..],
+                ) {
+                    Err(HpkeError::InvalidPsk) => {}
+                    Err(e) => panic!("setup_receiver() should have failed. Got {}", e),
+                    Ok(_) => panic!("setup_receiver() should have failed. It succeeded"),
+                }
+            }
+        }
+    }
 }
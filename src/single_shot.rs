@@ -1,38 +1,41 @@
 use crate::{
-    aead::{Aead, AeadTag},
+    aead::{Aead, AeadTag, SealingAead},
     kdf::Kdf as KdfTrait,
-    kem::{EncappedKey, Kem as KemTrait},
-    kex::KeyExchange,
+    kem::Kem as KemTrait,
     op_mode::{OpModeR, OpModeS},
     setup::{setup_receiver, setup_sender},
     HpkeError,
 };
 
+#[cfg(feature = "alloc")]
+use crate::prelude::*;
+
 use rand::{CryptoRng, RngCore};
 
 // def SealAuthPSK(pkR, info, aad, pt, psk, pskID, skS):
 //   enc, ctx = SetupAuthPSKS(pkR, info, psk, pskID, skS)
 //   ct = ctx.Seal(aad, pt)
 //   return enc, ct
-/// Does a `setup_sender` and `AeadCtx::seal` in one shot. That is, it does a key encapsulation to
+/// Does a `setup_sender` and `AeadCtxS::seal` in one shot. That is, it does a key encapsulation to
 /// the specified recipient and encrypts the provided plaintext in place. See `setup::setup_sender`
-/// and `AeadCtx::seal` for more detail.
+/// and `AeadCtxS::seal` for more detail.
 ///
 /// Return Value
 /// ============
-/// Returns `Ok((encapped_key, tag))` on success. If an error happened during key exchange, returns
+/// Returns `Ok((encapped_key, tag))` on success. If the PSK inputs are invalid, returns
+/// `Err(HpkeError::InvalidPsk)`. If an error happened during key exchange, returns
 /// `Err(HpkeError::InvalidKeyExchange)`. If an unspecified error happened during encryption,
 /// returns `Err(HpkeError::Encryption)`. In this case, the contents of `plaintext` is undefined.
 pub fn single_shot_seal<A, Kdf, Kem, R>(
-    mode: &OpModeS<Kem::Kex, Kdf>,
-    pk_recip: &<Kem::Kex as KeyExchange>::PublicKey,
+    mode: &OpModeS<Kem, Kdf>,
+    pk_recip: &Kem::PublicKey,
     info: &[u8],
     plaintext: &mut [u8],
     aad: &[u8],
     csprng: &mut R,
-) -> Result<(EncappedKey<Kem::Kex>, AeadTag<A>), HpkeError>
+) -> Result<(Kem::EncappedKey, AeadTag<A>), HpkeError>
 where
-    A: Aead,
+    A: SealingAead,
     Kdf: KdfTrait,
     Kem: KemTrait,
     R: CryptoRng + RngCore,
@@ -49,26 +52,27 @@ where
 // def OpenAuthPSK(enc, skR, info, aad, ct, psk, pskID, pkS):
 //   ctx = SetupAuthPSKR(enc, skR, info, psk, pskID, pkS)
 //   return ctx.Open(aad, ct)
-/// Does a `setup_receiver` and `AeadCtx::open` in one shot. That is, it does a key decapsulation
+/// Does a `setup_receiver` and `AeadCtxR::open` in one shot. That is, it does a key decapsulation
 /// for the specified recipient and decrypts the provided plaintext in place. See
-/// `setup::setup_reciever` and `AeadCtx::open` for more detail.
+/// `setup::setup_reciever` and `AeadCtxR::open` for more detail.
 ///
 /// Return Value
 /// ============
-/// Returns `Ok()` on success. If an error happened during key exchange, returns
-/// `Err(HpkeError::InvalidKeyExchange)`. If an unspecified error happened during decryption,
-/// returns `Err(HpkeError::Encryption)`. In this case, the contents of `ciphertext` is undefined.
+/// Returns `Ok()` on success. If the PSK inputs are invalid, returns `Err(HpkeError::InvalidPsk)`.
+/// If an error happened during key exchange, returns `Err(HpkeError::InvalidKeyExchange)`. If an
+/// unspecified error happened during decryption, returns `Err(HpkeError::Encryption)`. In this
+/// case, the contents of `ciphertext` is undefined.
 pub fn single_shot_open<A, Kdf, Kem>(
-    mode: &OpModeR<Kem::Kex, Kdf>,
-    sk_recip: &<Kem::Kex as KeyExchange>::PrivateKey,
-    encapped_key: &EncappedKey<Kem::Kex>,
+    mode: &OpModeR<Kem, Kdf>,
+    sk_recip: &Kem::PrivateKey,
+    encapped_key: &Kem::EncappedKey,
     info: &[u8],
     ciphertext: &mut [u8],
     aad: &[u8],
     tag: &AeadTag<A>,
 ) -> Result<(), HpkeError>
 where
-    A: Aead,
+    A: SealingAead,
     Kdf: KdfTrait,
     Kem: KemTrait,
 {
@@ -78,16 +82,147 @@ where
     aead_ctx.open(ciphertext, aad, tag)
 }
 
+/// Does a `setup_sender` and `AeadCtxS::seal_to_vec` in one shot. That is, it does a key
+/// encapsulation to the specified recipient and returns the encryption of the provided plaintext
+/// as `ciphertext || tag`. See `setup::setup_sender` and `AeadCtxS::seal_to_vec` for more detail.
+///
+/// Return Value
+/// ============
+/// Returns `Ok((encapped_key, ciphertext_and_tag))` on success. Errors are the same as in
+/// `single_shot_seal`.
+#[cfg(feature = "alloc")]
+pub fn single_shot_seal_to_vec<A, Kdf, Kem, R>(
+    mode: &OpModeS<Kem, Kdf>,
+    pk_recip: &Kem::PublicKey,
+    info: &[u8],
+    plaintext: &[u8],
+    aad: &[u8],
+    csprng: &mut R,
+) -> Result<(Kem::EncappedKey, Vec<u8>), HpkeError>
+where
+    A: SealingAead,
+    Kdf: KdfTrait,
+    Kem: KemTrait,
+    R: CryptoRng + RngCore,
+{
+    // Encap a key
+    let (encapped_key, mut aead_ctx) =
+        setup_sender::<A, Kdf, Kem, R>(mode, pk_recip, info, csprng)?;
+    // Encrypt
+    let ciphertext_and_tag = aead_ctx.seal_to_vec(plaintext, aad)?;
+
+    Ok((encapped_key, ciphertext_and_tag))
+}
+
+/// Does a `setup_receiver` and `AeadCtxR::open_to_vec` in one shot. That is, it does a key
+/// decapsulation for the specified recipient and returns the decryption of the provided
+/// `ciphertext || tag`. See `setup::setup_receiver` and `AeadCtxR::open_to_vec` for more detail.
+///
+/// Return Value
+/// ============
+/// Returns `Ok(plaintext)` on success. If `ciphertext_and_tag` is shorter than a tag, returns
+/// `Err(HpkeError::InvalidTag)`. Otherwise, errors are the same as in `single_shot_open`.
+#[cfg(feature = "alloc")]
+pub fn single_shot_open_to_vec<A, Kdf, Kem>(
+    mode: &OpModeR<Kem, Kdf>,
+    sk_recip: &Kem::PrivateKey,
+    encapped_key: &Kem::EncappedKey,
+    info: &[u8],
+    ciphertext_and_tag: &[u8],
+    aad: &[u8],
+) -> Result<Vec<u8>, HpkeError>
+where
+    A: SealingAead,
+    Kdf: KdfTrait,
+    Kem: KemTrait,
+{
+    // Decap the key
+    let mut aead_ctx = setup_receiver::<A, Kdf, Kem>(mode, sk_recip, encapped_key, info)?;
+    // Decrypt
+    aead_ctx.open_to_vec(ciphertext_and_tag, aad)
+}
+
+// def SendExport<MODE>(pkR, info, exporter_context, L, ...):
+//   enc, ctx = Setup<MODE>S(pkR, info, ...)
+//   exported = ctx.Export(exporter_context, L)
+//   return enc, exported
+/// Does a `setup_sender` and `AeadCtxS::export` in one shot. That is, it does a key encapsulation
+/// to the specified recipient and fills `out_buf` with a secret derived from the resulting
+/// context. See `setup::setup_sender` and `AeadCtxS::export` for more detail.
+///
+/// Return Value
+/// ============
+/// Returns `Ok(encapped_key)` on success. If the PSK inputs are invalid, returns
+/// `Err(HpkeError::InvalidPsk)`. If an error happened during key exchange, returns
+/// `Err(HpkeError::InvalidKeyExchange)`. If the buffer length is more than 255x the digest size of
+/// the underlying hash function, returns `Err(HpkeError::InvalidKdfLength)`.
+pub fn single_shot_send_export<A, Kdf, Kem, R>(
+    mode: &OpModeS<Kem, Kdf>,
+    pk_recip: &Kem::PublicKey,
+    info: &[u8],
+    exporter_ctx: &[u8],
+    out_buf: &mut [u8],
+    csprng: &mut R,
+) -> Result<Kem::EncappedKey, HpkeError>
+where
+    A: Aead,
+    Kdf: KdfTrait,
+    Kem: KemTrait,
+    R: CryptoRng + RngCore,
+{
+    // Encap a key
+    let (encapped_key, aead_ctx) = setup_sender::<A, Kdf, Kem, R>(mode, pk_recip, info, csprng)?;
+    // Export
+    aead_ctx.export(exporter_ctx, out_buf)?;
+
+    Ok(encapped_key)
+}
+
+// def ReceiveExport<MODE>(enc, skR, info, exporter_context, L, ...):
+//   ctx = Setup<MODE>R(enc, skR, info, ...)
+//   return ctx.Export(exporter_context, L)
+/// Does a `setup_receiver` and `AeadCtxR::export` in one shot. That is, it does a key
+/// decapsulation for the specified recipient and fills `out_buf` with a secret derived from the
+/// resulting context. See `setup::setup_receiver` and `AeadCtxR::export` for more detail.
+///
+/// Return Value
+/// ============
+/// Returns `Ok()` on success. If the PSK inputs are invalid, returns `Err(HpkeError::InvalidPsk)`.
+/// If an error happened during key exchange, returns `Err(HpkeError::InvalidKeyExchange)`. If the
+/// buffer length is more than 255x the digest size of the underlying hash function, returns
+/// `Err(HpkeError::InvalidKdfLength)`.
+pub fn single_shot_receive_export<A, Kdf, Kem>(
+    mode: &OpModeR<Kem, Kdf>,
+    sk_recip: &Kem::PrivateKey,
+    encapped_key: &Kem::EncappedKey,
+    info: &[u8],
+    exporter_ctx: &[u8],
+    out_buf: &mut [u8],
+) -> Result<(), HpkeError>
+where
+    A: Aead,
+    Kdf: KdfTrait,
+    Kem: KemTrait,
+{
+    // Decap the key
+    let aead_ctx = setup_receiver::<A, Kdf, Kem>(mode, sk_recip, encapped_key, info)?;
+    // Export
+    aead_ctx.export(exporter_ctx, out_buf)
+}
+
 #[cfg(test)]
 mod test {
-    use super::{single_shot_open, single_shot_seal};
+    use super::{
+        single_shot_open, single_shot_receive_export, single_shot_seal, single_shot_send_export,
+    };
+    #[cfg(feature = "alloc")]
+    use super::{single_shot_open_to_vec, single_shot_seal_to_vec};
     use crate::{
-        aead::ChaCha20Poly1305,
+        aead::{ChaCha20Poly1305, ExportOnly},
         kdf::HkdfSha256,
         kem::{Kem as KemTrait, X25519HkdfSha256},
-        kex::KeyExchange,
         op_mode::{OpModeR, OpModeS},
-        test_util::gen_psk_bundle,
+        test_util::{gen_op_mode_pair, gen_psk_bundle, OpModeKind},
     };
 
     /// Tests that `single_shot_open` can open a `single_shot_seal` ciphertext. This doens't need
@@ -98,7 +233,6 @@ mod test {
         type A = ChaCha20Poly1305;
         type Kd = HkdfSha256;
         type Ke = X25519HkdfSha256;
-        type Kex = <Ke as KemTrait>::Kex;
 
         let msg = b"Good night, a-ding ding ding ding ding";
         let aad = b"Five four three two one";
@@ -110,18 +244,18 @@ mod test {
         let psk_bundle = gen_psk_bundle::<Kd>();
 
         // Generate the sender's and receiver's long-term keypairs
-        let (sk_sender_id, pk_sender_id) = Kex::gen_keypair(&mut csprng);
-        let (sk_recip, pk_recip) = Kex::gen_keypair(&mut csprng);
+        let (sk_sender_id, pk_sender_id) = Ke::gen_keypair(&mut csprng);
+        let (sk_recip, pk_recip) = Ke::gen_keypair(&mut csprng);
 
         // Construct the sender's encryption context, and get an encapped key
         let sender_mode =
-            OpModeS::<Kex, _>::AuthPsk((sk_sender_id, pk_sender_id.clone()), psk_bundle.clone());
+            OpModeS::<Ke, _>::AuthPsk((sk_sender_id, pk_sender_id.clone()), psk_bundle.clone());
 
         // Use the encapped key to derive the reciever's encryption context
-        let receiver_mode = OpModeR::<Kex, _>::AuthPsk(pk_sender_id, psk_bundle);
+        let receiver_mode = OpModeR::<Ke, _>::AuthPsk(pk_sender_id, psk_bundle);
 
         // Encrypt with the first context
-        let mut ciphertext = msg.clone();
+        let mut ciphertext = *msg;
         let (encapped_key, tag) = single_shot_seal::<A, _, Ke, _>(
             &sender_mode,
             &pk_recip,
@@ -133,7 +267,7 @@ mod test {
         .expect("single_shot_seal() failed");
 
         // Make sure seal() isn't a no-op
-        assert!(&ciphertext[..] != &msg[..]);
+        assert!(ciphertext[..] != msg[..]);
 
         // Decrypt with the second context
         single_shot_open::<A, _, Ke>(
@@ -150,4 +284,98 @@ mod test {
         let decrypted = ciphertext;
         assert_eq!(&decrypted[..], &msg[..]);
     }
+
+    /// Tests that `single_shot_open_to_vec` can open a `single_shot_seal_to_vec` ciphertext
+    #[cfg(feature = "alloc")]
+    #[test]
+    fn test_single_shot_to_vec_correctness() {
+        type A = ChaCha20Poly1305;
+        type Kd = HkdfSha256;
+        type Ke = X25519HkdfSha256;
+
+        let msg = b"I'm a survivor, I'm not gon' give up";
+        let aad = b"I'm not gon' stop, I'm gon' work harder";
+
+        let mut csprng = rand::thread_rng();
+
+        let info = b"why would you think in a million years that that would actually work";
+        let (sk_recip, pk_recip) = Ke::gen_keypair(&mut csprng);
+        let (sender_mode, receiver_mode) = gen_op_mode_pair::<Ke, Kd>(OpModeKind::AuthPsk);
+
+        // Encrypt
+        let (encapped_key, ciphertext_and_tag) = single_shot_seal_to_vec::<A, _, Ke, _>(
+            &sender_mode,
+            &pk_recip,
+            &info[..],
+            msg,
+            aad,
+            &mut csprng,
+        )
+        .expect("single_shot_seal_to_vec() failed");
+
+        // Decrypt
+        let decrypted = single_shot_open_to_vec::<A, _, Ke>(
+            &receiver_mode,
+            &sk_recip,
+            &encapped_key,
+            info,
+            &ciphertext_and_tag,
+            aad,
+        )
+        .expect("single_shot_open_to_vec() failed");
+        assert_eq!(&decrypted[..], &msg[..]);
+    }
+
+    /// Tests that `single_shot_receive_export` exports the same secret as
+    /// `single_shot_send_export`, for every op mode
+    #[test]
+    fn test_single_shot_export_correctness() {
+        type A = ExportOnly;
+        type Kd = HkdfSha256;
+        type Ke = X25519HkdfSha256;
+
+        let mut csprng = rand::thread_rng();
+
+        let info = b"my family tree is a circle";
+        let exporter_ctx = b"test_single_shot_export_correctness";
+
+        // Generate the receiver's long-term keypair
+        let (sk_recip, pk_recip) = Ke::gen_keypair(&mut csprng);
+
+        for op_mode_kind in &[
+            OpModeKind::Base,
+            OpModeKind::Auth,
+            OpModeKind::Psk,
+            OpModeKind::AuthPsk,
+        ] {
+            // Generate a mutually agreeing op mode pair
+            let (sender_mode, receiver_mode) = gen_op_mode_pair::<Ke, Kd>(*op_mode_kind);
+
+            // Export a secret on the sender's side
+            let mut sender_secret = [0u8; 64];
+            let encapped_key = single_shot_send_export::<A, _, Ke, _>(
+                &sender_mode,
+                &pk_recip,
+                &info[..],
+                &exporter_ctx[..],
+                &mut sender_secret,
+                &mut csprng,
+            )
+            .expect("single_shot_send_export() failed");
+
+            // Export the same secret on the receiver's side
+            let mut receiver_secret = [0u8; 64];
+            single_shot_receive_export::<A, _, Ke>(
+                &receiver_mode,
+                &sk_recip,
+                &encapped_key,
+                &info[..],
+                &exporter_ctx[..],
+                &mut receiver_secret,
+            )
+            .expect("single_shot_receive_export() failed");
+
+            assert_eq!(&sender_secret[..], &receiver_secret[..]);
+        }
+    }
 }
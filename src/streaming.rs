@@ -0,0 +1,383 @@
+//! Streaming encryption on top of an `AeadCtxS` and `AeadCtxR`. A stream is split into records
+//! of `record_size` bytes of plaintext, except for the last record, which has at most
+//! `record_size` bytes. Each record is sealed under the next sequence number of the context, and
+//! is written out as `ciphertext || tag`. The AAD of a record says whether it's the last one. So
+//! records can't be reordered, and a stream can't be truncated or extended without `StreamOpener`
+//! noticing.
+
+use crate::prelude::*;
+use crate::{
+    aead::{AeadCtxR, AeadCtxS, AeadTag, SealingAead},
+    kdf::Kdf as KdfTrait,
+    kex::{Marshallable, Unmarshallable},
+    HpkeError,
+};
+
+use std::io::{self, Read, Write};
+
+use zeroize::Zeroizing;
+
+/// The AAD of every record but the last
+const NON_FINAL_AAD: &[u8] = &[0x00];
+/// The AAD of the last record
+const FINAL_AAD: &[u8] = &[0x01];
+
+// Turns an HPKE error into an I/O error. Every error we surface means the stream can't be trusted.
+fn io_err(e: HpkeError) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, e)
+}
+
+/// Encrypts everything written to it, and writes the resulting records to an underlying writer.
+/// The stream must be ended with `finish()`. Otherwise, the last record is never written, and the
+/// `StreamOpener` will reject the stream as truncated. If sealing or writing a record fails, the
+/// underlying writer may hold part of that record, so every write after that fails too.
+pub struct StreamSealer<A: SealingAead, Kdf: KdfTrait, W: Write> {
+    ctx: AeadCtxS<A, Kdf>,
+    inner: W,
+    record_size: usize,
+    /// The plaintext of the record that's being filled
+    buf: Zeroizing<Vec<u8>>,
+    /// Whether a record failed to be sealed or written
+    failed: bool,
+}
+
+impl<A: SealingAead, Kdf: KdfTrait, W: Write> StreamSealer<A, Kdf, W> {
+    /// Makes a sealer that encrypts records of `record_size` bytes under `ctx`, and writes them
+    /// to `inner`. The `StreamOpener` must use the same record size.
+    ///
+    /// Panics
+    /// ======
+    /// Panics if `record_size` is 0.
+    pub fn new(ctx: AeadCtxS<A, Kdf>, record_size: usize, inner: W) -> StreamSealer<A, Kdf, W> {
+        assert!(record_size > 0, "record size must be nonzero");
+        StreamSealer {
+            ctx,
+            inner,
+            record_size,
+            buf: Zeroizing::new(Vec::with_capacity(record_size)),
+            failed: false,
+        }
+    }
+
+    // Seals the buffered plaintext as a single record and writes it out
+    fn seal_record(&mut self, aad: &[u8]) -> io::Result<()> {
+        let res = self
+            .ctx
+            .seal(&mut self.buf, aad)
+            .map_err(io_err)
+            .and_then(|tag| {
+                self.inner.write_all(&self.buf)?;
+                self.inner.write_all(&tag.marshal())
+            });
+
+        // Once sealed, the buffer holds ciphertext. Retrying would seal that ciphertext again
+        // under the next sequence number, after whatever part of this record was written.
+        if res.is_err() {
+            self.failed = true;
+        }
+        self.buf.clear();
+
+        res
+    }
+
+    // The error returned by every write after a failed one
+    fn poisoned_err() -> io::Error {
+        io::Error::other("stream sealer failed on an earlier write")
+    }
+
+    /// Seals whatever is buffered as the last record of the stream, flushes the underlying
+    /// writer, and returns it.
+    ///
+    /// Return Value
+    /// ============
+    /// Returns the underlying writer on success. If the context's sequence number overflowed,
+    /// returns an `InvalidData` error wrapping `HpkeError::SeqOverflow`. If an earlier write
+    /// failed, returns an `Other` error. Otherwise, errors come from the underlying writer.
+    pub fn finish(mut self) -> io::Result<W> {
+        if self.failed {
+            return Err(Self::poisoned_err());
+        }
+        self.seal_record(FINAL_AAD)?;
+        self.inner.flush()?;
+        Ok(self.inner)
+    }
+}
+
+impl<A: SealingAead, Kdf: KdfTrait, W: Write> Write for StreamSealer<A, Kdf, W> {
+    fn write(&mut self, data: &[u8]) -> io::Result<usize> {
+        if self.failed {
+            return Err(Self::poisoned_err());
+        }
+        if data.is_empty() {
+            return Ok(0);
+        }
+
+        // A full buffer is only sealed once we know it's not the last record, i.e., now
+        if self.buf.len() == self.record_size {
+            self.seal_record(NON_FINAL_AAD)?;
+        }
+
+        let n = core::cmp::min(self.record_size - self.buf.len(), data.len());
+        self.buf.extend_from_slice(&data[..n]);
+        Ok(n)
+    }
+
+    /// Flushes the underlying writer. A partially filled record stays buffered, since records
+    /// have a fixed size.
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+/// Reads records from an underlying reader, and returns their decrypted contents. Plaintext is
+/// only returned once the record it came from has been authenticated. If any record fails to
+/// authenticate, or the stream was truncated, reordered, or extended, reading fails with an
+/// `InvalidData` error wrapping `HpkeError::InvalidTag`, and every read after that fails too.
+pub struct StreamOpener<A: SealingAead, Kdf: KdfTrait, R: Read> {
+    ctx: AeadCtxR<A, Kdf>,
+    inner: R,
+    record_size: usize,
+    /// The ciphertext that's been read but not opened yet. This holds up to one byte past the
+    /// current record, so we can tell whether it's the last one.
+    ciphertext: Vec<u8>,
+    /// The plaintext of the last record opened
+    plaintext: Zeroizing<Vec<u8>>,
+    /// How much of `plaintext` has been returned so far
+    pos: usize,
+    /// Whether the last record has been opened
+    finished: bool,
+    /// Whether a record failed to open
+    failed: bool,
+}
+
+impl<A: SealingAead, Kdf: KdfTrait, R: Read> StreamOpener<A, Kdf, R> {
+    /// Makes an opener that decrypts records of `record_size` bytes under `ctx`, reading them
+    /// from `inner`. This must be the same record size the `StreamSealer` used.
+    ///
+    /// Panics
+    /// ======
+    /// Panics if `record_size` is 0.
+    pub fn new(ctx: AeadCtxR<A, Kdf>, record_size: usize, inner: R) -> StreamOpener<A, Kdf, R> {
+        assert!(record_size > 0, "record size must be nonzero");
+        StreamOpener {
+            ctx,
+            inner,
+            record_size,
+            ciphertext: Vec::with_capacity(record_size + AeadTag::<A>::size() + 1),
+            plaintext: Zeroizing::new(Vec::with_capacity(record_size)),
+            pos: 0,
+            finished: false,
+            failed: false,
+        }
+    }
+
+    /// Returns the underlying reader
+    pub fn into_inner(self) -> R {
+        self.inner
+    }
+
+    // Reads and opens the next record, replacing the current plaintext
+    fn open_record(&mut self) -> io::Result<()> {
+        let record_len = self.record_size + AeadTag::<A>::size();
+
+        // Read until we have a byte past a full record, or we hit the end of the stream
+        while self.ciphertext.len() <= record_len {
+            let mut chunk = [0u8; 4096];
+            let want = core::cmp::min(chunk.len(), record_len + 1 - self.ciphertext.len());
+            match self.inner.read(&mut chunk[..want]) {
+                Ok(0) => break,
+                Ok(n) => self.ciphertext.extend_from_slice(&chunk[..n]),
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                Err(e) => return Err(e),
+            }
+        }
+
+        // If there's data past this record, this isn't the last one. Otherwise it is. A record
+        // that was sealed as the last one won't open as anything else, and vice versa.
+        let (this_len, aad) = if self.ciphertext.len() > record_len {
+            (record_len, NON_FINAL_AAD)
+        } else {
+            (self.ciphertext.len(), FINAL_AAD)
+        };
+
+        // Every record has a tag, even an empty one
+        let tag_idx = this_len
+            .checked_sub(AeadTag::<A>::size())
+            .ok_or(HpkeError::InvalidTag)
+            .map_err(io_err)?;
+        let tag = AeadTag::<A>::unmarshal(&self.ciphertext[tag_idx..this_len]).map_err(io_err)?;
+
+        self.plaintext.clear();
+        self.plaintext
+            .extend_from_slice(&self.ciphertext[..tag_idx]);
+        self.ctx
+            .open(&mut self.plaintext, aad, &tag)
+            .map_err(io_err)?;
+
+        self.ciphertext.drain(..this_len);
+        self.pos = 0;
+        self.finished = aad == FINAL_AAD;
+
+        Ok(())
+    }
+}
+
+impl<A: SealingAead, Kdf: KdfTrait, R: Read> Read for StreamOpener<A, Kdf, R> {
+    fn read(&mut self, out: &mut [u8]) -> io::Result<usize> {
+        if self.failed {
+            return Err(io_err(HpkeError::InvalidTag));
+        }
+
+        // Open records until there's something to return or the stream is over. Only the last
+        // record can be empty, but an attacker can't make us loop forever either way, since an
+        // empty record that isn't last won't authenticate.
+        while self.pos == self.plaintext.len() && !self.finished {
+            if let Err(e) = self.open_record() {
+                // Don't let anyone see a plaintext that didn't authenticate
+                if e.kind() == io::ErrorKind::InvalidData {
+                    self.failed = true;
+                    self.plaintext.clear();
+                    self.pos = 0;
+                }
+                return Err(e);
+            }
+        }
+
+        let n = core::cmp::min(out.len(), self.plaintext.len() - self.pos);
+        out[..n].copy_from_slice(&self.plaintext[self.pos..self.pos + n]);
+        self.pos += n;
+        Ok(n)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{StreamOpener, StreamSealer};
+    use crate::prelude::*;
+    use crate::{aead::ChaCha20Poly1305, kdf::HkdfSha256, test_util::gen_ctx_simple_pair};
+
+    use std::io::{self, Read, Write};
+
+    use rand::RngCore;
+
+    type A = ChaCha20Poly1305;
+    type Kdf = HkdfSha256;
+
+    const RECORD_SIZE: usize = 64;
+    // The size of a sealed record with a ChaCha20Poly1305 tag
+    const SEALED_RECORD_SIZE: usize = RECORD_SIZE + 16;
+
+    // Seals msg in oddly sized writes and returns the stream, along with a context to open it
+    fn seal_stream(msg: &[u8]) -> (Vec<u8>, crate::aead::AeadCtxR<A, Kdf>) {
+        let (sender_ctx, receiver_ctx) = gen_ctx_simple_pair::<A, Kdf>();
+
+        let mut sealer = StreamSealer::new(sender_ctx, RECORD_SIZE, Vec::new());
+        for chunk in msg.chunks(37) {
+            sealer.write_all(chunk).unwrap();
+        }
+        (sealer.finish().unwrap(), receiver_ctx)
+    }
+
+    // Opens the whole stream
+    fn open_stream(stream: &[u8], ctx: crate::aead::AeadCtxR<A, Kdf>) -> io::Result<Vec<u8>> {
+        let mut opener = StreamOpener::new(ctx, RECORD_SIZE, stream);
+        let mut plaintext = Vec::new();
+        opener.read_to_end(&mut plaintext)?;
+        Ok(plaintext)
+    }
+
+    /// Tests that streams of all sorts of lengths round-trip, including ones that end exactly on
+    /// a record boundary
+    #[test]
+    fn test_stream_correctness() {
+        let mut csprng = rand::thread_rng();
+
+        for &len in &[
+            0,
+            1,
+            RECORD_SIZE - 1,
+            RECORD_SIZE,
+            RECORD_SIZE + 1,
+            5 * RECORD_SIZE,
+        ] {
+            let mut msg = vec![0u8; len];
+            csprng.fill_bytes(&mut msg);
+
+            let (stream, receiver_ctx) = seal_stream(&msg);
+            // There's always a last record, even if it's empty
+            let num_records = msg.chunks(RECORD_SIZE).len().max(1);
+            assert_eq!(stream.len(), len + 16 * num_records);
+            assert_eq!(open_stream(&stream, receiver_ctx).unwrap(), msg);
+        }
+    }
+
+    // A writer whose first write fails
+    struct FailOnce {
+        out: Vec<u8>,
+        failed: bool,
+    }
+
+    impl Write for FailOnce {
+        fn write(&mut self, data: &[u8]) -> io::Result<usize> {
+            if !self.failed {
+                self.failed = true;
+                return Err(io::Error::other("transient failure"));
+            }
+            self.out.write(data)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    /// Tests that a sealer refuses to go on after the underlying writer fails, rather than
+    /// resealing the record it failed to write
+    #[test]
+    fn test_stream_write_failure() {
+        let (sender_ctx, _) = gen_ctx_simple_pair::<A, Kdf>();
+        let writer = FailOnce {
+            out: Vec::new(),
+            failed: false,
+        };
+        let mut sealer = StreamSealer::new(sender_ctx, RECORD_SIZE, writer);
+
+        // The first record is written once the sealer sees data past it
+        let msg = [0x42u8; RECORD_SIZE + 1];
+        assert!(sealer.write_all(&msg).is_err());
+
+        // The writer works now, but the sealer must not
+        assert!(sealer.write_all(&msg[RECORD_SIZE..]).is_err());
+        assert!(sealer.finish().is_err());
+    }
+
+    /// Tests that truncating, reordering, or extending a stream makes it fail to open
+    #[test]
+    fn test_stream_tampering() {
+        let msg = [0x42u8; 3 * RECORD_SIZE + 10];
+        let (stream, receiver_ctx) = seal_stream(&msg);
+
+        let assert_rejected = |tampered: &[u8]| {
+            let err = open_stream(tampered, receiver_ctx.clone()).unwrap_err();
+            assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+        };
+
+        // Cut off the last record, so the stream ends on a record boundary
+        assert_rejected(&stream[..3 * SEALED_RECORD_SIZE]);
+        // Cut off part of the last record
+        assert_rejected(&stream[..stream.len() - 1]);
+        // Swap the first two records
+        let mut swapped = stream.clone();
+        swapped[..SEALED_RECORD_SIZE]
+            .copy_from_slice(&stream[SEALED_RECORD_SIZE..][..SEALED_RECORD_SIZE]);
+        swapped[SEALED_RECORD_SIZE..][..SEALED_RECORD_SIZE]
+            .copy_from_slice(&stream[..SEALED_RECORD_SIZE]);
+        assert_rejected(&swapped);
+        // Append a byte after the last record
+        let mut extended = stream.clone();
+        extended.push(0);
+        assert_rejected(&extended);
+        // An empty stream is missing its last record
+        assert_rejected(&[]);
+    }
+}
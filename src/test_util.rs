@@ -1,9 +1,10 @@
 use crate::{
-    aead::{Aead, AeadCtx, AeadKey, AeadNonce},
+    aead::{Aead, AeadCtx, AeadCtxR, AeadCtxS, AeadKey, AeadNonce, SealingAead},
     kdf::Kdf as KdfTrait,
-    kex::KeyExchange,
+    kem::Kem as KemTrait,
     op_mode::{OpModeR, OpModeS, Psk, PskBundle},
     setup::ExporterSecret,
+    util::FullSuiteId,
 };
 
 use rand::{Rng, RngCore};
@@ -26,8 +27,9 @@ pub(crate) fn gen_psk_bundle<Kdf: KdfTrait>() -> PskBundle<Kdf> {
     PskBundle::<Kdf> { psk, psk_id }
 }
 
-/// Creates a pair of `AeadCtx`s without doing a key exchange
-pub(crate) fn gen_ctx_simple_pair<A: Aead, Kdf: KdfTrait>() -> (AeadCtx<A, Kdf>, AeadCtx<A, Kdf>) {
+/// Creates a sender and receiver context pair without doing a key exchange
+pub(crate) fn gen_ctx_simple_pair<A: Aead, Kdf: KdfTrait>() -> (AeadCtxS<A, Kdf>, AeadCtxR<A, Kdf>)
+{
     let mut csprng = rand::thread_rng();
 
     // Initialize the key and nonce
@@ -47,10 +49,13 @@ pub(crate) fn gen_ctx_simple_pair<A: Aead, Kdf: KdfTrait>() -> (AeadCtx<A, Kdf>,
         buf
     };
 
-    let ctx1 = AeadCtx::new(key.clone(), nonce.clone(), exporter_secret.clone());
-    let ctx2 = AeadCtx::new(key.clone(), nonce.clone(), exporter_secret.clone());
+    // These contexts don't come from a key schedule, so the suite ID doesn't matter
+    let suite_id = FullSuiteId::default();
 
-    (ctx1, ctx2)
+    let ctx1 = AeadCtx::new(&key, &nonce, &exporter_secret, suite_id);
+    let ctx2 = AeadCtx::new(&key, &nonce, &exporter_secret, suite_id);
+
+    (ctx1.into(), ctx2.into())
 }
 
 #[derive(Clone, Copy)]
@@ -61,12 +66,18 @@ pub(crate) enum OpModeKind {
     AuthPsk,
 }
 
-/// Makes an agreeing pair of `OpMode`s of the specified variant
-pub(crate) fn gen_op_mode_pair<Kex: KeyExchange, Kdf: KdfTrait>(
-    kind: OpModeKind,
-) -> (OpModeS<Kex, Kdf>, OpModeR<Kex, Kdf>) {
+/// Makes an agreeing pair of `OpMode`s of the specified variant. The sender's identity keys are
+/// ordinary keys of `Kem`, as they are in a DHKEM.
+pub(crate) fn gen_op_mode_pair<Kem, Kdf>(kind: OpModeKind) -> (OpModeS<Kem, Kdf>, OpModeR<Kem, Kdf>)
+where
+    Kem: KemTrait<
+        AuthPrivateKey = <Kem as KemTrait>::PrivateKey,
+        AuthPublicKey = <Kem as KemTrait>::PublicKey,
+    >,
+    Kdf: KdfTrait,
+{
     let mut csprng = rand::thread_rng();
-    let (sk_sender_id, pk_sender_id) = Kex::gen_keypair(&mut csprng);
+    let (sk_sender_id, pk_sender_id) = Kem::gen_keypair(&mut csprng);
     let psk_bundle = gen_psk_bundle::<Kdf>();
 
     match kind {
@@ -94,11 +105,11 @@ pub(crate) fn gen_op_mode_pair<Kex: KeyExchange, Kdf: KdfTrait>(
     }
 }
 
-/// Evaluates the equivalence of two encryption contexts by doing some encryption-decryption
+/// Evaluates the equivalence of a sender and receiver context by doing some encryption-decryption
 /// round trips. Returns `true` iff the contexts are equal after 1000 iterations
-pub(crate) fn aead_ctx_eq<A: Aead, K: KdfTrait>(
-    ctx1: &mut AeadCtx<A, K>,
-    ctx2: &mut AeadCtx<A, K>,
+pub(crate) fn aead_ctx_eq<A: SealingAead, K: KdfTrait>(
+    ctx1: &mut AeadCtxS<A, K>,
+    ctx2: &mut AeadCtxR<A, K>,
 ) -> bool {
     let mut csprng = rand::thread_rng();
 
@@ -123,12 +134,12 @@ pub(crate) fn aead_ctx_eq<A: Aead, K: KdfTrait>(
         // Encrypt the plaintext
         let tag = ctx1
             .seal(&mut plaintext[..], &aad)
-            .expect(&format!("seal() #{} failed", i));
+            .unwrap_or_else(|_| panic!("seal() #{} failed", i));
         // Rename for clarity
         let mut ciphertext = plaintext;
 
         // Now to decrypt on the other side
-        if let Err(_) = ctx2.open(&mut ciphertext[..], &aad, &tag) {
+        if ctx2.open(&mut ciphertext[..], &aad, &tag).is_err() {
             // An error occurred in decryption. These encryption contexts are not identical.
             return false;
         }
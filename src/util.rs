@@ -1,14 +1,278 @@
-use crate::kdf::Kdf;
+use crate::{aead::Aead, kdf::Kdf as KdfTrait, kem::Kem as KemTrait, kex::Marshallable};
 
-use digest::{generic_array::typenum::Unsigned, Digest};
+use core::fmt;
 
-/// For use with `static_zeros`. This only needs to be as long as the longest hash function digest.
-/// 128 bytes should be enough for anybody.
-const ZEROS: &[u8] = &[0u8; 128];
+use byteorder::{BigEndian, ByteOrder};
+use subtle::{Choice, ConstantTimeEq};
+use zeroize::Zeroize;
 
-/// Returns an immutable slice into a static array of zeros. The slice length is the digest length
-/// of the underlying hash function. This function is defined so we don't have to keep allocating
-/// the default value of a `Psk`, which is `[0u8; HashImpl::OutputSize]`.
-pub(crate) fn static_zeros<K: Kdf>() -> &'static [u8] {
-    &ZEROS[..<<K as Kdf>::HashImpl as Digest>::OutputSize::to_usize()]
+/// The suite ID used in a KEM's labeled KDF calls
+pub(crate) type KemSuiteId = [u8; 5];
+
+/// The suite ID used in the key schedule's labeled KDF calls
+pub(crate) type FullSuiteId = [u8; 10];
+
+// RFC 9180 §4.1: suite_id = concat("KEM", I2OSP(kem_id, 2))
+/// Returns the suite ID of the given KEM
+pub(crate) fn kem_suite_id<Kem: KemTrait>() -> KemSuiteId {
+    let mut suite_id = [0u8; 5];
+    suite_id[..3].copy_from_slice(b"KEM");
+    BigEndian::write_u16(&mut suite_id[3..5], Kem::KEM_ID);
+
+    suite_id
+}
+
+// RFC 9180 §5.1:
+//     suite_id = concat(
+//       "HPKE",
+//       I2OSP(kem_id, 2),
+//       I2OSP(kdf_id, 2),
+//       I2OSP(aead_id, 2)
+//     )
+/// Returns the suite ID of the given ciphersuite
+pub(crate) fn full_suite_id<A, Kdf, Kem>() -> FullSuiteId
+where
+    A: Aead,
+    Kdf: KdfTrait,
+    Kem: KemTrait,
+{
+    let mut suite_id = [0u8; 10];
+    suite_id[..4].copy_from_slice(b"HPKE");
+    BigEndian::write_u16(&mut suite_id[4..6], Kem::KEM_ID);
+    BigEndian::write_u16(&mut suite_id[6..8], Kdf::KDF_ID);
+    BigEndian::write_u16(&mut suite_id[8..10], A::AEAD_ID);
+
+    suite_id
+}
+
+/// Compares the marshalled forms of two values in constant time. These might be secret, so
+/// they're wiped afterwards.
+pub(crate) fn marshalled_ct_eq<T: Marshallable>(a: &T, b: &T) -> Choice {
+    let (mut a, mut b) = (a.marshal(), b.marshal());
+    let res = a.ct_eq(&b);
+    a.as_mut_slice().zeroize();
+    b.as_mut_slice().zeroize();
+
+    res
+}
+
+/// Prints bytes as lowercase hex in `Debug` output
+pub(crate) struct HexFmt<'a>(pub(crate) &'a [u8]);
+
+impl fmt::Debug for HexFmt<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for byte in self.0 {
+            write!(f, "{:02x}", byte)?;
+        }
+        Ok(())
+    }
+}
+
+/// Stands in for a secret in `Debug` output
+pub(crate) struct Redacted;
+
+impl fmt::Debug for Redacted {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("[redacted]")
+    }
+}
+
+/// Base64 encoding and decoding, as in RFC 4648. This is used by the key encodings in `pkcs8` and
+/// `jwk`.
+#[cfg(any(feature = "pkcs8", feature = "jwk"))]
+pub(crate) mod base64 {
+    use crate::prelude::*;
+
+    use zeroize::Zeroizing;
+
+    /// A base64 alphabet. Every alphabet in RFC 4648 starts with `A-Z`, `a-z`, and `0-9`, so only
+    /// the last two characters differ.
+    #[derive(Clone, Copy)]
+    pub(crate) struct Alphabet {
+        c62: u8,
+        c63: u8,
+    }
+
+    /// The alphabet of RFC 4648 §4
+    #[cfg(feature = "pkcs8")]
+    pub(crate) const STANDARD: Alphabet = Alphabet {
+        c62: b'+',
+        c63: b'/',
+    };
+
+    /// The alphabet of RFC 4648 §5
+    #[cfg(feature = "jwk")]
+    pub(crate) const URL_SAFE: Alphabet = Alphabet {
+        c62: b'-',
+        c63: b'_',
+    };
+
+    // The encoded data is often a private key, so neither of the following branches on it or
+    // uses it as a table index. Range checks are done as (lo - 1 - c) & (c - hi - 1), which is
+    // negative exactly when lo <= c <= hi, and so is all ones after the shift.
+
+    // Encodes a 6-bit value as a character of the given alphabet
+    fn encode_char(alphabet: Alphabet, n: u8) -> u8 {
+        let n = i16::from(n);
+        let (c62, c63) = (i16::from(alphabet.c62), i16::from(alphabet.c63));
+
+        let mut c = n + i16::from(b'A');
+        // 26..=63 start at 'a'
+        c += ((25 - n) >> 8) & (i16::from(b'a') - i16::from(b'A') - 26);
+        // 52..=63 start at '0'
+        c -= ((51 - n) >> 8) & (i16::from(b'a') - i16::from(b'0') + 26);
+        // 62 is c62. At this point, c is n - 4.
+        c += ((61 - n) >> 8) & (c62 - 58);
+        // 63 is c63. At this point, c is c62 + 1.
+        c += ((62 - n) >> 8) & (c63 - c62 - 1);
+
+        c as u8
+    }
+
+    // Decodes a character of the given alphabet. Returns its value, or -1 if it isn't in the
+    // alphabet.
+    fn decode_char(alphabet: Alphabet, c: u8) -> i16 {
+        let c = i16::from(c);
+        let (c62, c63) = (i16::from(alphabet.c62), i16::from(alphabet.c63));
+
+        let mut ret = -1;
+        // 'A'..='Z' are 0..=25
+        ret += (((0x40 - c) & (c - 0x5b)) >> 8) & (c - 0x40);
+        // 'a'..='z' are 26..=51
+        ret += (((0x60 - c) & (c - 0x7b)) >> 8) & (c - 0x46);
+        // '0'..='9' are 52..=61
+        ret += (((0x2f - c) & (c - 0x3a)) >> 8) & (c + 5);
+        // The last two characters are 62 and 63
+        ret += (((c62 - 1 - c) & (c - c62 - 1)) >> 8) & 63;
+        ret += (((c63 - 1 - c) & (c - c63 - 1)) >> 8) & 64;
+        ret
+    }
+
+    /// Encodes `data` with the given alphabet. If `pad` is set, the output is padded with `=` to a
+    /// multiple of 4 characters.
+    pub(crate) fn encode(alphabet: Alphabet, pad: bool, data: &[u8]) -> Zeroizing<Vec<u8>> {
+        let mut out = Zeroizing::new(Vec::with_capacity(4 * data.chunks(3).len()));
+        for chunk in data.chunks(3) {
+            let mut buf = [0u8; 3];
+            buf[..chunk.len()].copy_from_slice(chunk);
+            let n = ((buf[0] as u32) << 16) | ((buf[1] as u32) << 8) | (buf[2] as u32);
+
+            // n bytes of input make n+1 characters of output
+            for i in 0..=chunk.len() {
+                out.push(encode_char(alphabet, ((n >> (18 - 6 * i)) & 0x3f) as u8));
+            }
+            if pad {
+                for _ in chunk.len()..3 {
+                    out.push(b'=');
+                }
+            }
+        }
+
+        out
+    }
+
+    /// Decodes `input` with the given alphabet. If `pad` is set, the input must be padded with `=`
+    /// to a multiple of 4 characters. Otherwise, it must not be padded. Returns `None` if the input
+    /// is not valid. This includes input whose last character has bits set that don't make it
+    /// into the output, since that isn't the canonical encoding of anything.
+    pub(crate) fn decode(
+        alphabet: Alphabet,
+        pad: bool,
+        input: &[u8],
+    ) -> Option<Zeroizing<Vec<u8>>> {
+        let unpadded = if pad {
+            if !input.chunks_exact(4).remainder().is_empty() {
+                return None;
+            }
+            let num_padding = input
+                .iter()
+                .rev()
+                .take(2)
+                .take_while(|&&c| c == b'=')
+                .count();
+            &input[..input.len() - num_padding]
+        } else {
+            input
+        };
+        // A single leftover character doesn't encode a whole byte
+        if unpadded.len() % 4 == 1 {
+            return None;
+        }
+
+        // Errors are only checked for at the end, so we don't stop early on a secret
+        let mut invalid = 0i16;
+        let mut leftover_bits = 0u32;
+        let mut out = Zeroizing::new(Vec::with_capacity(unpadded.len() / 4 * 3 + 2));
+        for chunk in unpadded.chunks(4) {
+            let mut n = 0u32;
+            for &c in chunk {
+                let digit = decode_char(alphabet, c);
+                invalid |= digit;
+                n = (n << 6) | (digit as u32 & 0x3f);
+            }
+            n <<= 6 * (4 - chunk.len());
+
+            // n characters of input make n-1 bytes of output. The bits after those must be zero.
+            let bytes = n.to_be_bytes();
+            out.extend_from_slice(&bytes[1..chunk.len()]);
+            for &b in &bytes[chunk.len()..] {
+                leftover_bits |= u32::from(b);
+            }
+        }
+
+        if invalid < 0 || leftover_bits != 0 {
+            None
+        } else {
+            Some(out)
+        }
+    }
+
+    #[cfg(test)]
+    mod test {
+        use super::{decode, decode_char, encode_char, Alphabet};
+
+        const ALPHABETS: &[(Alphabet, &[u8; 64])] = &[
+            (
+                Alphabet {
+                    c62: b'+',
+                    c63: b'/',
+                },
+                b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/",
+            ),
+            (
+                Alphabet {
+                    c62: b'-',
+                    c63: b'_',
+                },
+                b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789-_",
+            ),
+        ];
+
+        /// Tests that the constant-time character mapping agrees with the RFC 4648 alphabets
+        #[test]
+        fn test_alphabets() {
+            for &(alphabet, table) in ALPHABETS {
+                for n in 0u8..64 {
+                    assert_eq!(encode_char(alphabet, n), table[n as usize]);
+                }
+                for c in 0u8..=255 {
+                    let expected = table.iter().position(|&a| a == c).map_or(-1, |i| i as i16);
+                    assert_eq!(decode_char(alphabet, c), expected);
+                }
+            }
+        }
+
+        /// Tests that an encoding with nonzero bits past the end of the data is rejected
+        #[test]
+        fn test_noncanonical() {
+            let alphabet = ALPHABETS[0].0;
+
+            // "QQ==" and "QUI=" are "A" and "AB". Changing the last character sets unused bits.
+            assert_eq!(&decode(alphabet, true, b"QQ==").unwrap()[..], b"A");
+            assert!(decode(alphabet, true, b"QR==").is_none());
+            assert_eq!(&decode(alphabet, true, b"QUI=").unwrap()[..], b"AB");
+            assert!(decode(alphabet, true, b"QUJ=").is_none());
+            assert!(decode(alphabet, false, b"QUJ").is_none());
+        }
+    }
 }